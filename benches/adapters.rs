@@ -0,0 +1,43 @@
+//! Measures the effect of the reshaping adapters on access patterns they target.
+
+use core::{alloc::Layout, hint::black_box, ptr::NonNull};
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use memapi::{Alloc, DefaultAlloc, adapters::ColoredAlloc};
+
+/// Allocates several arrays of the same size from `alloc` and sums them in lockstep.
+fn lockstep<A: Alloc>(c: &mut Criterion, name: &str, alloc: &A) {
+    const ARRAYS: usize = 16;
+    // a multiple of the page size, so uncolored arrays all start at the same set
+    const LEN: usize = 64 << 10;
+
+    let layout = Layout::array::<u64>(LEN).unwrap();
+    let arrays: Vec<NonNull<u64>> = (0..ARRAYS)
+        .map(|_| alloc.alloc_filled(layout, 1).unwrap().cast())
+        .collect();
+    c.benchmark_group("lockstep_scan").bench_with_input(
+        BenchmarkId::new(name, ARRAYS),
+        &arrays,
+        |b, arrays| {
+            b.iter(|| {
+                let mut sum = 0u64;
+                for i in 0..LEN {
+                    for a in arrays {
+                        sum = sum.wrapping_add(unsafe { a.add(i).read() });
+                    }
+                }
+                black_box(sum)
+            });
+        },
+    );
+    for a in arrays {
+        unsafe { alloc.dealloc(a.cast(), layout) };
+    }
+}
+
+fn colored(c: &mut Criterion) {
+    lockstep(c, "default", &DefaultAlloc);
+    lockstep(c, "colored", &ColoredAlloc::new(DefaultAlloc, 16));
+}
+
+criterion_group!(benches, colored);
+criterion_main!(benches);
@@ -0,0 +1,196 @@
+//! Compares the `AllocExt` helpers against their `std` equivalents.
+
+use core::{alloc::Layout, hint::black_box};
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use memapi::{Alloc, AllocExt, DefaultAlloc};
+
+const SIZES: [usize; 3] = [8, 1024, 1 << 16];
+
+fn write(c: &mut Criterion) {
+    let mut group = c.benchmark_group("alloc_write");
+    group.bench_function("memapi/u64", |b| {
+        b.iter(|| unsafe {
+            let p = DefaultAlloc.alloc_write(black_box(7u64)).unwrap();
+            // keep the allocation from being optimized out
+            let p = black_box(p);
+            DefaultAlloc.dealloc(p.cast(), Layout::new::<u64>());
+        });
+    });
+    group.bench_function("box/u64", |b| b.iter(|| Box::new(black_box(7u64))));
+
+    group.bench_function("memapi/[u8; 4096]", |b| {
+        b.iter(|| unsafe {
+            let p = DefaultAlloc.alloc_write(black_box([1u8; 4096])).unwrap();
+            DefaultAlloc.dealloc(p.cast(), Layout::new::<[u8; 4096]>());
+        });
+    });
+    group.bench_function("box/[u8; 4096]", |b| {
+        b.iter(|| Box::new(black_box([1u8; 4096])));
+    });
+
+    group.bench_function("memapi/String", |b| {
+        b.iter(|| unsafe {
+            let p = DefaultAlloc.alloc_write(black_box(String::new())).unwrap();
+            let p = black_box(p);
+            DefaultAlloc.drop_and_dealloc(p);
+        });
+    });
+    group.bench_function("box/String", |b| {
+        b.iter(|| Box::new(black_box(String::new())));
+    });
+    group.finish();
+}
+
+fn clone_slice(c: &mut Criterion) {
+    let mut group = c.benchmark_group("alloc_clone_slice_to");
+    for len in SIZES {
+        let copy: Vec<u64> = (0..len as u64).collect();
+        let owning: Vec<String> = (0..len).map(|i| i.to_string()).collect();
+
+        group.bench_with_input(BenchmarkId::new("memapi/u64", len), &copy, |b, src| {
+            b.iter(|| unsafe {
+                let p = DefaultAlloc.alloc_clone_slice_to(black_box(src)).unwrap();
+                let p = black_box(p);
+                DefaultAlloc.drop_and_dealloc(p);
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("std/u64", len), &copy, |b, src| {
+            b.iter(|| black_box(src.as_slice()).to_vec().into_boxed_slice());
+        });
+
+        group.bench_with_input(BenchmarkId::new("memapi/String", len), &owning, |b, src| {
+            b.iter(|| unsafe {
+                let p = DefaultAlloc.alloc_clone_slice_to(black_box(src)).unwrap();
+                let p = black_box(p);
+                DefaultAlloc.drop_and_dealloc(p);
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("std/String", len), &owning, |b, src| {
+            b.iter(|| black_box(src.as_slice()).to_vec().into_boxed_slice());
+        });
+    }
+    group.finish();
+}
+
+fn slice_with(c: &mut Criterion) {
+    let mut group = c.benchmark_group("alloc_slice_with");
+    for len in SIZES {
+        group.bench_with_input(BenchmarkId::new("memapi/u64", len), &len, |b, &len| {
+            b.iter(|| unsafe {
+                let p = DefaultAlloc
+                    .alloc_slice_with(black_box(len), |i| i as u64)
+                    .unwrap();
+                let p = black_box(p);
+                DefaultAlloc.drop_and_dealloc(p);
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("std/u64", len), &len, |b, &len| {
+            b.iter(|| (0..black_box(len)).map(|i| i as u64).collect::<Vec<_>>());
+        });
+
+        group.bench_with_input(BenchmarkId::new("memapi/String", len), &len, |b, &len| {
+            b.iter(|| unsafe {
+                let p = DefaultAlloc
+                    .alloc_slice_with(black_box(len), |_| String::new())
+                    .unwrap();
+                let p = black_box(p);
+                DefaultAlloc.drop_and_dealloc(p);
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("std/String", len), &len, |b, &len| {
+            b.iter(|| {
+                (0..black_box(len))
+                    .map(|_| String::new())
+                    .collect::<Vec<_>>()
+            });
+        });
+    }
+    group.finish();
+}
+
+fn zeroed_slice(c: &mut Criterion) {
+    const LEN: usize = 1 << 20;
+
+    let mut group = c.benchmark_group("zeroed [u64; 1Mi]");
+    group.bench_function("alloc_zeroable_slice", |b| {
+        b.iter(|| unsafe {
+            let p = DefaultAlloc
+                .alloc_zeroable_slice::<u64>(black_box(LEN))
+                .unwrap();
+            let p = black_box(p);
+            DefaultAlloc.dealloc_n(p.cast::<u64>(), LEN);
+        });
+    });
+    group.bench_function("alloc_default_slice", |b| {
+        b.iter(|| unsafe {
+            let p = DefaultAlloc
+                .alloc_default_slice::<u64>(black_box(LEN))
+                .unwrap();
+            let p = black_box(p);
+            DefaultAlloc.dealloc_n(p.cast::<u64>(), LEN);
+        });
+    });
+    group.finish();
+}
+
+fn copy_bytes_nt(c: &mut Criterion) {
+    let mut group = c.benchmark_group("alloc_copy_bytes_nt");
+    // large enough to spill out of most caches
+    let len = 8 << 20;
+    let src = vec![0xA5u8; len];
+    group.bench_with_input(BenchmarkId::new("memapi/nt", len), &src, |b, src| {
+        b.iter(|| unsafe {
+            let p = DefaultAlloc.alloc_copy_bytes_nt(black_box(src)).unwrap();
+            let p = black_box(p);
+            DefaultAlloc.dealloc_n(p.cast::<u8>(), len);
+        });
+    });
+    group.bench_with_input(BenchmarkId::new("memapi/copy", len), &src, |b, src| {
+        b.iter(|| unsafe {
+            let p = DefaultAlloc.alloc_clone_slice_to(black_box(src)).unwrap();
+            let p = black_box(p);
+            DefaultAlloc.dealloc_n(p.cast::<u8>(), len);
+        });
+    });
+    group.finish();
+}
+
+fn repeat(c: &mut Criterion) {
+    let mut group = c.benchmark_group("alloc_repeat");
+    for len in SIZES {
+        let value = [3u32; 4];
+        group.bench_with_input(BenchmarkId::new("memapi/doubling", len), &len, |b, &len| {
+            b.iter(|| unsafe {
+                let p = DefaultAlloc
+                    .alloc_repeat_copy(black_box(value), black_box(len))
+                    .unwrap();
+                let p = black_box(p);
+                DefaultAlloc.drop_and_dealloc(p);
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("memapi/clone", len), &len, |b, &len| {
+            b.iter(|| unsafe {
+                let p = DefaultAlloc
+                    .alloc_repeat(black_box(value), black_box(len))
+                    .unwrap();
+                let p = black_box(p);
+                DefaultAlloc.drop_and_dealloc(p);
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("std/vec!", len), &len, |b, &len| {
+            b.iter(|| vec![black_box(value); black_box(len)]);
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    write,
+    clone_slice,
+    slice_with,
+    zeroed_slice,
+    copy_bytes_nt,
+    repeat
+);
+criterion_main!(benches);
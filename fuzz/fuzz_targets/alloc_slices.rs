@@ -0,0 +1,256 @@
+#![no_main]
+
+//! Drives the slice allocation paths with arbitrary operation sequences against an allocator which
+//! tracks every live block, checking for leaks, double frees, layout mismatches and corrupted
+//! elements.
+
+use core::{alloc::Layout, ptr::NonNull};
+use libfuzzer_sys::fuzz_target;
+use memapi::{Alloc, AllocError, AllocExt, DefaultAlloc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+};
+
+thread_local! {
+    /// The number of live `Elem`s.
+    static ELEMS: Cell<isize> = const { Cell::new(0) };
+}
+
+/// An element which counts its live instances, so leaked or doubly dropped elements are caught.
+#[derive(Debug, PartialEq)]
+struct Elem(u64);
+
+impl Elem {
+    fn new(v: u64) -> Elem {
+        ELEMS.with(|c| c.set(c.get() + 1));
+        Elem(v)
+    }
+}
+
+impl Clone for Elem {
+    fn clone(&self) -> Elem {
+        Elem::new(self.0)
+    }
+}
+
+impl Drop for Elem {
+    fn drop(&mut self) {
+        ELEMS.with(|c| c.set(c.get() - 1));
+    }
+}
+
+/// Delegates to [`DefaultAlloc`], recording every live block and panicking on frees of unknown
+/// blocks or with the wrong layout.
+#[derive(Default)]
+struct Tracking {
+    live: RefCell<HashMap<usize, Layout>>,
+}
+
+impl Tracking {
+    fn track(
+        &self,
+        res: Result<NonNull<u8>, AllocError>,
+        layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        if let Ok(ptr) = res {
+            let prev = self.live.borrow_mut().insert(ptr.addr().get(), layout);
+            assert!(prev.is_none(), "allocator returned a live block");
+            assert_eq!(ptr.addr().get() % layout.align(), 0, "misaligned block");
+        }
+        res
+    }
+}
+
+impl Alloc for Tracking {
+    fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.track(DefaultAlloc.alloc(layout), layout)
+    }
+
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.track(DefaultAlloc.alloc_zeroed(layout), layout)
+    }
+
+    fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+        self.track(DefaultAlloc.alloc_filled(layout, n), layout)
+    }
+
+    fn alloc_patterned<F: Fn(usize) -> u8 + Clone>(
+        &self,
+        layout: Layout,
+        pattern: F,
+    ) -> Result<NonNull<u8>, AllocError> {
+        self.track(DefaultAlloc.alloc_patterned(layout, pattern), layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        match self.live.borrow_mut().remove(&ptr.addr().get()) {
+            Some(l) => assert_eq!(l, layout, "freed with the wrong layout"),
+            None => panic!("double free or free of an unknown block"),
+        }
+        unsafe {
+            DefaultAlloc.dealloc(ptr, layout);
+        }
+    }
+}
+
+/// Reads operands from the fuzzer's input.
+struct Input<'a>(&'a [u8]);
+
+impl Input<'_> {
+    fn u8(&mut self) -> Option<u8> {
+        let (&b, rest) = self.0.split_first()?;
+        self.0 = rest;
+        Some(b)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes([self.u8()?, self.u8()?]))
+    }
+
+    fn usize(&mut self) -> Option<usize> {
+        let mut bytes = [0; size_of::<usize>()];
+        for b in &mut bytes {
+            *b = self.u8()?;
+        }
+        Some(usize::from_le_bytes(bytes))
+    }
+}
+
+/// A live slice and the values it should hold.
+struct Live {
+    ptr: NonNull<[Elem]>,
+    expected: Vec<u64>,
+}
+
+fn value(seed: u8, i: usize) -> u64 {
+    (u64::from(seed) << 32) ^ i as u64
+}
+
+fn check(live: &Live) {
+    let got = unsafe { live.ptr.as_ref() };
+    assert_eq!(got.len(), live.expected.len());
+    for (e, &v) in got.iter().zip(&live.expected) {
+        assert_eq!(e.0, v, "element corrupted");
+    }
+}
+
+fn run(mut input: Input<'_>, alloc: &Tracking) {
+    let mut slices: Vec<Live> = Vec::new();
+
+    while let Some(op) = input.u8() {
+        match op % 6 {
+            // alloc_slice_with
+            0 => {
+                let (Some(len), Some(seed)) = (input.u16(), input.u8()) else {
+                    break;
+                };
+                let len = usize::from(len % 2048);
+                match alloc.alloc_slice_with(len, |i| Elem::new(value(seed, i))) {
+                    Ok(ptr) => slices.push(Live {
+                        ptr,
+                        expected: (0..len).map(|i| value(seed, i)).collect(),
+                    }),
+                    // only a zero-sized slice may fail with the default allocator
+                    Err(e) => assert_eq!(len, 0, "alloc_slice_with failed: {e}"),
+                }
+            }
+            // alloc_clone_slice_to
+            1 => {
+                let Some(idx) = input.u8() else { break };
+                let Some(src) = slices.get(usize::from(idx) % slices.len().max(1)) else {
+                    continue;
+                };
+                check(src);
+                match alloc.alloc_clone_slice_to(unsafe { src.ptr.as_ref() }) {
+                    Ok(ptr) => {
+                        let expected = src.expected.clone();
+                        slices.push(Live { ptr, expected });
+                    }
+                    Err(e) => assert!(src.expected.is_empty(), "alloc_clone_slice_to failed: {e}"),
+                }
+            }
+            // grow_slice, then initialize the new tail
+            2 => {
+                let (Some(idx), Some(extra), Some(seed)) = (input.u8(), input.u16(), input.u8())
+                else {
+                    break;
+                };
+                if slices.is_empty() {
+                    continue;
+                }
+                let i = usize::from(idx) % slices.len();
+                let old_len = slices[i].expected.len();
+                let new_len = old_len + usize::from(extra % 1024) + 1;
+                if old_len == 0 {
+                    continue;
+                }
+                let grown =
+                    unsafe { alloc.grow_slice(slices[i].ptr, new_len) }.expect("grow_slice failed");
+                let base = grown.cast::<Elem>();
+                for j in old_len..new_len {
+                    unsafe { base.add(j).write(Elem::new(value(seed, j))) };
+                }
+                let live = &mut slices[i];
+                live.ptr = grown;
+                live.expected
+                    .extend((old_len..new_len).map(|j| value(seed, j)));
+                check(live);
+            }
+            // drop_and_dealloc
+            3 => {
+                let Some(idx) = input.u8() else { break };
+                if slices.is_empty() {
+                    continue;
+                }
+                let live = slices.swap_remove(usize::from(idx) % slices.len());
+                check(&live);
+                unsafe { alloc.drop_and_dealloc(live.ptr) };
+            }
+            // huge lengths must fail cleanly
+            4 => {
+                let Some(len) = input.usize() else { break };
+                let len = len | (1 << (usize::BITS - 4));
+                assert!(
+                    alloc
+                        .alloc_slice_with(len, |i| Elem::new(i as u64))
+                        .is_err()
+                );
+            }
+            // raw blocks with odd alignments
+            _ => {
+                let (Some(size), Some(shift), Some(fill)) = (input.u16(), input.u8(), input.u8())
+                else {
+                    break;
+                };
+                let Ok(layout) = Layout::from_size_align(usize::from(size), 1 << (shift % 16))
+                else {
+                    continue;
+                };
+                if let Ok(ptr) = alloc.alloc_filled(layout, fill) {
+                    let bytes = unsafe { core::slice::from_raw_parts(ptr.as_ptr(), layout.size()) };
+                    assert!(bytes.iter().all(|&b| b == fill));
+                    unsafe { alloc.dealloc(ptr, layout) };
+                } else {
+                    assert_eq!(layout.size(), 0);
+                }
+            }
+        }
+    }
+
+    for live in slices {
+        check(&live);
+        unsafe { alloc.drop_and_dealloc(live.ptr) };
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let alloc = Tracking::default();
+    run(Input(data), &alloc);
+    assert!(alloc.live.borrow().is_empty(), "leaked blocks");
+    assert_eq!(
+        ELEMS.with(Cell::get),
+        0,
+        "leaked or doubly dropped elements"
+    );
+});
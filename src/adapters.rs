@@ -0,0 +1,1468 @@
+use crate::{Alloc, AllocError, helpers::dangling_nonnull};
+use alloc::vec::Vec;
+use core::{
+    alloc::Layout,
+    cell::RefCell,
+    mem::take,
+    ptr::NonNull,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering::Relaxed},
+};
+#[cfg(feature = "std")]
+use core::{marker::PhantomData, time::Duration};
+
+/// An allocation group over `A` which frees everything allocated through it unless committed.
+///
+/// Every allocation made through the transaction is recorded. Dropping the transaction without
+/// calling [`commit`](Transaction::commit) deallocates all of them, so a multi-step construction
+/// which fails midway can bail out with `?` and leak nothing. Deallocating through the
+/// transaction forgets the record, and resizing keeps it up to date.
+///
+/// Committed allocations are ordinary allocations of the underlying allocator and must still be
+/// deallocated through it afterward.
+///
+/// Allocations are only recorded as memory; no destructors are run on rollback.
+///
+/// A transaction is confined to the thread which began it. With the `debug_checks` feature, using
+/// it from another thread through unsafe code panics instead of racing on the record.
+pub struct Transaction<'a, A: Alloc + ?Sized> {
+    alloc: &'a A,
+    live: RefCell<Vec<(NonNull<u8>, Layout)>>,
+    #[cfg(feature = "debug_checks")]
+    owner: crate::debug_checks::ThreadOwner,
+}
+
+impl<'a, A: Alloc + ?Sized> Transaction<'a, A> {
+    /// Begins a new, empty transaction allocating through `alloc`.
+    #[must_use]
+    #[inline]
+    pub const fn new(alloc: &'a A) -> Transaction<'a, A> {
+        Transaction {
+            alloc,
+            live: RefCell::new(Vec::new()),
+            #[cfg(feature = "debug_checks")]
+            owner: crate::debug_checks::ThreadOwner::new(),
+        }
+    }
+
+    /// Returns the underlying allocator.
+    #[must_use]
+    #[inline]
+    pub const fn inner(&self) -> &'a A {
+        self.alloc
+    }
+
+    /// Returns the number of allocations currently recorded.
+    #[must_use]
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.live.borrow().len()
+    }
+
+    /// Returns `true` if no allocations are currently recorded.
+    #[must_use]
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.live.borrow().is_empty()
+    }
+
+    /// Keeps every allocation made through this transaction, handing ownership back to the
+    /// caller.
+    #[inline]
+    pub fn commit(self) {
+        self.live.borrow_mut().clear();
+    }
+
+    #[track_caller]
+    fn record(
+        &self,
+        res: Result<NonNull<u8>, AllocError>,
+        layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        #[cfg(feature = "debug_checks")]
+        self.owner.check();
+        if let Ok(ptr) = res {
+            self.live.borrow_mut().push((ptr, layout));
+        }
+        res
+    }
+}
+
+impl<A: Alloc + ?Sized> Alloc for Transaction<'_, A> {
+    #[track_caller]
+    #[inline]
+    fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.record(self.alloc.alloc(layout), layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.record(self.alloc.alloc_zeroed(layout), layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+        self.record(self.alloc.alloc_filled(layout, n), layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_patterned<F: Fn(usize) -> u8 + Clone>(
+        &self,
+        layout: Layout,
+        pattern: F,
+    ) -> Result<NonNull<u8>, AllocError> {
+        self.record(self.alloc.alloc_patterned(layout, pattern), layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        #[cfg(feature = "debug_checks")]
+        self.owner.check();
+        let mut live = self.live.borrow_mut();
+        // search from the back, the most recent allocations are the likeliest to be freed
+        if let Some(i) = live.iter().rposition(|&(p, _)| p == ptr) {
+            live.swap_remove(i);
+        }
+        drop(live);
+        unsafe {
+            self.alloc.dealloc(ptr, layout);
+        }
+    }
+
+    #[track_caller]
+    #[inline]
+    fn reserve(&self, bytes: usize) -> Result<(), AllocError> {
+        self.alloc.reserve(bytes)
+    }
+
+    #[inline]
+    fn granularity(&self) -> usize {
+        self.alloc.granularity()
+    }
+}
+
+impl<A: Alloc + ?Sized> Drop for Transaction<'_, A> {
+    fn drop(&mut self) {
+        for (ptr, layout) in take(self.live.get_mut()) {
+            unsafe {
+                self.alloc.dealloc(ptr, layout);
+            }
+        }
+    }
+}
+
+/// An allocator which defers deallocation until no reader could still be referencing the memory,
+/// for epoch-based reclamation in lock-free data structures.
+///
+/// Readers [`pin`](EpochAlloc::pin) the allocator before loading shared pointers and hold the
+/// returned guard for as long as they use them. Deallocating through the allocator doesn't free
+/// the block; it retires it, tagged with the current epoch. [`collect`](EpochAlloc::collect)
+/// advances the epoch and frees every retired block which was retired before the oldest live
+/// guard was pinned, since no such guard can have observed it.
+///
+/// A block must be made unreachable for new readers before it's deallocated, as with any
+/// epoch-based scheme. Remaining retired blocks are freed when the allocator is dropped.
+#[cfg(feature = "std")]
+pub struct EpochAlloc<A: Alloc> {
+    inner: A,
+    state: std::sync::Mutex<EpochState>,
+}
+
+#[cfg(feature = "std")]
+struct EpochState {
+    epoch: u64,
+    /// The number of live guards pinned at each epoch.
+    pinned: alloc::collections::BTreeMap<u64, usize>,
+    retired: Vec<(NonNull<u8>, Layout, u64)>,
+}
+
+// SAFETY: the retired pointers are owned blocks of `inner` which are only ever freed through it.
+#[cfg(feature = "std")]
+unsafe impl<A: Alloc + Send> Send for EpochAlloc<A> {}
+// SAFETY: all shared state is behind the mutex.
+#[cfg(feature = "std")]
+unsafe impl<A: Alloc + Sync> Sync for EpochAlloc<A> {}
+
+#[cfg(feature = "std")]
+impl<A: Alloc> EpochAlloc<A> {
+    /// Creates a new epoch-reclaiming allocator over `inner`.
+    #[must_use]
+    #[inline]
+    pub const fn new(inner: A) -> EpochAlloc<A> {
+        EpochAlloc {
+            inner,
+            state: std::sync::Mutex::new(EpochState {
+                epoch: 0,
+                pinned: alloc::collections::BTreeMap::new(),
+                retired: Vec::new(),
+            }),
+        }
+    }
+
+    /// Returns a reference to the underlying allocator.
+    #[must_use]
+    #[inline]
+    pub const fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Pins the current epoch, keeping every block retired from now on alive until the returned
+    /// guard is dropped.
+    #[must_use]
+    #[inline]
+    pub fn pin(&self) -> EpochGuard<'_, A> {
+        let mut state = self.lock();
+        let epoch = state.epoch;
+        *state.pinned.entry(epoch).or_insert(0) += 1;
+        EpochGuard { alloc: self, epoch }
+    }
+
+    /// Returns the current epoch.
+    #[must_use]
+    #[inline]
+    pub fn epoch(&self) -> u64 {
+        self.lock().epoch
+    }
+
+    /// Returns the number of retired blocks which haven't been freed yet.
+    #[must_use]
+    #[inline]
+    pub fn pending(&self) -> usize {
+        self.lock().retired.len()
+    }
+
+    /// Advances the epoch and frees every retired block which no live guard can reference.
+    ///
+    /// Returns the number of blocks freed.
+    #[track_caller]
+    pub fn collect(&self) -> usize {
+        let ready = {
+            let mut state = self.lock();
+            // guards pinned at an epoch after a block's retirement can't have seen it
+            let oldest = state.pinned.keys().next().copied().unwrap_or(u64::MAX);
+            state.epoch += 1;
+            let (ready, kept) = take(&mut state.retired)
+                .into_iter()
+                .partition::<Vec<_>, _>(|&(_, _, retired)| retired < oldest);
+            state.retired = kept;
+            ready
+        };
+        for &(ptr, layout, _) in &ready {
+            unsafe {
+                self.inner.dealloc(ptr, layout);
+            }
+        }
+        ready.len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, EpochState> {
+        self.state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A: Alloc> Alloc for EpochAlloc<A> {
+    #[track_caller]
+    #[inline]
+    fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.inner.alloc(layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.inner.alloc_zeroed(layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+        self.inner.alloc_filled(layout, n)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_patterned<F: Fn(usize) -> u8 + Clone>(
+        &self,
+        layout: Layout,
+        pattern: F,
+    ) -> Result<NonNull<u8>, AllocError> {
+        self.inner.alloc_patterned(layout, pattern)
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        let mut state = self.lock();
+        let epoch = state.epoch;
+        state.retired.push((ptr, layout, epoch));
+    }
+
+    #[track_caller]
+    #[inline]
+    fn reserve(&self, bytes: usize) -> Result<(), AllocError> {
+        self.inner.reserve(bytes)
+    }
+
+    #[inline]
+    fn granularity(&self) -> usize {
+        self.inner.granularity()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A: Alloc> Drop for EpochAlloc<A> {
+    fn drop(&mut self) {
+        let state = self
+            .state
+            .get_mut()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        for (ptr, layout, _) in take(&mut state.retired) {
+            unsafe {
+                self.inner.dealloc(ptr, layout);
+            }
+        }
+    }
+}
+
+/// A pinned epoch of an [`EpochAlloc`]. Blocks retired while it's alive aren't freed until it's
+/// dropped.
+#[cfg(feature = "std")]
+pub struct EpochGuard<'a, A: Alloc> {
+    alloc: &'a EpochAlloc<A>,
+    epoch: u64,
+}
+
+#[cfg(feature = "std")]
+impl<A: Alloc> EpochGuard<'_, A> {
+    /// Returns the epoch this guard is pinned at.
+    #[must_use]
+    #[inline]
+    pub const fn epoch(&self) -> u64 {
+        self.epoch
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A: Alloc> Drop for EpochGuard<'_, A> {
+    fn drop(&mut self) {
+        let mut state = self.alloc.lock();
+        if let Some(n) = state.pinned.get_mut(&self.epoch) {
+            *n -= 1;
+            if *n == 0 {
+                state.pinned.remove(&self.epoch);
+            }
+        }
+    }
+}
+
+/// An allocator which refuses to allocate while the calling thread is recursed deeper than a
+/// limit, for hardening recursive parsers and deserializers against deeply nested input.
+///
+/// The recursive code brackets each level with [`enter`](DepthLimitedAlloc::enter), holding the
+/// returned guard until it returns. Once more than `max_depth` levels are entered, allocations
+/// fail with [`AllocError::Unsupported`], so hostile input nested a million levels deep is
+/// rejected before it builds a huge structure, rather than after memory runs out.
+///
+/// Depth is counted separately for each thread, in a thread-local, and separately for each
+/// allocator. Deallocation and resizing existing allocations down are never refused.
+#[cfg(feature = "std")]
+pub struct DepthLimitedAlloc<A: Alloc> {
+    inner: A,
+    max_depth: usize,
+}
+
+#[cfg(feature = "std")]
+std::thread_local! {
+    /// The depth entered on this thread into each depth-limited allocator with a live guard, by
+    /// the allocator's address.
+    static DEPTHS: RefCell<Vec<(usize, usize)>> = const { RefCell::new(Vec::new()) };
+}
+
+#[cfg(feature = "std")]
+impl<A: Alloc> DepthLimitedAlloc<A> {
+    /// Creates a new depth-limited allocator over `inner`, refusing allocations from threads
+    /// which have entered more than `max_depth` levels.
+    #[must_use]
+    #[inline]
+    pub const fn new(inner: A, max_depth: usize) -> DepthLimitedAlloc<A> {
+        DepthLimitedAlloc { inner, max_depth }
+    }
+
+    /// Returns a reference to the underlying allocator.
+    #[must_use]
+    #[inline]
+    pub const fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Returns the deepest level allocations are still allowed at.
+    #[must_use]
+    #[inline]
+    pub const fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Returns the number of levels the current thread has entered.
+    #[must_use]
+    pub fn depth(&self) -> usize {
+        let key = self.key();
+        DEPTHS.with_borrow(|depths| {
+            depths
+                .iter()
+                .find(|&&(k, _)| k == key)
+                .map_or(0, |&(_, d)| d)
+        })
+    }
+
+    /// Enters one level of recursion on the current thread, until the returned guard is dropped.
+    ///
+    /// Entering past the limit succeeds; it's allocating there which fails.
+    #[must_use = "the level is left as soon as the guard is dropped"]
+    pub fn enter(&self) -> DepthGuard<'_, A> {
+        let key = self.key();
+        DEPTHS.with_borrow_mut(|depths| match depths.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, d)) => *d += 1,
+            None => depths.push((key, 1)),
+        });
+        DepthGuard {
+            alloc: self,
+            _not_send: PhantomData,
+        }
+    }
+
+    #[inline]
+    fn key(&self) -> usize {
+        core::ptr::from_ref(self).addr()
+    }
+
+    #[inline]
+    fn check(&self) -> Result<(), AllocError> {
+        if self.depth() > self.max_depth {
+            Err(AllocError::Unsupported("recursion depth exceeded"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A: Alloc> Alloc for DepthLimitedAlloc<A> {
+    #[track_caller]
+    #[inline]
+    fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.check()?;
+        self.inner.alloc(layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.check()?;
+        self.inner.alloc_zeroed(layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+        self.check()?;
+        self.inner.alloc_filled(layout, n)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_patterned<F: Fn(usize) -> u8 + Clone>(
+        &self,
+        layout: Layout,
+        pattern: F,
+    ) -> Result<NonNull<u8>, AllocError> {
+        self.check()?;
+        self.inner.alloc_patterned(layout, pattern)
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe {
+            self.inner.dealloc(ptr, layout);
+        }
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        // shrinking only ever frees memory, so it's allowed at any depth
+        unsafe { self.inner.shrink(ptr, old_layout, new_layout) }
+    }
+
+    #[track_caller]
+    #[inline]
+    fn reserve(&self, bytes: usize) -> Result<(), AllocError> {
+        self.inner.reserve(bytes)
+    }
+
+    #[inline]
+    fn granularity(&self) -> usize {
+        self.inner.granularity()
+    }
+}
+
+/// One level of recursion entered into a [`DepthLimitedAlloc`], left when dropped.
+///
+/// The guard can't be sent to another thread, as the level was entered on this one.
+#[cfg(feature = "std")]
+pub struct DepthGuard<'a, A: Alloc> {
+    alloc: &'a DepthLimitedAlloc<A>,
+    _not_send: PhantomData<*const ()>,
+}
+
+#[cfg(feature = "std")]
+impl<A: Alloc> Drop for DepthGuard<'_, A> {
+    fn drop(&mut self) {
+        let key = self.alloc.key();
+        DEPTHS.with_borrow_mut(|depths| {
+            if let Some(i) = depths.iter().position(|&(k, _)| k == key) {
+                depths[i].1 -= 1;
+                if depths[i].1 == 0 {
+                    depths.swap_remove(i);
+                }
+            }
+        });
+    }
+}
+
+/// An allocator which rounds the size of every allocation up to a multiple of `N` bytes.
+///
+/// The start of each allocation keeps its requested alignment, but the block behind it extends to
+/// the next multiple of `N` past the start. A loop processing `N` bytes at a time can therefore
+/// run its final iteration over the tail of the buffer without a scalar remainder loop, reading
+/// and writing past the requested size but never past the padded block.
+///
+/// The padding is part of the allocation: zeroed, filled, and patterned allocations initialize it
+/// like the rest of the block, while plain allocations leave it uninitialized, so overrun reads of
+/// those must go through `MaybeUninit` or intrinsics which tolerate uninitialized lanes.
+///
+/// Every layout passed to this allocator, including on deallocation, is padded the same way, so
+/// callers keep using their unpadded layouts. The granularity is the least common multiple of `N`
+/// and the inner allocator's.
+pub struct TailPadded<const N: usize, A: Alloc> {
+    inner: A,
+}
+
+impl<const N: usize, A: Alloc> TailPadded<N, A> {
+    /// Creates a new tail-padding allocator over `inner`. `N` being zero is a compile-time error.
+    #[must_use]
+    #[inline]
+    pub const fn new(inner: A) -> TailPadded<N, A> {
+        const { assert!(N != 0, "`N` must be non-zero") };
+        TailPadded { inner }
+    }
+
+    /// Returns a reference to the underlying allocator.
+    #[must_use]
+    #[inline]
+    pub const fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Returns the layout actually allocated for `layout`.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::ArithmeticOverflow`] if rounding the size up overflows.
+    /// - [`AllocError::LayoutError`] if the padded size is too large for `layout`'s alignment.
+    #[inline]
+    pub const fn padded(layout: Layout) -> Result<Layout, AllocError> {
+        let Some(size) = layout.size().checked_next_multiple_of(N) else {
+            return Err(AllocError::ArithmeticOverflow);
+        };
+        match Layout::from_size_align(size, layout.align()) {
+            Ok(padded) => Ok(padded),
+            Err(_) => Err(AllocError::LayoutError(size, layout.align())),
+        }
+    }
+}
+
+impl<const N: usize, A: Alloc> Alloc for TailPadded<N, A> {
+    #[track_caller]
+    #[inline]
+    fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.inner.alloc(Self::padded(layout)?)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.inner.alloc_zeroed(Self::padded(layout)?)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+        self.inner.alloc_filled(Self::padded(layout)?, n)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_patterned<F: Fn(usize) -> u8 + Clone>(
+        &self,
+        layout: Layout,
+        pattern: F,
+    ) -> Result<NonNull<u8>, AllocError> {
+        self.inner.alloc_patterned(Self::padded(layout)?, pattern)
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        // the padded layout was valid when this block was allocated
+        let padded =
+            Self::padded(layout).expect("deallocated with a layout which couldn't be padded");
+        unsafe {
+            self.inner.dealloc(ptr, padded);
+        }
+    }
+
+    #[track_caller]
+    #[inline]
+    fn reserve(&self, bytes: usize) -> Result<(), AllocError> {
+        self.inner.reserve(bytes)
+    }
+
+    #[inline]
+    fn granularity(&self) -> usize {
+        // sizes which are multiples of both waste neither the padding nor the inner slack
+        let inner = self.inner.granularity();
+        let mut a = N;
+        let mut b = inner;
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        (N / a).checked_mul(inner).unwrap_or(N.max(inner))
+    }
+}
+
+/// An allocator which refuses any single allocation larger than a fixed maximum.
+///
+/// Unlike a limit on the total allocated, this is a hard ceiling per request, however much memory
+/// is available. It guards against a corrupt or hostile length field, like one read while parsing
+/// untrusted input, turning into one huge allocation which is clearly a bug even if it would fit.
+/// Requests over the maximum, including growing an allocation past it, fail with
+/// [`AllocError::Unsupported`] without reaching the underlying allocator.
+pub struct MaxSizeAlloc<A: Alloc> {
+    inner: A,
+    max: usize,
+}
+
+impl<A: Alloc> MaxSizeAlloc<A> {
+    /// Creates a new size-capping allocator over `inner`, refusing allocations of more than `max`
+    /// bytes.
+    #[must_use]
+    #[inline]
+    pub const fn new(inner: A, max: usize) -> MaxSizeAlloc<A> {
+        MaxSizeAlloc { inner, max }
+    }
+
+    /// Returns a reference to the underlying allocator.
+    #[must_use]
+    #[inline]
+    pub const fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Returns the largest size in bytes a single allocation may have.
+    #[must_use]
+    #[inline]
+    pub const fn max_alloc_size(&self) -> usize {
+        self.max
+    }
+
+    #[inline]
+    const fn check(&self, layout: Layout) -> Result<(), AllocError> {
+        if layout.size() > self.max {
+            Err(AllocError::Unsupported("exceeds max allocation size"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<A: Alloc> Alloc for MaxSizeAlloc<A> {
+    #[track_caller]
+    #[inline]
+    fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.check(layout)?;
+        self.inner.alloc(layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.check(layout)?;
+        self.inner.alloc_zeroed(layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+        self.check(layout)?;
+        self.inner.alloc_filled(layout, n)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_patterned<F: Fn(usize) -> u8 + Clone>(
+        &self,
+        layout: Layout,
+        pattern: F,
+    ) -> Result<NonNull<u8>, AllocError> {
+        self.check(layout)?;
+        self.inner.alloc_patterned(layout, pattern)
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe {
+            self.inner.dealloc(ptr, layout);
+        }
+    }
+
+    #[track_caller]
+    #[inline]
+    fn reserve(&self, bytes: usize) -> Result<(), AllocError> {
+        self.inner.reserve(bytes)
+    }
+
+    #[inline]
+    fn granularity(&self) -> usize {
+        self.inner.granularity()
+    }
+}
+
+/// An allocator which staggers successive allocations across cache sets.
+///
+/// Large blocks from most allocators start at the same offset within a page, so arrays of equal
+/// size scanned in lockstep hit the same cache sets on every step and evict each other even when
+/// the cache has room for all of them. This shifts each allocation forward by a varying "color",
+/// a multiple of the cache line size cycling through `colors` values, so that related buffers
+/// land on different sets.
+///
+/// The shift is leading padding inside a larger block from `A`. Every block also reserves one
+/// extra line in front of the allocation to record its color, so deallocation finds the start of
+/// the block again from the caller's pointer and unpadded layout. A block costs
+/// `colors * max(line, align)` bytes more than was asked for.
+pub struct ColoredAlloc<A: Alloc> {
+    inner: A,
+    colors: usize,
+    line: usize,
+    next: AtomicUsize,
+}
+
+impl<A: Alloc> ColoredAlloc<A> {
+    /// Creates a new coloring allocator over `inner`, cycling through `colors` offsets of 64-byte
+    /// cache lines.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `colors` is zero.
+    #[track_caller]
+    #[must_use]
+    #[inline]
+    pub const fn new(inner: A, colors: usize) -> ColoredAlloc<A> {
+        assert!(colors != 0, "`colors` must be non-zero");
+        ColoredAlloc {
+            inner,
+            colors,
+            line: 64,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Sets the cache line size offsets are a multiple of.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `line` isn't a power of two, or is smaller than a `usize`.
+    #[track_caller]
+    #[must_use]
+    #[inline]
+    pub const fn with_line_size(mut self, line: usize) -> ColoredAlloc<A> {
+        assert!(
+            line.is_power_of_two() && line >= size_of::<usize>(),
+            "`line` must be a power of two no smaller than a `usize`"
+        );
+        self.line = line;
+        self
+    }
+
+    /// Returns a reference to the underlying allocator.
+    #[must_use]
+    #[inline]
+    pub const fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Returns the number of colors allocations cycle through.
+    #[must_use]
+    #[inline]
+    pub const fn colors(&self) -> usize {
+        self.colors
+    }
+
+    /// Returns the cache line size offsets are a multiple of.
+    #[must_use]
+    #[inline]
+    pub const fn line_size(&self) -> usize {
+        self.line
+    }
+
+    /// Returns the distance between colors for `layout` and the layout of the block holding it.
+    fn outer(&self, layout: Layout) -> Result<(usize, Layout), AllocError> {
+        // both are powers of two, so the larger is a multiple of the smaller
+        let stride = self.line.max(layout.align());
+        let size = stride
+            .checked_mul(self.colors)
+            .and_then(|pad| pad.checked_add(layout.size()))
+            .ok_or(AllocError::ArithmeticOverflow)?;
+        Layout::from_size_align(size, layout.align())
+            .map(|outer| (stride, outer))
+            .map_err(|_| AllocError::LayoutError(size, layout.align()))
+    }
+
+    /// Places an allocation inside `block` at the next color, recording the color in front of it.
+    fn place(&self, block: NonNull<u8>, stride: usize) -> NonNull<u8> {
+        let color = self.next.fetch_add(1, Relaxed) % self.colors;
+        unsafe {
+            let ptr = block.add((color + 1) * stride);
+            ptr.cast::<usize>().sub(1).write_unaligned(color);
+            ptr
+        }
+    }
+
+    #[track_caller]
+    fn alloc_with(
+        &self,
+        layout: Layout,
+        alloc: impl FnOnce(Layout) -> Result<NonNull<u8>, AllocError>,
+    ) -> Result<NonNull<u8>, AllocError> {
+        // the padding alone would make the outer block nonzero, with the pointer at its end
+        if layout.size() == 0 {
+            return Err(AllocError::ZeroSizedLayout(unsafe {
+                dangling_nonnull(layout.align())
+            }));
+        }
+        let (stride, outer) = self.outer(layout)?;
+        alloc(outer).map(|block| self.place(block, stride))
+    }
+}
+
+impl<A: Alloc> Alloc for ColoredAlloc<A> {
+    #[track_caller]
+    #[inline]
+    fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.alloc_with(layout, |l| self.inner.alloc(l))
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.alloc_with(layout, |l| self.inner.alloc_zeroed(l))
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+        self.alloc_with(layout, |l| self.inner.alloc_filled(l, n))
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_patterned<F: Fn(usize) -> u8 + Clone>(
+        &self,
+        layout: Layout,
+        pattern: F,
+    ) -> Result<NonNull<u8>, AllocError> {
+        // the pattern is indexed from the start of the allocation, not the block
+        let ptr = self.alloc(layout)?;
+        for i in 0..layout.size() {
+            unsafe {
+                ptr.add(i).write(pattern(i));
+            }
+        }
+        Ok(ptr)
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        // the block's layout was valid when it was allocated
+        let (stride, outer) = self
+            .outer(layout)
+            .expect("deallocated with a layout which couldn't be colored");
+        unsafe {
+            let color = ptr.cast::<usize>().sub(1).read_unaligned();
+            self.inner.dealloc(ptr.sub((color + 1) * stride), outer);
+        }
+    }
+
+    #[track_caller]
+    #[inline]
+    fn reserve(&self, bytes: usize) -> Result<(), AllocError> {
+        self.inner.reserve(bytes)
+    }
+
+    #[inline]
+    fn granularity(&self) -> usize {
+        self.inner.granularity()
+    }
+}
+
+impl<A: Alloc> core::fmt::Debug for ColoredAlloc<A> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ColoredAlloc")
+            .field("colors", &self.colors)
+            .field("line", &self.line)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The type of an unset [`CallbackAlloc`] zeroed allocation callback.
+pub type ZeroedCallback = fn(Layout) -> Result<NonNull<u8>, AllocError>;
+/// The type of an unset [`CallbackAlloc`] reallocation callback.
+pub type ReallocCallback = fn(NonNull<u8>, Layout, Layout) -> Result<NonNull<u8>, AllocError>;
+
+/// An allocator which dispatches to user-supplied callbacks, for bridging to backends such as a C
+/// library's allocation functions or a device memory pool.
+///
+/// Only `alloc` and `dealloc` callbacks are required. Zeroed allocations use the
+/// [`alloc_zeroed`](CallbackAlloc::with_alloc_zeroed) callback if set, and are zeroed manually
+/// otherwise. Every resize goes through the [`realloc`](CallbackAlloc::with_realloc) callback if
+/// set, with new bytes filled afterward as requested; otherwise it allocates, copies, and frees.
+/// Filled and patterned allocations are always written after allocating. Zero-sized layouts are
+/// refused with [`AllocError::ZeroSizedLayout`] without calling any callback.
+///
+/// The callbacks are stored by value, so `CallbackAlloc` is `Send` or `Sync` exactly when all of
+/// them are.
+pub struct CallbackAlloc<Al, De, Z = ZeroedCallback, R = ReallocCallback> {
+    alloc: Al,
+    dealloc: De,
+    alloc_zeroed: Option<Z>,
+    realloc: Option<R>,
+}
+
+impl<Al: Fn(Layout) -> Result<NonNull<u8>, AllocError>, De: Fn(NonNull<u8>, Layout)>
+    CallbackAlloc<Al, De>
+{
+    /// Creates a new allocator from its allocation and deallocation callbacks.
+    ///
+    /// `alloc` must return a block valid for `layout`, and `dealloc` is only ever passed blocks
+    /// returned by this allocator's callbacks, with the layout they were last allocated or
+    /// resized to.
+    #[must_use]
+    #[inline]
+    pub const fn new(alloc: Al, dealloc: De) -> CallbackAlloc<Al, De> {
+        CallbackAlloc {
+            alloc,
+            dealloc,
+            alloc_zeroed: None,
+            realloc: None,
+        }
+    }
+}
+
+impl<Al, De, Z, R> CallbackAlloc<Al, De, Z, R> {
+    /// Sets the callback used for zeroed allocations, which must return zeroed memory.
+    #[must_use]
+    #[inline]
+    pub fn with_alloc_zeroed<Z2: Fn(Layout) -> Result<NonNull<u8>, AllocError>>(
+        self,
+        alloc_zeroed: Z2,
+    ) -> CallbackAlloc<Al, De, Z2, R> {
+        CallbackAlloc {
+            alloc: self.alloc,
+            dealloc: self.dealloc,
+            alloc_zeroed: Some(alloc_zeroed),
+            realloc: self.realloc,
+        }
+    }
+
+    /// Sets the callback used for every resize.
+    ///
+    /// It's passed a live block with its current layout and the requested one, and must return a
+    /// block valid for the new layout holding the first `min(old, new)` bytes of the old one. On
+    /// error, the old block must be left untouched.
+    #[must_use]
+    #[inline]
+    pub fn with_realloc<R2: Fn(NonNull<u8>, Layout, Layout) -> Result<NonNull<u8>, AllocError>>(
+        self,
+        realloc: R2,
+    ) -> CallbackAlloc<Al, De, Z, R2> {
+        CallbackAlloc {
+            alloc: self.alloc,
+            dealloc: self.dealloc,
+            alloc_zeroed: self.alloc_zeroed,
+            realloc: Some(realloc),
+        }
+    }
+}
+
+impl<
+    Al: Fn(Layout) -> Result<NonNull<u8>, AllocError>,
+    De: Fn(NonNull<u8>, Layout),
+    Z: Fn(Layout) -> Result<NonNull<u8>, AllocError>,
+    R: Fn(NonNull<u8>, Layout, Layout) -> Result<NonNull<u8>, AllocError>,
+> CallbackAlloc<Al, De, Z, R>
+{
+    /// Fails with [`AllocError::ZeroSizedLayout`] if `layout` has a size of zero, and calls
+    /// `alloc` with it otherwise.
+    ///
+    /// Backends like C's `malloc` return a block even for a size of zero, so this keeps such
+    /// layouts from reaching the callbacks.
+    #[track_caller]
+    fn nonzero(
+        layout: Layout,
+        alloc: impl FnOnce(Layout) -> Result<NonNull<u8>, AllocError>,
+    ) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 {
+            return Err(AllocError::ZeroSizedLayout(unsafe {
+                dangling_nonnull(layout.align())
+            }));
+        }
+        alloc(layout)
+    }
+
+    /// Resizes the block at `ptr` to `new_layout`, filling any new bytes with `pattern`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a live block of this allocator described by `old_layout`.
+    #[track_caller]
+    unsafe fn resize<F: Fn(usize) -> u8>(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        pattern: Option<F>,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let new_ptr = if let Some(realloc) = &self.realloc {
+            Self::nonzero(new_layout, |l| realloc(ptr, old_layout, l))?
+        } else {
+            let new_ptr = Self::nonzero(new_layout, &self.alloc)?;
+            unsafe {
+                ptr.copy_to_nonoverlapping(new_ptr, old_layout.size().min(new_layout.size()));
+            }
+            (self.dealloc)(ptr, old_layout);
+            new_ptr
+        };
+        if let Some(pattern) = pattern {
+            for i in old_layout.size()..new_layout.size() {
+                unsafe { new_ptr.add(i).write(pattern(i)) };
+            }
+        }
+        Ok(new_ptr)
+    }
+
+    /// Checks that a resize grows, then performs it.
+    ///
+    /// # Safety
+    ///
+    /// See [`resize`](CallbackAlloc::resize).
+    #[track_caller]
+    unsafe fn checked_grow<F: Fn(usize) -> u8>(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        pattern: Option<F>,
+    ) -> Result<NonNull<u8>, AllocError> {
+        if new_layout.size() < old_layout.size() {
+            return Err(AllocError::GrowSmallerNewLayout(
+                old_layout.size(),
+                new_layout.size(),
+            ));
+        }
+        unsafe { self.resize(ptr, old_layout, new_layout, pattern) }
+    }
+}
+
+impl<
+    Al: Fn(Layout) -> Result<NonNull<u8>, AllocError>,
+    De: Fn(NonNull<u8>, Layout),
+    Z: Fn(Layout) -> Result<NonNull<u8>, AllocError>,
+    R: Fn(NonNull<u8>, Layout, Layout) -> Result<NonNull<u8>, AllocError>,
+> Alloc for CallbackAlloc<Al, De, Z, R>
+{
+    #[track_caller]
+    #[inline]
+    fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        Self::nonzero(layout, &self.alloc)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        match &self.alloc_zeroed {
+            Some(alloc_zeroed) => Self::nonzero(layout, alloc_zeroed),
+            None => self.alloc_filled(layout, 0),
+        }
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+        let ptr = Self::nonzero(layout, &self.alloc)?;
+        unsafe { ptr.write_bytes(n, layout.size()) };
+        Ok(ptr)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_patterned<F: Fn(usize) -> u8 + Clone>(
+        &self,
+        layout: Layout,
+        pattern: F,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let ptr = Self::nonzero(layout, &self.alloc)?;
+        for i in 0..layout.size() {
+            unsafe { ptr.add(i).write(pattern(i)) };
+        }
+        Ok(ptr)
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        (self.dealloc)(ptr, layout);
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        unsafe { self.checked_grow(ptr, old_layout, new_layout, None::<fn(usize) -> u8>) }
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        unsafe { self.checked_grow(ptr, old_layout, new_layout, Some(|_| 0)) }
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn grow_patterned<F: Fn(usize) -> u8 + Clone>(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        pattern: F,
+    ) -> Result<NonNull<u8>, AllocError> {
+        unsafe { self.checked_grow(ptr, old_layout, new_layout, Some(pattern)) }
+    }
+
+    #[track_caller]
+    #[inline]
+    fn grow_filled(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        n: u8,
+    ) -> Result<NonNull<u8>, AllocError> {
+        // `Alloc::grow_filled` carries the same contract as the other resizing methods
+        unsafe { self.checked_grow(ptr, old_layout, new_layout, Some(|_| n)) }
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        if new_layout.size() > old_layout.size() {
+            return Err(AllocError::ShrinkBiggerNewLayout(
+                old_layout.size(),
+                new_layout.size(),
+            ));
+        }
+        unsafe { self.resize(ptr, old_layout, new_layout, None::<fn(usize) -> u8>) }
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn realloc(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        unsafe { self.resize(ptr, old_layout, new_layout, None::<fn(usize) -> u8>) }
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn realloc_patterned<F: Fn(usize) -> u8 + Clone>(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        pattern: F,
+    ) -> Result<NonNull<u8>, AllocError> {
+        unsafe { self.resize(ptr, old_layout, new_layout, Some(pattern)) }
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn realloc_filled(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        n: u8,
+    ) -> Result<NonNull<u8>, AllocError> {
+        unsafe { self.resize(ptr, old_layout, new_layout, Some(|_| n)) }
+    }
+}
+
+/// How long a [`DelayAlloc`] stalls each allocation.
+///
+/// Each delay is drawn uniformly from `min..=max`, so setting both to the same value gives a fixed
+/// delay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delay {
+    /// Sleep the thread.
+    #[cfg(feature = "std")]
+    Sleep {
+        /// The shortest delay.
+        min: Duration,
+        /// The longest delay.
+        max: Duration,
+    },
+    /// Busy-wait for a number of spin-loop iterations, for targets without threads to sleep.
+    Spin {
+        /// The fewest iterations.
+        min: u64,
+        /// The most iterations.
+        max: u64,
+    },
+}
+
+impl Delay {
+    /// Sleeps for exactly `d`.
+    #[cfg(feature = "std")]
+    #[must_use]
+    #[inline]
+    pub const fn sleep(d: Duration) -> Delay {
+        Delay::Sleep { min: d, max: d }
+    }
+
+    /// Spins for exactly `n` iterations.
+    #[must_use]
+    #[inline]
+    pub const fn spin(n: u64) -> Delay {
+        Delay::Spin { min: n, max: n }
+    }
+}
+
+/// A wrapper which delays every allocation before delegating it to `A`, for testing code which
+/// must cope with slow allocation, like timeouts and cancellation.
+///
+/// Only allocations are delayed; deallocation goes straight through. Resizing is delayed once, by
+/// the allocation of the new block. The total delay injected is counted, so tests can check the
+/// slowness was actually exercised.
+///
+/// Random delays are drawn from a xorshift generator, seeded with
+/// [`with_seed`](DelayAlloc::with_seed) for reproducible runs.
+pub struct DelayAlloc<A: Alloc> {
+    inner: A,
+    delay: Delay,
+    rng: AtomicU64,
+    delayed: AtomicUsize,
+    /// Nanoseconds slept or iterations spun, depending on the delay.
+    total: AtomicU64,
+}
+
+impl<A: Alloc> DelayAlloc<A> {
+    /// Creates a new delaying allocator over `inner`.
+    #[must_use]
+    #[inline]
+    pub const fn new(inner: A, delay: Delay) -> DelayAlloc<A> {
+        DelayAlloc {
+            inner,
+            delay,
+            rng: AtomicU64::new(0x9E37_79B9_7F4A_7C15),
+            delayed: AtomicUsize::new(0),
+            total: AtomicU64::new(0),
+        }
+    }
+
+    /// Seeds the generator random delays are drawn from.
+    #[must_use]
+    #[inline]
+    pub const fn with_seed(mut self, seed: u64) -> DelayAlloc<A> {
+        // xorshift gets stuck at zero
+        self.rng = AtomicU64::new(if seed == 0 { 1 } else { seed });
+        self
+    }
+
+    /// Returns a reference to the underlying allocator.
+    #[must_use]
+    #[inline]
+    pub const fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Returns the number of allocations which have been delayed.
+    #[must_use]
+    #[inline]
+    pub fn delayed_calls(&self) -> usize {
+        self.delayed.load(Relaxed)
+    }
+
+    /// Returns the total time slept, or zero if spinning.
+    #[cfg(feature = "std")]
+    #[must_use]
+    #[inline]
+    pub fn total_slept(&self) -> Duration {
+        match self.delay {
+            Delay::Sleep { .. } => Duration::from_nanos(self.total.load(Relaxed)),
+            Delay::Spin { .. } => Duration::ZERO,
+        }
+    }
+
+    /// Returns the total number of iterations spun, or zero if sleeping.
+    #[must_use]
+    #[inline]
+    pub fn total_spins(&self) -> u64 {
+        match self.delay {
+            #[cfg(feature = "std")]
+            Delay::Sleep { .. } => 0,
+            Delay::Spin { .. } => self.total.load(Relaxed),
+        }
+    }
+
+    /// Draws a value from `min..=max`.
+    fn sample(&self, min: u64, max: u64) -> u64 {
+        if min >= max {
+            return min;
+        }
+        let step = |x: u64| {
+            let x = x ^ (x << 13);
+            let x = x ^ (x >> 7);
+            x ^ (x << 17)
+        };
+        let prev = match self.rng.fetch_update(Relaxed, Relaxed, |x| Some(step(x))) {
+            Ok(x) | Err(x) => x,
+        };
+        let x = step(prev);
+        match (max - min).checked_add(1) {
+            Some(span) => min + x % span,
+            None => x,
+        }
+    }
+
+    fn stall(&self) {
+        let amount = match self.delay {
+            #[cfg(feature = "std")]
+            Delay::Sleep { min, max } => {
+                let nanos = |d: Duration| u64::try_from(d.as_nanos()).unwrap_or(u64::MAX);
+                let n = self.sample(nanos(min), nanos(max));
+                std::thread::sleep(Duration::from_nanos(n));
+                n
+            }
+            Delay::Spin { min, max } => {
+                let n = self.sample(min, max);
+                for _ in 0..n {
+                    core::hint::spin_loop();
+                }
+                n
+            }
+        };
+        self.delayed.fetch_add(1, Relaxed);
+        self.total.fetch_add(amount, Relaxed);
+    }
+}
+
+impl<A: Alloc> Alloc for DelayAlloc<A> {
+    #[track_caller]
+    #[inline]
+    fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.stall();
+        self.inner.alloc(layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.stall();
+        self.inner.alloc_zeroed(layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+        self.stall();
+        self.inner.alloc_filled(layout, n)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_patterned<F: Fn(usize) -> u8 + Clone>(
+        &self,
+        layout: Layout,
+        pattern: F,
+    ) -> Result<NonNull<u8>, AllocError> {
+        self.stall();
+        self.inner.alloc_patterned(layout, pattern)
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe {
+            self.inner.dealloc(ptr, layout);
+        }
+    }
+
+    #[track_caller]
+    #[inline]
+    fn reserve(&self, bytes: usize) -> Result<(), AllocError> {
+        self.inner.reserve(bytes)
+    }
+
+    #[inline]
+    fn granularity(&self) -> usize {
+        self.inner.granularity()
+    }
+}
+
+impl<A: Alloc> core::fmt::Debug for DelayAlloc<A> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("DelayAlloc")
+            .field("delay", &self.delay)
+            .field("delayed_calls", &self.delayed_calls())
+            .finish_non_exhaustive()
+    }
+}
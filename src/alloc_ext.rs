@@ -1,15 +1,27 @@
-#[cfg(feature = "metadata")]
-use crate::UnsizedCopy;
+#[cfg(feature = "adapters")]
+use crate::adapters::Transaction;
+#[cfg(feature = "owned")]
+use crate::owned::AllocArc;
 use crate::{
-    Alloc, AllocError, PtrProps, SizedProps,
-    helpers::{AllocGuard, SliceAllocGuard},
-    layout_or_sz_align,
+    Alloc, AllocError, PtrProps, SizedProps, Zeroable,
+    helpers::{AllocGuard, LayoutBuilder, SliceAllocGuard, array_layout},
 };
+#[cfg(feature = "metadata")]
+use crate::{UnsizedCopy, helpers::validate_layout};
+use alloc::vec::Vec;
 #[cfg(feature = "clone_to_uninit")]
 use core::clone::CloneToUninit;
 #[cfg(feature = "metadata")]
 use core::ptr::metadata;
-use core::{alloc::Layout, mem::MaybeUninit, ptr::NonNull};
+use core::{
+    alloc::Layout,
+    cell::RefCell,
+    fmt::{self, Debug, Formatter},
+    marker::PhantomData,
+    mem::{MaybeUninit, forget, needs_drop},
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+};
 
 /// Extension methods for the core [`Alloc`] trait, providing convenient
 /// routines to allocate, initialize, clone, copy, and deallocate sized
@@ -73,6 +85,23 @@ pub trait AllocExt: Alloc {
         self.alloc_slice_with(len, |_| T::default())
     }
 
+    /// Allocates a `[T]` of length `len` with every element initialized to zero.
+    ///
+    /// Unlike [`alloc_default_slice`](AllocExt::alloc_default_slice), this doesn't write each
+    /// element, it relies on [`alloc_zeroed`](Alloc::alloc_zeroed), which is usually much
+    /// cheaper for large buffers.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    /// - [`AllocError::LayoutError`] if the computed layout is invalid.
+    #[track_caller]
+    #[inline]
+    fn alloc_zeroable_slice<T: Zeroable>(&self, len: usize) -> Result<NonNull<[T]>, AllocError> {
+        // `T: Zeroable`, so the zeroed memory is already a valid `[T]`
+        self.alloc_slice_zeroed(len)
+    }
+
     /// Allocates uninitialized memory for a single `T` and writes `data` into it.
     ///
     /// # Errors
@@ -91,6 +120,135 @@ pub trait AllocExt: Alloc {
         }
     }
 
+    /// Allocates uninitialized memory for a single `T`, writes `data` into it, and registers the
+    /// value with `reg` so it's dropped when the registry runs.
+    ///
+    /// The registry only drops the value; the memory is still owned by the caller. It must stay
+    /// allocated, and the value must not be moved out or dropped, until `reg` has run. `reg`
+    /// borrows this allocator for `'a`, so it can't outlive it, and it can't be reset or dropped
+    /// while the registry is alive. Values may only borrow data which outlives the registry.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails. `data` is dropped in this case.
+    #[track_caller]
+    #[inline]
+    fn alloc_write_registered<'a, T: 'a>(
+        &'a self,
+        data: T,
+        reg: &DropRegistry<'a>,
+    ) -> Result<NonNull<T>, AllocError> {
+        let ptr = self.alloc_write(data)?;
+        reg.register(ptr);
+        Ok(ptr)
+    }
+
+    /// Allocates `value` behind an atomically reference-counted [`AllocArc`] which borrows this
+    /// allocator, and frees through it once the last clone is dropped.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails. `value` is dropped in this case.
+    #[cfg(feature = "owned")]
+    #[track_caller]
+    #[inline]
+    fn alloc_arc<T>(&self, value: T) -> Result<AllocArc<T, &Self>, AllocError> {
+        AllocArc::new_in(value, self)
+    }
+
+    /// Allocates uninitialized memory for a single `T`, writes `data` into it, and returns it as a
+    /// raw pointer for handing ownership across an FFI boundary.
+    ///
+    /// Nothing on the Rust side drops the value or frees the memory. The foreign code owns it
+    /// until it gives the pointer back, which must then be passed to
+    /// [`reclaim_from_ffi`](AllocExt::reclaim_from_ffi) on this same allocator.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails. `data` is dropped in this case.
+    #[track_caller]
+    #[inline]
+    fn alloc_write_for_ffi<T>(&self, data: T) -> Result<*mut T, AllocError> {
+        match self.alloc_write(data) {
+            Ok(ptr) => Ok(ptr.as_ptr()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Takes back ownership of a value handed out by
+    /// [`alloc_write_for_ffi`](AllocExt::alloc_write_for_ffi), dropping it and freeing its memory.
+    ///
+    /// A null `ptr` is ignored, like C's `free`, so foreign code may hand back a pointer it never
+    /// set.
+    ///
+    /// # Safety
+    ///
+    /// - `ptr` must be null or have been returned by `alloc_write_for_ffi::<T>` on this allocator.
+    /// - The value must still be valid, and must not be used again afterward.
+    #[track_caller]
+    #[inline]
+    unsafe fn reclaim_from_ffi<T>(&self, ptr: *mut T) {
+        if let Some(ptr) = NonNull::new(ptr) {
+            unsafe {
+                self.drop_and_dealloc(ptr);
+            }
+        }
+    }
+
+    /// Allocates uninitialized memory for a single `T`, then initializes it with the value `f`
+    /// returns when given the allocation's address.
+    ///
+    /// This allows building values which refer to their own location, like a node linked to
+    /// itself, similar to `Rc::new_cyclic`. If `f` panics, the memory is deallocated.
+    ///
+    /// The pointer passed to `f` is uninitialized until `f` returns: reading or writing through
+    /// it inside `f` is undefined behavior. It may only be stored or have its address used.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    #[track_caller]
+    #[inline]
+    fn alloc_cyclic<T, F: FnOnce(NonNull<T>) -> T>(&self, f: F) -> Result<NonNull<T>, AllocError> {
+        let guard = AllocGuard::new(self.alloc(T::LAYOUT)?.cast::<T>(), self);
+        let data = f(*guard);
+        unsafe { guard.write(data) };
+        Ok(guard.release())
+    }
+
+    /// Allocates memory fitting `layout` and writes `data` at its start.
+    ///
+    /// This allows over-allocating, e.g. to leave trailing space for later in-place growth. The
+    /// returned pointer must be deallocated using `layout`, not `T`'s layout.
+    ///
+    /// # Safety
+    ///
+    /// `layout`'s size and alignment must be at least those of `T`.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    #[track_caller]
+    #[inline]
+    unsafe fn alloc_write_layout<T>(
+        &self,
+        data: T,
+        layout: Layout,
+    ) -> Result<NonNull<T>, AllocError> {
+        debug_assert!(
+            layout.size() >= T::SZ && layout.align() >= T::ALIGN,
+            "layout is too small or under-aligned for `T`"
+        );
+        match self.alloc(layout) {
+            Ok(ptr) => Ok(unsafe {
+                let ptr = ptr.cast();
+                ptr.write(data);
+                ptr
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
     #[cfg(not(feature = "clone_to_uninit"))]
     /// Allocates uninitialized memory for a single `T` and clones `data` into it.
     ///
@@ -144,14 +302,41 @@ pub trait AllocExt: Alloc {
     fn alloc_clone_to<T: CloneToUninit>(&self, data: &T) -> Result<NonNull<T>, AllocError> {
         match self.alloc(Layout::for_value::<T>(data)) {
             Ok(ptr) => Ok(unsafe {
-                let guard = AllocGuard::new(ptr, self);
-                data.clone_to_uninit(guard.as_ptr());
-                guard.release().cast()
+                // the guard must be typed so a panicking clone deallocates with `T`'s layout
+                let guard = AllocGuard::new(ptr.cast::<T>(), self);
+                data.clone_to_uninit(guard.as_ptr().cast());
+                guard.release()
             }),
             Err(e) => Err(e),
         }
     }
 
+    /// Moves the elements of `v` into a new slice allocated using this allocator.
+    ///
+    /// The elements are moved, not cloned: they're copied bitwise into the new slice and the
+    /// vector's own buffer is freed using the global allocator without dropping them.
+    ///
+    /// # Errors
+    ///
+    /// On any error, `v` is dropped along with its elements.
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    /// - [`AllocError::LayoutError`] if the computed layout is invalid.
+    /// - [`AllocError::ZeroSizedLayout`] if `v` is empty or `T` is zero-sized.
+    #[track_caller]
+    #[inline]
+    fn vec_into_alloc<T>(&self, mut v: Vec<T>) -> Result<NonNull<[T]>, AllocError> {
+        let len = v.len();
+        let ptr = self.alloc_slice::<T>(len)?;
+        unsafe {
+            ptr.cast::<T>()
+                .copy_from_nonoverlapping(NonNull::new_unchecked(v.as_mut_ptr()), len);
+            // the elements now belong to the new slice, so `v` must only free its buffer
+            v.set_len(0);
+        }
+        Ok(ptr)
+    }
+
     /// Allocates uninitialized memory for a slice of `T` and clones each element.
     ///
     /// # Errors
@@ -172,6 +357,28 @@ pub trait AllocExt: Alloc {
         }
     }
 
+    /// Allocates a copy of `data`, writing it with non-temporal stores where the target supports
+    /// them.
+    ///
+    /// Non-temporal stores bypass the cache, so copying a large buffer which won't be read again
+    /// soon doesn't evict everything else from it. On `x86_64` this streams 16-byte chunks with
+    /// SSE2; elsewhere, and for small slices where it wouldn't pay off, it's an ordinary copy. Reads
+    /// of the copy right after it's made are slower than after an ordinary copy.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    /// - [`AllocError::ZeroSizedLayout`] if `data` is empty.
+    #[track_caller]
+    #[inline]
+    fn alloc_copy_bytes_nt(&self, data: &[u8]) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = self.alloc(Layout::for_value(data))?;
+        unsafe {
+            copy_bytes_nt(data, ptr.as_ptr());
+        }
+        Ok(NonNull::slice_from_raw_parts(ptr, data.len()))
+    }
+
     /// Allocates uninitialized memory for a `[T]` of length `len` and fills each element
     /// with the result of `f(elem_idx)`.
     ///
@@ -186,9 +393,7 @@ pub trait AllocExt: Alloc {
         len: usize,
         f: F,
     ) -> Result<NonNull<[T]>, AllocError> {
-        match self.alloc(
-            layout_or_sz_align::<T>(len).map_err(|(sz, aln)| AllocError::LayoutError(sz, aln))?,
-        ) {
+        match self.alloc(array_layout::<T>(len)?) {
             Ok(ptr) => Ok(unsafe {
                 let mut guard = SliceAllocGuard::new(ptr.cast(), self, len);
                 for i in 0..len {
@@ -200,6 +405,325 @@ pub trait AllocExt: Alloc {
         }
     }
 
+    /// Allocates uninitialized memory for a `[T]` of length `len` and fills each element with a
+    /// clone of `template`, customized by `f(&mut elem, elem_idx)`.
+    ///
+    /// This suits elements which mostly share their contents, where building each from scratch
+    /// with [`alloc_slice_with`](AllocExt::alloc_slice_with) would repeat the shared work. If a
+    /// clone or `f` panics, the elements written so far are dropped and the memory is freed.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    /// - [`AllocError::LayoutError`] if the computed layout is invalid.
+    #[track_caller]
+    #[inline]
+    fn alloc_slice_from_template<T: Clone, F: FnMut(&mut T, usize)>(
+        &self,
+        len: usize,
+        template: &T,
+        mut f: F,
+    ) -> Result<NonNull<[T]>, AllocError> {
+        let ptr = self.alloc(array_layout::<T>(len)?)?;
+        Ok(unsafe {
+            let mut guard = SliceAllocGuard::new(ptr.cast(), self, len);
+            for i in 0..len {
+                // a panic in `f` drops this copy, then the guard drops the written prefix
+                let mut elem = template.clone();
+                f(&mut elem, i);
+                guard.init_unchecked(elem);
+            }
+            guard.release()
+        })
+    }
+
+    /// Allocates a `[T]` of length `len` with every element a clone of `value`, like
+    /// `vec![value; len]`.
+    ///
+    /// `value` itself is moved into the last element, so only `len - 1` clones are made. If a
+    /// clone panics, the elements written so far are dropped and the memory is freed. For `Copy`
+    /// types, [`alloc_repeat_copy`](AllocExt::alloc_repeat_copy) is faster.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    /// - [`AllocError::LayoutError`] if the computed layout is invalid.
+    /// - [`AllocError::ZeroSizedLayout`] if `len` is zero or `T` is zero-sized.
+    #[track_caller]
+    fn alloc_repeat<T: Clone>(&self, value: T, len: usize) -> Result<NonNull<[T]>, AllocError> {
+        let layout = array_layout::<T>(len)?;
+        // checked here, as the last element is written unconditionally
+        if layout.size() == 0 {
+            return Err(AllocError::ZeroSizedLayout(NonNull::<T>::dangling().cast()));
+        }
+        let ptr = self.alloc(layout)?;
+        Ok(unsafe {
+            let mut guard = SliceAllocGuard::new(ptr.cast(), self, len);
+            for _ in 1..len {
+                guard.init_unchecked(value.clone());
+            }
+            guard.init_unchecked(value);
+            guard.release()
+        })
+    }
+
+    /// Allocates a `[T]` of length `len` with every element `value`, filled by doubling copies.
+    ///
+    /// The first element is written, then the filled prefix is copied after itself, doubling it
+    /// each time until the slice is full. This takes `log2(len)` bulk copies instead of `len`
+    /// writes, which is much faster for long slices.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    /// - [`AllocError::LayoutError`] if the computed layout is invalid.
+    /// - [`AllocError::ZeroSizedLayout`] if `len` is zero or `T` is zero-sized.
+    #[track_caller]
+    fn alloc_repeat_copy<T: Copy>(&self, value: T, len: usize) -> Result<NonNull<[T]>, AllocError> {
+        let layout = array_layout::<T>(len)?;
+        // checked here, as the first element is written unconditionally
+        if layout.size() == 0 {
+            return Err(AllocError::ZeroSizedLayout(NonNull::<T>::dangling().cast()));
+        }
+        let ptr = self.alloc(layout)?.cast::<T>();
+        unsafe {
+            ptr.write(value);
+            let mut filled = 1;
+            while filled < len {
+                let n = filled.min(len - filled);
+                ptr.copy_to_nonoverlapping(ptr.add(filled), n);
+                filled += n;
+            }
+        }
+        Ok(NonNull::slice_from_raw_parts(ptr, len))
+    }
+
+    /// Allocates uninitialized memory for a `[T]` of length `len` and fills it with the results
+    /// of `f(elem_idx)`, partitioned in a single pass: elements for which `f` returns `true` are
+    /// written from the front, and the rest from the back.
+    ///
+    /// Returns the slice along with the partition point, the number of `true` elements. Those
+    /// keep the order they were produced in, while the `false` elements after the partition
+    /// point end up in reverse order. If `f` panics, the elements written at both ends are
+    /// dropped and the memory is freed.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    /// - [`AllocError::LayoutError`] if the computed layout is invalid.
+    #[track_caller]
+    fn alloc_slice_partitioned<T, F: FnMut(usize) -> (bool, T)>(
+        &self,
+        len: usize,
+        mut f: F,
+    ) -> Result<(NonNull<[T]>, usize), AllocError> {
+        let ptr = self.alloc(array_layout::<T>(len)?)?.cast::<T>();
+        let mut guard = PartitionGuard {
+            ptr,
+            alloc: self,
+            front: 0,
+            back: len,
+            full: len,
+        };
+        for i in 0..len {
+            let (front, elem) = f(i);
+            // `front < back` until the loop ends, so both writes are in bounds
+            unsafe {
+                if front {
+                    ptr.add(guard.front).write(elem);
+                    guard.front += 1;
+                } else {
+                    guard.back -= 1;
+                    ptr.add(guard.back).write(elem);
+                }
+            }
+        }
+        let mid = guard.front;
+        forget(guard);
+        Ok((NonNull::slice_from_raw_parts(ptr, len), mid))
+    }
+
+    /// Converts an array of structs into a struct of arrays, allocating one `[X]` and one `[Y]`
+    /// of `src.len()` elements, filled with `fx` and `fy` applied to each element of `src`.
+    ///
+    /// If allocating either array fails, or a projection panics, everything allocated so far is
+    /// dropped and freed.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    /// - [`AllocError::LayoutError`] if a computed layout is invalid.
+    #[track_caller]
+    #[allow(clippy::type_complexity)]
+    fn alloc_soa_2<S, X, Y, FX: FnMut(&S) -> X, FY: FnMut(&S) -> Y>(
+        &self,
+        src: &[S],
+        fx: FX,
+        fy: FY,
+    ) -> Result<(NonNull<[X]>, NonNull<[Y]>), AllocError> {
+        let xs = project_into(self, src, fx)?;
+        let ys = project_into(self, src, fy)?;
+        Ok((xs.release(), ys.release()))
+    }
+
+    /// Like [`alloc_soa_2`](AllocExt::alloc_soa_2), but splits each element of `src` into three
+    /// arrays.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    /// - [`AllocError::LayoutError`] if a computed layout is invalid.
+    #[track_caller]
+    #[allow(clippy::type_complexity)]
+    fn alloc_soa_3<S, X, Y, Z, FX, FY, FZ>(
+        &self,
+        src: &[S],
+        fx: FX,
+        fy: FY,
+        fz: FZ,
+    ) -> Result<(NonNull<[X]>, NonNull<[Y]>, NonNull<[Z]>), AllocError>
+    where
+        FX: FnMut(&S) -> X,
+        FY: FnMut(&S) -> Y,
+        FZ: FnMut(&S) -> Z,
+    {
+        let xs = project_into(self, src, fx)?;
+        let ys = project_into(self, src, fy)?;
+        let zs = project_into(self, src, fz)?;
+        Ok((xs.release(), ys.release(), zs.release()))
+    }
+
+    /// Allocates `header` followed by space for `tail_cap` elements of `T` in a single block.
+    ///
+    /// The returned [`HeaderTail`] starts with an empty tail, and grows or shrinks only the tail
+    /// afterward; the header always stays at the start of the block.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    /// - [`AllocError::LayoutError`] if the computed layout is invalid.
+    /// - [`AllocError::ZeroSizedLayout`] if the header and tail together take no space.
+    #[track_caller]
+    #[inline]
+    fn alloc_header_tail<H, T>(
+        &self,
+        header: H,
+        tail_cap: usize,
+    ) -> Result<HeaderTail<'_, H, T, Self>, AllocError> {
+        let (layout, offset) = header_tail_layout::<H, T>(tail_cap)?;
+        let ptr = self.alloc(layout)?;
+        unsafe {
+            ptr.cast::<H>().write(header);
+        }
+        Ok(HeaderTail {
+            ptr,
+            offset,
+            len: 0,
+            cap: tail_cap,
+            alloc: self,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Allocates space for up to `cap` elements and fills it with the results of `f(elem_idx)`,
+    /// stopping early at the first `None`. The allocation is then shrunk to fit the elements
+    /// produced, so the returned slice can be deallocated like any other.
+    ///
+    /// If `f` panics, the elements produced so far are dropped and the memory is deallocated.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation or the final shrink fails. The produced
+    ///   elements are dropped in the latter case.
+    /// - [`AllocError::LayoutError`] if the computed layout is invalid.
+    /// - [`AllocError::ZeroSizedLayout`] if `cap` is zero or `f` produced no elements, as the
+    ///   result would be an empty slice.
+    #[track_caller]
+    fn alloc_slice_until<T, F: FnMut(usize) -> Option<T>>(
+        &self,
+        cap: usize,
+        mut f: F,
+    ) -> Result<NonNull<[T]>, AllocError> {
+        let full = array_layout::<T>(cap)?;
+        let mut guard = SliceAllocGuard::new(self.alloc(full)?.cast::<T>(), self, cap);
+        for i in 0..cap {
+            match f(i) {
+                Some(elem) => unsafe { guard.init_unchecked(elem) },
+                None => break,
+            }
+        }
+        let slice = guard.release();
+        let len = slice.len();
+        if len == cap {
+            return Ok(slice);
+        }
+        // `len < cap`, so this layout is valid too
+        let fit =
+            unsafe { Layout::from_size_align_unchecked(size_of::<T>() * len, align_of::<T>()) };
+        if len == 0 {
+            unsafe { self.dealloc(slice.cast(), full) };
+            return Err(AllocError::ZeroSizedLayout(NonNull::<T>::dangling().cast()));
+        }
+        match unsafe { self.shrink(slice.cast(), full, fit) } {
+            Ok(ptr) => Ok(NonNull::slice_from_raw_parts(ptr.cast(), len)),
+            Err(e) => {
+                unsafe {
+                    slice.drop_in_place();
+                    self.dealloc(slice.cast(), full);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Like [`alloc_write`](AllocExt::alloc_write), but converts the error into the caller's
+    /// error type.
+    ///
+    /// `E` can't be inferred through `?`, so it should be named, for example with
+    /// `alloc.alloc_write_or::<_, MyError>(data)?`, or inferred from the enclosing function's
+    /// return type when the result is returned directly.
+    ///
+    /// # Errors
+    ///
+    /// - `E::from(`[`AllocError::AllocFailed`]`)` if allocation fails.
+    #[track_caller]
+    #[inline]
+    fn alloc_write_or<T, E: From<AllocError>>(&self, data: T) -> Result<NonNull<T>, E> {
+        self.alloc_write(data).map_err(E::from)
+    }
+
+    /// Like [`alloc_clone_slice_to`](AllocExt::alloc_clone_slice_to), but converts the error into
+    /// the caller's error type, as with [`alloc_write_or`](AllocExt::alloc_write_or).
+    ///
+    /// # Errors
+    ///
+    /// - `E::from(`[`AllocError::AllocFailed`]`)` if allocation fails.
+    #[track_caller]
+    #[inline]
+    fn alloc_clone_slice_to_or<T: Clone, E: From<AllocError>>(
+        &self,
+        data: &[T],
+    ) -> Result<NonNull<[T]>, E> {
+        self.alloc_clone_slice_to(data).map_err(E::from)
+    }
+
+    /// Like [`alloc_slice_with`](AllocExt::alloc_slice_with), but converts the error into the
+    /// caller's error type, as with [`alloc_write_or`](AllocExt::alloc_write_or).
+    ///
+    /// # Errors
+    ///
+    /// - `E::from(`[`AllocError::AllocFailed`]`)` if allocation fails.
+    /// - `E::from(`[`AllocError::LayoutError`]`)` if the computed layout is invalid.
+    #[track_caller]
+    #[inline]
+    fn alloc_slice_with_or<T, F: Fn(usize) -> T, E: From<AllocError>>(
+        &self,
+        len: usize,
+        f: F,
+    ) -> Result<NonNull<[T]>, E> {
+        self.alloc_slice_with(len, f).map_err(E::from)
+    }
+
     /// Grows a slice to a new length.
     ///
     /// # Errors
@@ -248,10 +772,8 @@ pub trait AllocExt: Alloc {
         unsafe {
             self.grow(
                 ptr.cast(),
-                layout_or_sz_align::<T>(len)
-                    .map_err(|(sz, aln)| AllocError::LayoutError(sz, aln))?,
-                layout_or_sz_align::<T>(new_len)
-                    .map_err(|(sz, aln)| AllocError::LayoutError(sz, aln))?,
+                array_layout::<T>(len)?,
+                array_layout::<T>(new_len)?,
             )
             .map(NonNull::cast)
         }
@@ -332,6 +854,28 @@ pub trait AllocExt: Alloc {
         self.dealloc_typed(ptr);
     }
 
+    /// Drops the first `init_len` elements of a slice and deallocates the whole slice.
+    ///
+    /// This is for slices which were allocated at their full length but only partially
+    /// initialized, such as when building one fails midway.
+    ///
+    /// # Safety
+    ///
+    /// - `slice_ptr` must point to a block of memory allocated using this allocator with the
+    ///   layout of a `[T]` of length `slice_ptr.len()`.
+    /// - `init_len <= slice_ptr.len()`.
+    /// - The elements `0..init_len` must be initialized and valid for dropping.
+    #[track_caller]
+    #[inline]
+    unsafe fn drop_prefix_and_dealloc_slice<T>(&self, slice_ptr: NonNull<[T]>, init_len: usize) {
+        debug_assert!(
+            init_len <= slice_ptr.len(),
+            "`init_len` exceeds the slice's length"
+        );
+        NonNull::slice_from_raw_parts(slice_ptr.cast::<T>(), init_len).drop_in_place();
+        self.dealloc_n(slice_ptr.cast::<T>(), slice_ptr.len());
+    }
+
     /// Drops `init` elements from a partially initialized slice, then zeroes and deallocates its
     /// memory.
     ///
@@ -366,6 +910,37 @@ pub trait AllocExt: Alloc {
         unsafe { self.alloc_copy_ref_to_unchecked(data) }
     }
 
+    #[cfg(feature = "metadata")]
+    /// Allocates and copies an unsized `T` by reference into a block aligned to at least `align`,
+    /// returning a `NonNull<T>`.
+    ///
+    /// This allows, for example, copying a `[f32]` to a block aligned for SIMD loads. The block
+    /// must be deallocated with the same over-aligned layout: the value's size with `align`.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    /// - [`AllocError::LayoutError`] if `align` isn't a power of two, is less than `T`'s
+    ///   alignment, or the aligned layout is invalid.
+    /// - [`AllocError::ZeroSizedLayout`] if `data` has a size of zero.
+    #[track_caller]
+    #[inline]
+    fn alloc_copy_ref_to_aligned<T: ?Sized + UnsizedCopy>(
+        &self,
+        data: &T,
+        align: usize,
+    ) -> Result<NonNull<T>, AllocError> {
+        let natural = Layout::for_value(data);
+        if align < natural.align() {
+            return Err(AllocError::LayoutError(natural.size(), align));
+        }
+        let ptr = self.alloc(validate_layout(natural.size(), align)?)?;
+        unsafe {
+            ptr.copy_from_nonoverlapping(NonNull::from_ref(data).cast(), natural.size());
+        }
+        Ok(NonNull::from_raw_parts(ptr, metadata(&raw const *data)))
+    }
+
     #[cfg(feature = "metadata")]
     /// Allocates and copies an unsized `T` by raw pointer, returning a `NonNull<T>`.
     ///
@@ -439,6 +1014,471 @@ pub trait AllocExt: Alloc {
             Err(e) => Err(e),
         }
     }
+
+    /// Places `data` in the caller's `stack` slot, or in a new allocation if it `escapes`.
+    ///
+    /// The returned [`Placed`] borrows `stack` either way, so a value placed on the stack can't
+    /// outlive the frame owning the slot; pass `escapes = true` for a value which must, and move
+    /// it out of the heap with [`Placed::into_heap`]. Anything left in the slot is dropped along
+    /// with the `Placed`, but the slot itself is never dropped, so it may be reused afterward.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if `escapes` is `true` and allocation fails.
+    #[track_caller]
+    #[inline]
+    fn place<'s, T>(
+        &'s self,
+        stack: &'s mut MaybeUninit<T>,
+        data: T,
+        escapes: bool,
+    ) -> Result<Placed<'s, T, Self>, AllocError> {
+        if escapes {
+            Ok(Placed {
+                slot: Slot::Heap(self.alloc_write(data)?, self),
+            })
+        } else {
+            Ok(Placed {
+                slot: Slot::Stack(stack.write(data)),
+            })
+        }
+    }
+
+    /// Begins a [`Transaction`] over this allocator, which frees everything allocated through it
+    /// unless committed.
+    #[cfg(feature = "adapters")]
+    #[must_use]
+    #[inline]
+    fn begin(&self) -> Transaction<'_, Self> {
+        Transaction::new(self)
+    }
 }
 
 impl<A: Alloc> AllocExt for A {}
+
+/// A value placed by [`AllocExt::place`], either in a borrowed stack slot or on the heap.
+///
+/// Dropping a `Placed` drops the value, and deallocates it if it was heap-allocated.
+pub struct Placed<'s, T, A: Alloc + ?Sized> {
+    slot: Slot<'s, T, A>,
+}
+
+/// Where a [`Placed`] value lives.
+enum Slot<'s, T, A: Alloc + ?Sized> {
+    /// The value lives in the caller's stack slot.
+    Stack(&'s mut T),
+    /// The value was allocated using the allocator.
+    Heap(NonNull<T>, &'s A),
+}
+
+impl<'s, T, A: Alloc + ?Sized> Placed<'s, T, A> {
+    /// Returns `true` if the value was heap-allocated.
+    #[must_use]
+    #[inline]
+    pub const fn is_heap(&self) -> bool {
+        matches!(self.slot, Slot::Heap(..))
+    }
+
+    /// Gets the heap pointer to the value, giving up ownership of it.
+    ///
+    /// The pointer is an allocation of `T` in the allocator which placed it, and must be freed
+    /// there.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err(self)` if the value is on the stack.
+    #[inline]
+    pub fn into_heap(self) -> Result<NonNull<T>, Placed<'s, T, A>> {
+        match self.slot {
+            Slot::Stack(_) => Err(self),
+            Slot::Heap(ptr, _) => {
+                forget(self);
+                Ok(ptr)
+            }
+        }
+    }
+}
+
+impl<T, A: Alloc + ?Sized> Deref for Placed<'_, T, A> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        match &self.slot {
+            Slot::Stack(val) => val,
+            Slot::Heap(ptr, _) => unsafe { ptr.as_ref() },
+        }
+    }
+}
+
+impl<T, A: Alloc + ?Sized> DerefMut for Placed<'_, T, A> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        match &mut self.slot {
+            Slot::Stack(val) => val,
+            Slot::Heap(ptr, _) => unsafe { ptr.as_mut() },
+        }
+    }
+}
+
+impl<T: Debug, A: Alloc + ?Sized> Debug for Placed<'_, T, A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let variant = if self.is_heap() { "Heap" } else { "Stack" };
+        f.debug_tuple(variant).field(&**self).finish()
+    }
+}
+
+impl<T, A: Alloc + ?Sized> Drop for Placed<'_, T, A> {
+    fn drop(&mut self) {
+        match &mut self.slot {
+            Slot::Stack(val) => unsafe { ptr::drop_in_place(&raw mut **val) },
+            Slot::Heap(ptr, alloc) => unsafe { alloc.drop_and_dealloc(*ptr) },
+        }
+    }
+}
+
+/// Copies `src` to `dst`, streaming past the cache where possible.
+///
+/// # Safety
+///
+/// `dst` must be valid for writes of `src.len()` bytes and not overlap `src`.
+#[cfg(target_arch = "x86_64")]
+// the loads are unaligned, and the stores are only made past the aligned head
+#[allow(clippy::cast_ptr_alignment)]
+unsafe fn copy_bytes_nt(src: &[u8], dst: *mut u8) {
+    use core::arch::x86_64::{__m128i, _mm_loadu_si128, _mm_sfence, _mm_stream_si128};
+
+    // below a few pages, the fence costs more than the cache pollution
+    const MIN_NT_LEN: usize = 16 * 1024;
+
+    if src.len() < MIN_NT_LEN {
+        unsafe { ptr::copy_nonoverlapping(src.as_ptr(), dst, src.len()) };
+        return;
+    }
+    // streaming stores need an aligned destination, so copy the unaligned head normally
+    let head = dst.align_offset(16);
+    let chunks = (src.len() - head) / 16;
+    unsafe {
+        ptr::copy_nonoverlapping(src.as_ptr(), dst, head);
+        for i in 0..chunks {
+            let off = head + i * 16;
+            let chunk = _mm_loadu_si128(src.as_ptr().add(off).cast::<__m128i>());
+            _mm_stream_si128(dst.add(off).cast::<__m128i>(), chunk);
+        }
+        let done = head + chunks * 16;
+        ptr::copy_nonoverlapping(src.as_ptr().add(done), dst.add(done), src.len() - done);
+        // order the streamed stores before any later ones
+        _mm_sfence();
+    }
+}
+
+/// Copies `src` to `dst`.
+///
+/// # Safety
+///
+/// `dst` must be valid for writes of `src.len()` bytes and not overlap `src`.
+#[cfg(not(target_arch = "x86_64"))]
+unsafe fn copy_bytes_nt(src: &[u8], dst: *mut u8) {
+    unsafe { ptr::copy_nonoverlapping(src.as_ptr(), dst, src.len()) };
+}
+
+/// A list of values to be dropped together, filled by [`AllocExt::alloc_write_registered`].
+///
+/// [`run_all`](DropRegistry::run_all) drops every registered value, most recently registered
+/// first, and empties the registry; dropping the registry runs whatever is left. Values are only
+/// dropped, never deallocated, so this works the same over bump allocators which free everything
+/// at once and allocators whose memory the caller frees afterward.
+///
+/// The registry is tied to a lifetime `'a` for which it borrows the allocators its values live in
+/// and anything the values borrow, so it can't run after either is gone:
+///
+/// ```compile_fail,E0597
+/// # use core::alloc::Layout;
+/// # use memapi::{AllocExt, DropRegistry, arena::AtomicBump};
+/// let reg = DropRegistry::new();
+/// {
+///     let bump = AtomicBump::new(Layout::new::<[usize; 64]>()).unwrap();
+///     bump.alloc_write_registered(String::from("freed with the bump"), &reg).unwrap();
+/// }
+/// drop(reg);
+/// ```
+///
+/// ```compile_fail,E0597
+/// # use memapi::{AllocExt, DefaultAlloc, DropRegistry};
+/// let alloc = DefaultAlloc;
+/// let reg = DropRegistry::new();
+/// {
+///     let s = String::from("borrowed");
+///     alloc.alloc_write_registered(vec![s.as_str()], &reg).unwrap();
+/// }
+/// drop(reg);
+/// ```
+#[derive(Default)]
+pub struct DropRegistry<'a> {
+    entries: RefCell<Vec<DropEntry>>,
+    // invariant, so a registry can't be coerced to a shorter lifetime to accept shorter borrows
+    _marker: PhantomData<fn(&'a ()) -> &'a ()>,
+}
+
+/// A registered value's address and the function dropping it.
+type DropEntry = (NonNull<u8>, unsafe fn(*mut u8));
+
+impl<'a> DropRegistry<'a> {
+    /// Creates an empty registry.
+    #[must_use]
+    #[inline]
+    pub const fn new() -> DropRegistry<'a> {
+        DropRegistry {
+            entries: RefCell::new(Vec::new()),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the number of values waiting to be dropped.
+    #[must_use]
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    /// Returns `true` if no values are waiting to be dropped.
+    #[must_use]
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.borrow().is_empty()
+    }
+
+    /// Drops every registered value in reverse order of registration.
+    ///
+    /// If a value's drop panics, the values registered before it are left in the registry, to be
+    /// dropped by the next run or when the registry is dropped.
+    pub fn run_all(&self) {
+        // pop one at a time, as a drop may register more values or panic
+        let next = || self.entries.borrow_mut().pop();
+        while let Some((ptr, glue)) = next() {
+            unsafe { glue(ptr.as_ptr()) };
+        }
+    }
+
+    fn register<T: 'a>(&self, ptr: NonNull<T>) {
+        if needs_drop::<T>() {
+            self.entries.borrow_mut().push((ptr.cast(), drop_glue::<T>));
+        }
+    }
+}
+
+impl Debug for DropRegistry<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DropRegistry")
+            .field("pending", &self.len())
+            .finish()
+    }
+}
+
+impl Drop for DropRegistry<'_> {
+    fn drop(&mut self) {
+        self.run_all();
+    }
+}
+
+/// Drops the `T` at `ptr` in place.
+unsafe fn drop_glue<T>(ptr: *mut u8) {
+    unsafe { ptr::drop_in_place(ptr.cast::<T>()) }
+}
+
+/// Drops the elements written at either end of a slice being filled by
+/// [`AllocExt::alloc_slice_partitioned`] and deallocates it, unless forgotten.
+struct PartitionGuard<'a, T, A: Alloc + ?Sized> {
+    ptr: NonNull<T>,
+    alloc: &'a A,
+    front: usize,
+    back: usize,
+    full: usize,
+}
+
+impl<T, A: Alloc + ?Sized> Drop for PartitionGuard<'_, T, A> {
+    fn drop(&mut self) {
+        unsafe {
+            NonNull::slice_from_raw_parts(self.ptr, self.front).drop_in_place();
+            NonNull::slice_from_raw_parts(self.ptr.add(self.back), self.full - self.back)
+                .drop_in_place();
+            self.alloc.dealloc_n(self.ptr, self.full);
+        }
+    }
+}
+
+/// Allocates a `[X]` of `src.len()` elements filled with `f` applied to each element of `src`,
+/// still guarded so it's freed if a later step fails.
+#[track_caller]
+fn project_into<'a, S, X, A: Alloc + ?Sized, F: FnMut(&S) -> X>(
+    alloc: &'a A,
+    src: &[S],
+    mut f: F,
+) -> Result<SliceAllocGuard<'a, X, A>, AllocError> {
+    let ptr = alloc.alloc(array_layout::<X>(src.len())?)?;
+    let mut guard = SliceAllocGuard::new(ptr.cast(), alloc, src.len());
+    for elem in src {
+        unsafe { guard.init_unchecked(f(elem)) };
+    }
+    Ok(guard)
+}
+
+/// Gets the layout of an `H` followed by `cap` elements of `T`, and the offset of the elements.
+const fn header_tail_layout<H, T>(cap: usize) -> Result<(Layout, usize), AllocError> {
+    let tail = match array_layout::<T>(cap) {
+        Ok(tail) => tail,
+        Err(e) => return Err(e),
+    };
+    let (builder, offset) = LayoutBuilder::new().field::<H>().push(tail);
+    match builder.build() {
+        Ok(layout) => Ok((layout, offset)),
+        Err(e) => Err(e),
+    }
+}
+
+/// A fixed header and a growable tail of `T`s sharing one allocation, created by
+/// [`AllocExt::alloc_header_tail`].
+///
+/// The tail is padded to `T`'s alignment after the header, and resizing it reallocates the whole
+/// block with the header bytes carried along. The block may move when resized, so pointers into it
+/// are invalidated. Dropping a `HeaderTail` drops the header and the tail's elements, then
+/// deallocates the block.
+pub struct HeaderTail<'a, H, T, A: Alloc + ?Sized> {
+    ptr: NonNull<u8>,
+    offset: usize,
+    len: usize,
+    cap: usize,
+    alloc: &'a A,
+    _marker: PhantomData<(H, T)>,
+}
+
+impl<H, T, A: Alloc + ?Sized> HeaderTail<'_, H, T, A> {
+    /// Returns a reference to the header.
+    #[must_use]
+    #[inline]
+    pub const fn header(&self) -> &H {
+        unsafe { self.ptr.cast::<H>().as_ref() }
+    }
+
+    /// Returns a mutable reference to the header.
+    #[must_use]
+    #[inline]
+    pub const fn header_mut(&mut self) -> &mut H {
+        unsafe { self.ptr.cast::<H>().as_mut() }
+    }
+
+    /// Returns the initialized elements of the tail.
+    #[must_use]
+    #[inline]
+    pub const fn tail(&self) -> &[T] {
+        unsafe { core::slice::from_raw_parts(self.tail_ptr().as_ptr(), self.len) }
+    }
+
+    /// Returns the initialized elements of the tail mutably.
+    #[must_use]
+    #[inline]
+    pub const fn tail_mut(&mut self) -> &mut [T] {
+        unsafe { core::slice::from_raw_parts_mut(self.tail_ptr().as_ptr(), self.len) }
+    }
+
+    /// Returns the number of elements in the tail.
+    #[must_use]
+    #[inline]
+    pub const fn tail_len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the number of elements the tail has space for.
+    #[must_use]
+    #[inline]
+    pub const fn tail_capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// Appends `value` to the tail, doubling the tail's capacity first if it's full.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error along with `value` if growing fails:
+    /// - [`AllocError::AllocFailed`] if reallocation fails.
+    /// - [`AllocError::LayoutError`] if the computed layout is invalid.
+    #[track_caller]
+    pub fn push_tail(&mut self, value: T) -> Result<(), (AllocError, T)> {
+        if self.len == self.cap {
+            let new_cap = self.cap.saturating_mul(2).max(4);
+            if let Err(e) = self.realloc_tail(new_cap) {
+                return Err((e, value));
+            }
+        }
+        unsafe {
+            self.tail_ptr().add(self.len).write(value);
+        }
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Resizes the tail to space for `new_cap` elements, dropping any elements past it.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if reallocation fails.
+    /// - [`AllocError::LayoutError`] if the computed layout is invalid.
+    /// - [`AllocError::ZeroSizedLayout`] if the header and resized tail together take no space.
+    ///
+    /// The tail is unchanged on error, except that elements past `new_cap` have been dropped.
+    #[track_caller]
+    pub fn realloc_tail(&mut self, new_cap: usize) -> Result<(), AllocError> {
+        let (new_layout, _) = header_tail_layout::<H, T>(new_cap)?;
+        if new_cap < self.len {
+            let excess = self.len - new_cap;
+            // drop first, so a panicking drop leaves the tail consistent
+            self.len = new_cap;
+            unsafe {
+                ptr::drop_in_place(ptr::slice_from_raw_parts_mut(
+                    self.tail_ptr().add(new_cap).as_ptr(),
+                    excess,
+                ));
+            }
+        }
+        self.ptr = unsafe {
+            self.alloc
+                .realloc(self.ptr, self.block_layout(), new_layout)?
+        };
+        self.cap = new_cap;
+        Ok(())
+    }
+
+    #[inline]
+    const fn tail_ptr(&self) -> NonNull<T> {
+        unsafe { self.ptr.add(self.offset).cast() }
+    }
+
+    #[inline]
+    fn block_layout(&self) -> Layout {
+        // this layout was computed successfully when the block was last allocated
+        unsafe { header_tail_layout::<H, T>(self.cap).unwrap_unchecked().0 }
+    }
+}
+
+impl<H: Debug, T: Debug, A: Alloc + ?Sized> Debug for HeaderTail<'_, H, T, A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HeaderTail")
+            .field("header", self.header())
+            .field("tail", &self.tail())
+            .field("capacity", &self.cap)
+            .finish()
+    }
+}
+
+impl<H, T, A: Alloc + ?Sized> Drop for HeaderTail<'_, H, T, A> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.ptr.cast::<H>().as_ptr());
+            ptr::drop_in_place(ptr::slice_from_raw_parts_mut(
+                self.tail_ptr().as_ptr(),
+                self.len,
+            ));
+            self.alloc.dealloc(self.ptr, self.block_layout());
+        }
+    }
+}
@@ -0,0 +1,938 @@
+use crate::{
+    Alloc, AllocError, DefaultAlloc,
+    helpers::{LayoutBuilder, array_layout, dangling_nonnull},
+    owned::OwnedBuf,
+};
+use alloc::vec::Vec;
+use core::{
+    alloc::Layout,
+    cell::RefCell,
+    error::Error,
+    fmt::{self, Debug, Display, Formatter},
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    ptr::NonNull,
+    sync::atomic::{AtomicUsize, Ordering::Relaxed},
+};
+
+/// A typed handle to a value stored in a [`GenArena`].
+///
+/// A handle stays valid until the arena which issued it is [`reset`](GenArena::reset), after
+/// which the arena will refuse to resolve it. Handles are only meaningful to the arena which
+/// issued them.
+pub struct Handle<T> {
+    /// The index of the value in the arena.
+    index: usize,
+    /// The generation of the arena when the value was inserted.
+    generation: usize,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    /// Returns the index of the value this handle refers to.
+    #[inline]
+    #[must_use]
+    pub const fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the generation this handle was issued in.
+    #[inline]
+    #[must_use]
+    pub const fn generation(&self) -> usize {
+        self.generation
+    }
+}
+
+// manual impls so `T` doesn't need to implement these
+impl<T> Clone for Handle<T> {
+    #[inline]
+    fn clone(&self) -> Handle<T> {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    #[inline]
+    fn eq(&self, other: &Handle<T>) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> Debug for Handle<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Handle")
+            .field("index", &self.index)
+            .field("generation", &self.generation)
+            .finish()
+    }
+}
+
+/// An arena of `T` allocated using `A`, which hands out generation-checked [`Handle`]s instead
+/// of pointers.
+///
+/// [`reset`](GenArena::reset) drops every value and bumps the arena's generation, so handles
+/// issued before the reset resolve to `None` instead of dangling.
+pub struct GenArena<T, A: Alloc = DefaultAlloc> {
+    /// The stored values.
+    values: OwnedBuf<T, A>,
+    /// The current generation.
+    generation: usize,
+}
+
+impl<T> GenArena<T> {
+    /// Creates a new, unallocated arena of `T` in the default allocator.
+    #[must_use]
+    #[inline]
+    pub const fn new() -> GenArena<T> {
+        GenArena::new_in(DefaultAlloc)
+    }
+}
+
+impl<T> Default for GenArena<T> {
+    #[inline]
+    fn default() -> GenArena<T> {
+        GenArena::new()
+    }
+}
+
+impl<T, A: Alloc> GenArena<T, A> {
+    /// Creates a new, unallocated arena of `T`, set to use the given allocator for future
+    /// allocations.
+    #[must_use]
+    #[inline]
+    pub const fn new_in(alloc: A) -> GenArena<T, A> {
+        GenArena {
+            values: OwnedBuf::new_unallocated_in(alloc),
+            generation: 0,
+        }
+    }
+
+    /// Creates a new arena of `T` with space for `cap` values, in the given allocator.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    /// - [`AllocError::LayoutError`] if the computed layout is invalid.
+    #[track_caller]
+    #[inline]
+    pub fn with_capacity_in(cap: usize, alloc: A) -> Result<GenArena<T, A>, AllocError> {
+        Ok(GenArena {
+            values: OwnedBuf::new_in(cap, alloc)?,
+            generation: 0,
+        })
+    }
+
+    /// Moves `val` into the arena and returns a handle to it.
+    ///
+    /// # Errors
+    ///
+    /// If growth was necessary:
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    /// - [`AllocError::LayoutError`] if the computed layout is invalid.
+    #[track_caller]
+    #[inline]
+    pub fn insert(&mut self, val: T) -> Result<Handle<T>, AllocError> {
+        let index = self.values.initialized();
+        if index == self.values.size() {
+            // grow geometrically so repeated inserts don't reallocate every time
+            self.values.expand_to_fit((index * 2).max(4))?;
+        }
+        unsafe {
+            self.values.init_next_unchecked(val);
+        }
+        Ok(Handle {
+            index,
+            generation: self.generation,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Returns `true` if `handle` was issued in the current generation.
+    #[inline]
+    #[must_use]
+    pub const fn contains(&self, handle: Handle<T>) -> bool {
+        handle.generation == self.generation && handle.index < self.values.initialized()
+    }
+
+    /// Gets a reference to the value `handle` refers to, or `None` if the arena has been reset
+    /// since the handle was issued.
+    #[inline]
+    #[must_use]
+    pub const fn get(&self, handle: Handle<T>) -> Option<&T> {
+        if self.contains(handle) {
+            Some(unsafe { self.values.get_unchecked(handle.index) })
+        } else {
+            None
+        }
+    }
+
+    /// Gets a mutable reference to the value `handle` refers to, or `None` if the arena has been
+    /// reset since the handle was issued.
+    #[inline]
+    #[must_use]
+    pub const fn get_mut(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        if self.contains(handle) {
+            Some(unsafe { self.values.get_mut_unchecked(handle.index) })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the number of values in the arena.
+    #[inline]
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.values.initialized()
+    }
+
+    /// Returns `true` if the arena contains no values.
+    #[inline]
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.values.initialized() == 0
+    }
+
+    /// Returns the number of values the arena can hold without reallocating.
+    #[inline]
+    #[must_use]
+    pub const fn capacity(&self) -> usize {
+        self.values.size()
+    }
+
+    /// Returns the arena's current generation.
+    #[inline]
+    #[must_use]
+    pub const fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// Drops every value in the arena and invalidates all outstanding handles, keeping the
+    /// allocated space for reuse.
+    ///
+    /// The generation wraps on overflow, so a handle kept across `usize::MAX` resets may
+    /// resolve again.
+    #[track_caller]
+    #[inline]
+    pub fn reset(&mut self) {
+        let init = self.values.init_buf_ptr();
+        // bump first so a panicking destructor still leaves old handles invalid
+        self.generation = self.generation.wrapping_add(1);
+        unsafe {
+            self.values.set_initialized(0);
+            init.drop_in_place();
+        }
+    }
+}
+
+impl<T, A: Alloc> Drop for GenArena<T, A> {
+    fn drop(&mut self) {
+        // `OwnedBuf::reset` only frees the buffer, the values have to be dropped separately
+        unsafe {
+            let init = self.values.init_buf_ptr();
+            self.values.set_initialized(0);
+            init.drop_in_place();
+        }
+        self.values.reset();
+    }
+}
+
+impl<T, A: Alloc> Debug for GenArena<T, A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GenArena")
+            .field("len", &self.values.initialized())
+            .field("capacity", &self.values.size())
+            .field("generation", &self.generation)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A fixed-size bump allocator which can be shared between threads without locking.
+///
+/// The cursor into the region is advanced with a compare-and-swap loop, so concurrent
+/// allocations never block each other; a thread which loses a race just recomputes its aligned
+/// start from the new cursor and retries. Deallocation is a no-op, and the whole region is
+/// reclaimed at once with [`reset`](AtomicBump::reset).
+pub struct AtomicBump<A: Alloc = DefaultAlloc> {
+    region: NonNull<u8>,
+    layout: Layout,
+    /// Offset of the first free byte in the region.
+    cursor: AtomicUsize,
+    alloc: A,
+}
+
+// the region is only ever handed out in disjoint pieces
+unsafe impl<A: Alloc + Send> Send for AtomicBump<A> {}
+unsafe impl<A: Alloc + Sync> Sync for AtomicBump<A> {}
+
+impl AtomicBump {
+    /// Creates a new bump allocator over a region fitting `layout`, in the default allocator.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    /// - [`AllocError::ZeroSizedLayout`] if `layout` has a size of zero.
+    #[track_caller]
+    #[inline]
+    pub fn new(layout: Layout) -> Result<AtomicBump, AllocError> {
+        AtomicBump::new_in(layout, DefaultAlloc)
+    }
+}
+
+impl<A: Alloc> AtomicBump<A> {
+    /// Creates a new bump allocator over a region fitting `layout`, allocated using `alloc`.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    /// - [`AllocError::ZeroSizedLayout`] if `layout` has a size of zero.
+    #[track_caller]
+    #[inline]
+    pub fn new_in(layout: Layout, alloc: A) -> Result<AtomicBump<A>, AllocError> {
+        Ok(AtomicBump {
+            region: alloc.alloc(layout)?,
+            layout,
+            cursor: AtomicUsize::new(0),
+            alloc,
+        })
+    }
+
+    /// Returns the size of the region in bytes.
+    #[must_use]
+    #[inline]
+    pub const fn capacity(&self) -> usize {
+        self.layout.size()
+    }
+
+    /// Returns the number of bytes handed out so far, including alignment padding.
+    #[must_use]
+    #[inline]
+    pub fn used(&self) -> usize {
+        self.cursor.load(Relaxed)
+    }
+
+    /// Reclaims the whole region for reuse.
+    ///
+    /// Taking `&mut self` guarantees no other thread is allocating concurrently, but not that
+    /// earlier allocations are dead; the caller must not use any pointer obtained before the
+    /// reset afterward, as its memory will be handed out again.
+    #[inline]
+    pub fn reset(&mut self) {
+        *self.cursor.get_mut() = 0;
+    }
+
+    #[track_caller]
+    fn bump(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 {
+            return Err(AllocError::ZeroSizedLayout(unsafe {
+                dangling_nonnull(layout.align())
+            }));
+        }
+        let base = self.region.addr().get();
+        let mut cur = self.cursor.load(Relaxed);
+        loop {
+            // align the absolute address, the region itself may be less aligned than `layout`
+            let start = (base + cur)
+                .checked_next_multiple_of(layout.align())
+                .map(|addr| addr - base);
+            let end = match start.and_then(|start| start.checked_add(layout.size())) {
+                Some(end) if end <= self.layout.size() => end,
+                _ => return Err(AllocError::AllocFailed(layout)),
+            };
+            // the memory itself isn't published through the cursor, so no ordering is needed
+            match self
+                .cursor
+                .compare_exchange_weak(cur, end, Relaxed, Relaxed)
+            {
+                Ok(_) => {
+                    let ptr = self.region.as_ptr().wrapping_add(end - layout.size());
+                    return Ok(unsafe { NonNull::new_unchecked(ptr) });
+                }
+                Err(actual) => cur = actual,
+            }
+        }
+    }
+}
+
+impl<A: Alloc> Alloc for AtomicBump<A> {
+    #[track_caller]
+    #[inline]
+    fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.bump(layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.alloc_filled(layout, 0)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+        let ptr = self.bump(layout)?;
+        // the region may have been used before a reset, so it always has to be written
+        unsafe {
+            ptr.write_bytes(n, layout.size());
+        }
+        Ok(ptr)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_patterned<F: Fn(usize) -> u8 + Clone>(
+        &self,
+        layout: Layout,
+        pattern: F,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let ptr = self.bump(layout)?;
+        for i in 0..layout.size() {
+            unsafe {
+                ptr.add(i).write(pattern(i));
+            }
+        }
+        Ok(ptr)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, _ptr: NonNull<u8>, _layout: Layout) {}
+
+    /// The region can't grow, so this only checks that `bytes` are still free. Concurrent
+    /// allocations can use up the space again before the caller gets to it.
+    #[track_caller]
+    #[inline]
+    fn reserve(&self, bytes: usize) -> Result<(), AllocError> {
+        if self.capacity() - self.used() >= bytes {
+            Ok(())
+        } else {
+            Err(AllocError::AllocFailed(
+                Layout::from_size_align(bytes, 1).map_err(|_| AllocError::LayoutError(bytes, 1))?,
+            ))
+        }
+    }
+}
+
+impl<A: Alloc> Drop for AtomicBump<A> {
+    fn drop(&mut self) {
+        unsafe {
+            self.alloc.dealloc(self.region, self.layout);
+        }
+    }
+}
+
+impl<A: Alloc> Debug for AtomicBump<A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AtomicBump")
+            .field("used", &self.used())
+            .field("capacity", &self.capacity())
+            .finish_non_exhaustive()
+    }
+}
+
+/// One allocation made through a [`ReplayAlloc`]: its size and alignment, and the offset into the
+/// region it was placed at.
+///
+/// An event displays as its three fields separated by spaces, which
+/// [`parse`](ReplayEvent::parse) reads back, so a log can be saved one event per line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayEvent {
+    /// The requested size.
+    pub size: usize,
+    /// The requested alignment.
+    pub align: usize,
+    /// The offset from the start of the region the allocation was placed at.
+    pub offset: usize,
+}
+
+impl ReplayEvent {
+    /// Parses an event in the format it displays as.
+    #[must_use]
+    pub fn parse(line: &str) -> Option<ReplayEvent> {
+        let mut fields = line.split_whitespace().map(str::parse::<usize>);
+        let event = ReplayEvent {
+            size: fields.next()?.ok()?,
+            align: fields.next()?.ok()?,
+            offset: fields.next()?.ok()?,
+        };
+        fields.next().is_none().then_some(event)
+    }
+}
+
+impl Display for ReplayEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.size, self.align, self.offset)
+    }
+}
+
+/// The first point at which a replayed run's allocations differed from the recording.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Divergence {
+    /// The index of the allocation in the sequence.
+    pub index: usize,
+    /// The recorded allocation, or `None` if the replay made more allocations than were recorded.
+    pub expected: Option<ReplayEvent>,
+    /// The replayed allocation, or `None` if the replay finished before making it.
+    pub actual: Option<ReplayEvent>,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        fn event(f: &mut Formatter<'_>, ev: Option<ReplayEvent>) -> fmt::Result {
+            match ev {
+                Some(ev) => write!(
+                    f,
+                    "{} bytes (align {}) at +{:#x}",
+                    ev.size, ev.align, ev.offset
+                ),
+                None => f.write_str("nothing"),
+            }
+        }
+        write!(f, "allocation #{} diverged: expected ", self.index)?;
+        event(f, self.expected)?;
+        f.write_str(", got ")?;
+        event(f, self.actual)
+    }
+}
+
+impl Error for Divergence {}
+
+enum ReplayMode {
+    Record(Vec<ReplayEvent>),
+    Replay {
+        log: Vec<ReplayEvent>,
+        next: usize,
+        divergence: Option<Divergence>,
+    },
+}
+
+/// A bump allocator which records the sequence of allocations made through it, or checks a run
+/// against a previous recording, for tracking down nondeterminism.
+///
+/// Allocations are bump-allocated from a fixed region and never reused, so the same sequence of
+/// requests always lands at the same offsets into the region. In record mode, each allocation's
+/// [`ReplayEvent`] is appended to the log. In replay mode, the same allocator is given a log, and
+/// each allocation is compared against the next event in it; the first mismatch is kept as a
+/// [`Divergence`], and [`finish`](ReplayAlloc::finish) reports it. Allocation carries on as
+/// normal after a divergence.
+///
+/// A replay must use a region of the same layout as the recording. Requests aligned more strictly
+/// than the region fail, since their placement would depend on the region's address. Failed
+/// allocations aren't logged, and deallocation is a no-op.
+///
+/// The allocator isn't `Sync`, as the order of allocations from several threads would itself be
+/// nondeterministic.
+pub struct ReplayAlloc<A: Alloc = DefaultAlloc> {
+    bump: AtomicBump<A>,
+    mode: RefCell<ReplayMode>,
+}
+
+impl ReplayAlloc {
+    /// Creates a recording allocator over a region fitting `layout`, in the default allocator.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    /// - [`AllocError::ZeroSizedLayout`] if `layout` has a size of zero.
+    #[track_caller]
+    #[inline]
+    pub fn record(layout: Layout) -> Result<ReplayAlloc, AllocError> {
+        ReplayAlloc::record_in(layout, DefaultAlloc)
+    }
+
+    /// Creates an allocator replaying `log` over a region fitting `layout`, in the default
+    /// allocator.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    /// - [`AllocError::ZeroSizedLayout`] if `layout` has a size of zero.
+    #[track_caller]
+    #[inline]
+    pub fn replay(layout: Layout, log: Vec<ReplayEvent>) -> Result<ReplayAlloc, AllocError> {
+        ReplayAlloc::replay_in(layout, log, DefaultAlloc)
+    }
+}
+
+impl<A: Alloc> ReplayAlloc<A> {
+    /// Creates a recording allocator over a region fitting `layout`, allocated using `alloc`.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    /// - [`AllocError::ZeroSizedLayout`] if `layout` has a size of zero.
+    #[track_caller]
+    #[inline]
+    pub fn record_in(layout: Layout, alloc: A) -> Result<ReplayAlloc<A>, AllocError> {
+        Ok(ReplayAlloc {
+            bump: AtomicBump::new_in(layout, alloc)?,
+            mode: RefCell::new(ReplayMode::Record(Vec::new())),
+        })
+    }
+
+    /// Creates an allocator replaying `log` over a region fitting `layout`, allocated using
+    /// `alloc`.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    /// - [`AllocError::ZeroSizedLayout`] if `layout` has a size of zero.
+    #[track_caller]
+    #[inline]
+    pub fn replay_in(
+        layout: Layout,
+        log: Vec<ReplayEvent>,
+        alloc: A,
+    ) -> Result<ReplayAlloc<A>, AllocError> {
+        Ok(ReplayAlloc {
+            bump: AtomicBump::new_in(layout, alloc)?,
+            mode: RefCell::new(ReplayMode::Replay {
+                log,
+                next: 0,
+                divergence: None,
+            }),
+        })
+    }
+
+    /// Returns `true` if the allocator is replaying a log rather than recording one.
+    #[must_use]
+    #[inline]
+    pub fn is_replaying(&self) -> bool {
+        matches!(*self.mode.borrow(), ReplayMode::Replay { .. })
+    }
+
+    /// Returns the events recorded so far, or the log being replayed.
+    #[must_use]
+    pub fn log(&self) -> Vec<ReplayEvent> {
+        match &*self.mode.borrow() {
+            ReplayMode::Record(log) | ReplayMode::Replay { log, .. } => log.clone(),
+        }
+    }
+
+    /// Returns the first divergence from the log so far, if replaying.
+    #[must_use]
+    pub fn divergence(&self) -> Option<Divergence> {
+        match &*self.mode.borrow() {
+            ReplayMode::Record(_) => None,
+            ReplayMode::Replay { divergence, .. } => *divergence,
+        }
+    }
+
+    /// Checks that the replay has made exactly the recorded allocations. Always succeeds when
+    /// recording.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first divergence, or one with no `actual` event if fewer allocations were made
+    /// than were recorded.
+    pub fn finish(&self) -> Result<(), Divergence> {
+        match &*self.mode.borrow() {
+            ReplayMode::Record(_) => Ok(()),
+            ReplayMode::Replay {
+                divergence: Some(d),
+                ..
+            } => Err(*d),
+            ReplayMode::Replay { log, next, .. } => match log.get(*next) {
+                Some(&expected) => Err(Divergence {
+                    index: *next,
+                    expected: Some(expected),
+                    actual: None,
+                }),
+                None => Ok(()),
+            },
+        }
+    }
+
+    #[track_caller]
+    fn place(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.align() > self.bump.layout.align() {
+            return Err(AllocError::AllocFailed(layout));
+        }
+        let ptr = self.bump.bump(layout)?;
+        let actual = ReplayEvent {
+            size: layout.size(),
+            align: layout.align(),
+            offset: ptr.addr().get() - self.bump.region.addr().get(),
+        };
+        match &mut *self.mode.borrow_mut() {
+            ReplayMode::Record(log) => log.push(actual),
+            ReplayMode::Replay {
+                log,
+                next,
+                divergence,
+            } => {
+                let expected = log.get(*next).copied();
+                if divergence.is_none() && expected != Some(actual) {
+                    *divergence = Some(Divergence {
+                        index: *next,
+                        expected,
+                        actual: Some(actual),
+                    });
+                }
+                *next += 1;
+            }
+        }
+        Ok(ptr)
+    }
+}
+
+impl<A: Alloc> Alloc for ReplayAlloc<A> {
+    #[track_caller]
+    #[inline]
+    fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.place(layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.alloc_filled(layout, 0)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+        let ptr = self.place(layout)?;
+        unsafe {
+            ptr.write_bytes(n, layout.size());
+        }
+        Ok(ptr)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_patterned<F: Fn(usize) -> u8 + Clone>(
+        &self,
+        layout: Layout,
+        pattern: F,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let ptr = self.place(layout)?;
+        for i in 0..layout.size() {
+            unsafe {
+                ptr.add(i).write(pattern(i));
+            }
+        }
+        Ok(ptr)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, _ptr: NonNull<u8>, _layout: Layout) {}
+
+    #[track_caller]
+    #[inline]
+    fn reserve(&self, bytes: usize) -> Result<(), AllocError> {
+        self.bump.reserve(bytes)
+    }
+}
+
+impl<A: Alloc> Debug for ReplayAlloc<A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplayAlloc")
+            .field("replaying", &self.is_replaying())
+            .field("used", &self.bump.used())
+            .field("capacity", &self.bump.capacity())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A region of a [`Partition`] holding `len` elements of `T`, returned by [`PartitionBuilder::add`].
+pub struct Region<T> {
+    offset: usize,
+    len: usize,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Region<T> {
+    /// Returns the region's offset from the start of the partition's block.
+    #[must_use]
+    #[inline]
+    pub const fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the number of elements in the region.
+    #[must_use]
+    #[inline]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the region holds no elements.
+    #[must_use]
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+// manual impls so `T` doesn't need to implement these
+impl<T> Clone for Region<T> {
+    #[inline]
+    fn clone(&self) -> Region<T> {
+        *self
+    }
+}
+
+impl<T> Copy for Region<T> {}
+
+impl<T> Debug for Region<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Region")
+            .field("offset", &self.offset)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+/// Computes the layout of a [`Partition`], one region at a time.
+#[derive(Debug, Default)]
+pub struct PartitionBuilder {
+    layout: LayoutBuilder,
+}
+
+impl PartitionBuilder {
+    /// Creates a builder with no regions.
+    #[must_use]
+    #[inline]
+    pub const fn new() -> PartitionBuilder {
+        PartitionBuilder {
+            layout: LayoutBuilder::new(),
+        }
+    }
+
+    /// Adds a region of `count` elements of `T`, placed after the previous one.
+    ///
+    /// An invalid layout isn't reported until the partition is built; the region returned in that
+    /// case must not be used.
+    #[must_use]
+    pub fn add<T>(&mut self, count: usize) -> Region<T> {
+        let offset = if let Ok(layout) = array_layout::<T>(count) {
+            self.add_layout(layout)
+        } else {
+            // let the builder record the error for `build`
+            self.layout = core::mem::take(&mut self.layout).array::<T>(count);
+            0
+        };
+        Region {
+            offset,
+            len: count,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Adds an untyped region fitting `layout`, placed after the previous one, and returns its
+    /// offset.
+    pub fn add_layout(&mut self, layout: Layout) -> usize {
+        let (builder, offset) = core::mem::take(&mut self.layout).push(layout);
+        self.layout = builder;
+        offset
+    }
+
+    /// Allocates the partition's block in the default allocator.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    /// - [`AllocError::LayoutError`] if the combined layout is invalid.
+    /// - [`AllocError::ZeroSizedLayout`] if every region is empty.
+    #[track_caller]
+    #[inline]
+    pub fn build(self) -> Result<Partition, AllocError> {
+        self.build_in(DefaultAlloc)
+    }
+
+    /// Allocates the partition's block using `alloc`.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    /// - [`AllocError::LayoutError`] if the combined layout is invalid.
+    /// - [`AllocError::ZeroSizedLayout`] if every region is empty.
+    #[track_caller]
+    pub fn build_in<A: Alloc>(self, alloc: A) -> Result<Partition<A>, AllocError> {
+        let layout = self.layout.build()?;
+        Ok(Partition {
+            block: alloc.alloc(layout)?,
+            layout,
+            alloc,
+        })
+    }
+}
+
+/// One allocation split into several typed regions, laid out by a [`PartitionBuilder`].
+///
+/// Laying related arrays out in one block, as for struct-of-arrays data, costs a single
+/// allocation and keeps the arrays close together. Each region is aligned for its element type.
+/// The regions start uninitialized, and are never dropped; the whole block is freed at once with
+/// [`free`](Partition::free) or when the partition is dropped.
+pub struct Partition<A: Alloc = DefaultAlloc> {
+    block: NonNull<u8>,
+    layout: Layout,
+    alloc: A,
+}
+
+impl<A: Alloc> Partition<A> {
+    /// Returns the layout of the whole block.
+    #[must_use]
+    #[inline]
+    pub const fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Returns a pointer to the start of the whole block.
+    #[must_use]
+    #[inline]
+    pub const fn block(&self) -> NonNull<u8> {
+        self.block
+    }
+
+    /// Returns the elements of `region`, which may be uninitialized.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `region` extends past the block, which means it came from another builder.
+    #[track_caller]
+    #[must_use]
+    pub fn slice<T>(&self, region: Region<T>) -> NonNull<[T]> {
+        let fits = region
+            .len
+            .checked_mul(size_of::<T>())
+            .and_then(|size| size.checked_add(region.offset))
+            .is_some_and(|end| end <= self.layout.size());
+        assert!(fits, "region doesn't belong to this partition");
+        NonNull::slice_from_raw_parts(unsafe { self.block.add(region.offset).cast() }, region.len)
+    }
+
+    /// Frees the whole block.
+    #[inline]
+    pub fn free(self) {
+        drop(self);
+    }
+}
+
+impl<A: Alloc> Drop for Partition<A> {
+    fn drop(&mut self) {
+        unsafe {
+            self.alloc.dealloc(self.block, self.layout);
+        }
+    }
+}
+
+impl<A: Alloc> Debug for Partition<A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Partition")
+            .field("layout", &self.layout)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,590 @@
+use crate::{Alloc, AllocError};
+use core::{
+    alloc::Layout,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    ptr::NonNull,
+    slice,
+    sync::atomic::{AtomicUsize, Ordering::Relaxed},
+};
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+    thread::{self, ThreadId},
+};
+
+/// An allocator which detects memory being corrupted while it's held.
+///
+/// A CRC32 checksum of an allocation's contents is stored when it is sealed, and checked against
+/// the current contents when it's deallocated. If they differ, `on_corrupt` is called with the
+/// allocation's pointer and layout before the memory is freed.
+///
+/// Zeroed, filled, and patterned allocations are sealed automatically, since their contents are
+/// known. Uninitialized allocations must be sealed with [`seal`](MirrorOnDealloc::seal) once
+/// they've been written, and should be resealed after every intended write. Unsealed
+/// allocations aren't checked. Resizing checks the old allocation as though it were being freed,
+/// and produces a new, unsealed allocation.
+pub struct MirrorOnDealloc<A, F: Fn(NonNull<u8>, Layout)> {
+    inner: A,
+    on_corrupt: F,
+    /// Live allocations, by address, with their checksum if sealed.
+    live: Mutex<HashMap<usize, (Layout, Option<u32>)>>,
+}
+
+impl<A: Alloc, F: Fn(NonNull<u8>, Layout)> MirrorOnDealloc<A, F> {
+    /// Creates a new checksumming allocator over `inner`, reporting corruption to `on_corrupt`.
+    #[must_use]
+    #[inline]
+    pub fn new(inner: A, on_corrupt: F) -> MirrorOnDealloc<A, F> {
+        MirrorOnDealloc {
+            inner,
+            on_corrupt,
+            live: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns a reference to the underlying allocator.
+    #[must_use]
+    #[inline]
+    pub const fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Records the current contents of the allocation at `ptr` as its expected contents.
+    ///
+    /// Returns `false` if `ptr` isn't a live allocation of this allocator.
+    ///
+    /// # Safety
+    ///
+    /// Every byte of the allocation must be initialized.
+    #[track_caller]
+    pub unsafe fn seal(&self, ptr: NonNull<u8>) -> bool {
+        match self.live_map().get_mut(&ptr.addr().get()) {
+            Some((layout, sum)) => {
+                *sum = Some(crc32(unsafe { contents(ptr, *layout) }));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Checks the allocation at `ptr` against its sealed checksum without deallocating it.
+    ///
+    /// Returns `None` if `ptr` isn't a live, sealed allocation of this allocator.
+    ///
+    /// # Safety
+    ///
+    /// The allocation must not be being written to concurrently.
+    #[track_caller]
+    #[must_use]
+    pub unsafe fn verify(&self, ptr: NonNull<u8>) -> Option<bool> {
+        match self.live_map().get(&ptr.addr().get()) {
+            Some(&(layout, Some(sum))) => Some(crc32(unsafe { contents(ptr, layout) }) == sum),
+            _ => None,
+        }
+    }
+
+    #[track_caller]
+    fn live_map(&self) -> std::sync::MutexGuard<'_, HashMap<usize, (Layout, Option<u32>)>> {
+        // a panicking callback can poison the lock, but the map itself is still consistent
+        self.live
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    #[track_caller]
+    fn track(
+        &self,
+        res: Result<NonNull<u8>, AllocError>,
+        layout: Layout,
+        initialized: bool,
+    ) -> Result<NonNull<u8>, AllocError> {
+        if let Ok(ptr) = res {
+            let sum = initialized.then(|| crc32(unsafe { contents(ptr, layout) }));
+            self.live_map().insert(ptr.addr().get(), (layout, sum));
+        }
+        res
+    }
+
+    /// Stops tracking the allocation at `ptr`, reporting it if its contents no longer match its
+    /// seal, and returns its entry.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a live allocation of `layout` from this allocator.
+    #[track_caller]
+    unsafe fn untrack(&self, ptr: NonNull<u8>, layout: Layout) -> Option<(Layout, Option<u32>)> {
+        let entry = self.live_map().remove(&ptr.addr().get());
+        if let Some((_, Some(sum))) = entry
+            && crc32(unsafe { contents(ptr, layout) }) != sum
+        {
+            (self.on_corrupt)(ptr, layout);
+        }
+        entry
+    }
+
+    /// Checks and untracks the allocation at `ptr`, resizes it using `resize` on the inner
+    /// allocator, and tracks the result as unsealed. The old entry is restored if resizing fails.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a live allocation of `old_layout` from this allocator, and `resize` must
+    /// uphold the contract of the inner resizing method it calls.
+    #[track_caller]
+    unsafe fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        resize: impl FnOnce(&A) -> Result<NonNull<u8>, AllocError>,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let entry = unsafe { self.untrack(ptr, old_layout) };
+        let res = resize(&self.inner);
+        if res.is_err()
+            && let Some(entry) = entry
+        {
+            self.live_map().insert(ptr.addr().get(), entry);
+        }
+        self.track(res, new_layout, false)
+    }
+}
+
+impl<A: Alloc, F: Fn(NonNull<u8>, Layout)> Alloc for MirrorOnDealloc<A, F> {
+    #[track_caller]
+    #[inline]
+    fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.track(self.inner.alloc(layout), layout, false)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.track(self.inner.alloc_zeroed(layout), layout, true)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+        self.track(self.inner.alloc_filled(layout, n), layout, true)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_patterned<P: Fn(usize) -> u8 + Clone>(
+        &self,
+        layout: Layout,
+        pattern: P,
+    ) -> Result<NonNull<u8>, AllocError> {
+        self.track(self.inner.alloc_patterned(layout, pattern), layout, true)
+    }
+
+    #[track_caller]
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe {
+            self.untrack(ptr, layout);
+            self.inner.dealloc(ptr, layout);
+        }
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        unsafe {
+            self.resize(ptr, old_layout, new_layout, |a| {
+                a.grow(ptr, old_layout, new_layout)
+            })
+        }
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        unsafe {
+            self.resize(ptr, old_layout, new_layout, |a| {
+                a.grow_zeroed(ptr, old_layout, new_layout)
+            })
+        }
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn grow_patterned<P: Fn(usize) -> u8 + Clone>(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        pattern: P,
+    ) -> Result<NonNull<u8>, AllocError> {
+        unsafe {
+            self.resize(ptr, old_layout, new_layout, |a| {
+                a.grow_patterned(ptr, old_layout, new_layout, pattern)
+            })
+        }
+    }
+
+    #[track_caller]
+    #[inline]
+    fn grow_filled(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        n: u8,
+    ) -> Result<NonNull<u8>, AllocError> {
+        // `Alloc::grow_filled` carries the same contract as the other resizing methods
+        unsafe {
+            self.resize(ptr, old_layout, new_layout, |a| {
+                a.grow_filled(ptr, old_layout, new_layout, n)
+            })
+        }
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        unsafe {
+            self.resize(ptr, old_layout, new_layout, |a| {
+                a.shrink(ptr, old_layout, new_layout)
+            })
+        }
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn realloc(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        unsafe {
+            self.resize(ptr, old_layout, new_layout, |a| {
+                a.realloc(ptr, old_layout, new_layout)
+            })
+        }
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn realloc_patterned<P: Fn(usize) -> u8 + Clone>(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        pattern: P,
+    ) -> Result<NonNull<u8>, AllocError> {
+        unsafe {
+            self.resize(ptr, old_layout, new_layout, |a| {
+                a.realloc_patterned(ptr, old_layout, new_layout, pattern)
+            })
+        }
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn realloc_filled(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        n: u8,
+    ) -> Result<NonNull<u8>, AllocError> {
+        unsafe {
+            self.resize(ptr, old_layout, new_layout, |a| {
+                a.realloc_filled(ptr, old_layout, new_layout, n)
+            })
+        }
+    }
+
+    #[track_caller]
+    #[inline]
+    fn reserve(&self, bytes: usize) -> Result<(), AllocError> {
+        self.inner.reserve(bytes)
+    }
+
+    #[inline]
+    fn granularity(&self) -> usize {
+        self.inner.granularity()
+    }
+}
+
+/// A wrapper that delegates all `Alloc` calls to `A`, panicking if it's used from more than one
+/// thread.
+///
+/// The first operation binds the allocator to the calling thread, and every later one checks that
+/// it comes from the same thread, at the cost of a thread-local read and an atomic load. This
+/// catches an allocator meant to be confined to one thread being shared, such as a `!Sync`
+/// allocator smuggled across threads through unsafe code, which would otherwise be silent,
+/// hard-to-reproduce undefined behavior. The panic names both threads.
+///
+/// An allocator which is `Send` can be moved to another thread legitimately; call
+/// [`unbind`](ThreadBound::unbind) after moving it so the next operation rebinds it.
+pub struct ThreadBound<A> {
+    inner: A,
+    owner: ThreadOwner,
+}
+
+impl<A> ThreadBound<A> {
+    /// Creates a new, unbound thread-checking allocator over `inner`.
+    #[must_use]
+    #[inline]
+    pub const fn new(inner: A) -> ThreadBound<A> {
+        ThreadBound {
+            inner,
+            owner: ThreadOwner::new(),
+        }
+    }
+
+    /// Returns a reference to the underlying allocator.
+    ///
+    /// Using it directly bypasses the check.
+    #[must_use]
+    #[inline]
+    pub const fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Returns the thread the allocator is bound to, if it's been used yet.
+    #[must_use]
+    #[inline]
+    pub fn owner(&self) -> Option<ThreadId> {
+        self.owner.id.get().copied()
+    }
+
+    /// Unbinds the allocator, so the next operation binds it to whichever thread makes it.
+    #[inline]
+    pub fn unbind(&mut self) {
+        self.owner = ThreadOwner::new();
+    }
+}
+
+impl<A: Alloc> Alloc for ThreadBound<A> {
+    #[track_caller]
+    #[inline]
+    fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.owner.check();
+        self.inner.alloc(layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.owner.check();
+        self.inner.alloc_zeroed(layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+        self.owner.check();
+        self.inner.alloc_filled(layout, n)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_patterned<P: Fn(usize) -> u8 + Clone>(
+        &self,
+        layout: Layout,
+        pattern: P,
+    ) -> Result<NonNull<u8>, AllocError> {
+        self.owner.check();
+        self.inner.alloc_patterned(layout, pattern)
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.owner.check();
+        unsafe {
+            self.inner.dealloc(ptr, layout);
+        }
+    }
+
+    #[track_caller]
+    #[inline]
+    fn reserve(&self, bytes: usize) -> Result<(), AllocError> {
+        self.owner.check();
+        self.inner.reserve(bytes)
+    }
+
+    #[inline]
+    fn granularity(&self) -> usize {
+        self.inner.granularity()
+    }
+}
+
+/// The thread an allocator was first used from, for panicking on use from any other.
+pub(crate) struct ThreadOwner {
+    /// The owning thread's tag, or zero if unbound.
+    tag: AtomicUsize,
+    /// The owning thread's id, only read to report a violation.
+    id: OnceLock<ThreadId>,
+}
+
+impl ThreadOwner {
+    pub(crate) const fn new() -> ThreadOwner {
+        ThreadOwner {
+            tag: AtomicUsize::new(0),
+            id: OnceLock::new(),
+        }
+    }
+
+    /// Binds to the current thread if unbound, and panics if bound to another.
+    #[track_caller]
+    #[inline]
+    pub(crate) fn check(&self) {
+        let tag = THREAD_TAG.with(|t| *t);
+        let owner = self.tag.load(Relaxed);
+        if owner == tag {
+            return;
+        }
+        if owner == 0 && self.tag.compare_exchange(0, tag, Relaxed, Relaxed).is_ok() {
+            let _ = self.id.set(thread::current().id());
+            return;
+        }
+        self.violated();
+    }
+
+    #[cold]
+    #[track_caller]
+    fn violated(&self) -> ! {
+        let current = thread::current();
+        // the owner may still be recording its id if both threads raced to bind
+        match self.id.get() {
+            Some(owner) => panic!(
+                "allocator bound to thread {owner:?} was used from thread {:?} ({})",
+                current.id(),
+                current.name().unwrap_or("<unnamed>")
+            ),
+            None => panic!(
+                "allocator bound to another thread was used from thread {:?} ({})",
+                current.id(),
+                current.name().unwrap_or("<unnamed>")
+            ),
+        }
+    }
+}
+
+static NEXT_TAG: AtomicUsize = AtomicUsize::new(1);
+
+thread_local! {
+    /// A nonzero tag unique to each thread, cheaper to read than its `ThreadId`.
+    static THREAD_TAG: usize = NEXT_TAG.fetch_add(1, Relaxed);
+}
+
+/// An inconsistency found in an allocator's internal bookkeeping by a `verify_integrity` walk.
+///
+/// Walks report the first inconsistency they find. Any of these means the allocator's metadata
+/// was corrupted, usually by a write out of bounds of an allocation or a free with the wrong
+/// pointer or layout, and that further allocations from it can't be trusted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegrityError {
+    /// A free block extends past the end of the allocator's memory.
+    OutOfBounds {
+        /// The block's address.
+        addr: usize,
+        /// The block's recorded size.
+        size: usize,
+    },
+    /// A free block overlaps the free block before it.
+    Overlapping {
+        /// The later block's address.
+        addr: usize,
+    },
+    /// A free block directly follows another without having been merged with it.
+    Uncoalesced {
+        /// The later block's address.
+        addr: usize,
+    },
+    /// A block's recorded size is impossible.
+    BadSize {
+        /// The block's address.
+        addr: usize,
+        /// The block's recorded size.
+        size: usize,
+    },
+    /// Per-block or per-page counts disagree with each other or with the allocator's totals.
+    Accounting {
+        /// The address of the offending block or page.
+        addr: usize,
+    },
+}
+
+impl Display for IntegrityError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegrityError::OutOfBounds { addr, size } => write!(
+                f,
+                "free block at {addr:#x} of {size} bytes extends out of bounds"
+            ),
+            IntegrityError::Overlapping { addr } => {
+                write!(f, "free block at {addr:#x} overlaps the one before it")
+            }
+            IntegrityError::Uncoalesced { addr } => write!(
+                f,
+                "free block at {addr:#x} wasn't merged with the one before it"
+            ),
+            IntegrityError::BadSize { addr, size } => {
+                write!(f, "block at {addr:#x} has an impossible size of {size}")
+            }
+            IntegrityError::Accounting { addr } => {
+                write!(f, "bookkeeping for {addr:#x} is inconsistent")
+            }
+        }
+    }
+}
+
+impl Error for IntegrityError {}
+
+/// Gets the bytes of the allocation at `ptr`.
+///
+/// # Safety
+///
+/// The allocation must be live, fit `layout`, and be fully initialized.
+unsafe fn contents<'a>(ptr: NonNull<u8>, layout: Layout) -> &'a [u8] {
+    unsafe { slice::from_raw_parts(ptr.as_ptr(), layout.size()) }
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        #[allow(clippy::cast_possible_truncation)]
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            // reflected IEEE polynomial
+            c = if c & 1 == 0 {
+                c >> 1
+            } else {
+                0xEDB8_8320 ^ (c >> 1)
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+/// Computes the IEEE CRC32 of `bytes`.
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |c, &b| {
+        CRC32_TABLE[((c ^ u32::from(b)) & 0xFF) as usize] ^ (c >> 8)
+    })
+}
@@ -13,6 +13,7 @@
 //!
 //! - [`UnsizedCopy`], a marker trait indicating a value can be copied safely even if unsized.
 //! - [`Thin`], a marker trait indicating a pointer to a type has no metadata.
+//! - [`Zeroable`], a marker trait indicating the all-zero bit pattern is a valid value.
 //!
 //! And, if the `alloc_ext` feature is on:
 //!
@@ -68,17 +69,31 @@ mod type_props;
 /// Small alternatives to Rust functions which are currently unstable.
 pub mod unstable_util;
 
+#[cfg(feature = "adapters")]
+/// Allocators which wrap another allocator to change how it behaves.
+pub mod adapters;
+#[cfg(feature = "arena")]
+/// Arena allocators: a generational arena handing out checked handles, a lock-free bump
+/// allocator, a bump allocator recording allocation order for deterministic replay, and one block
+/// partitioned into typed regions.
+pub mod arena;
+#[cfg(feature = "debug_checks")]
+/// Allocators which check for misuse or corruption, for debugging.
+pub mod debug_checks;
 #[cfg(feature = "owned")]
-/// An owned buffer type.
+/// Owned buffer and reference-counted pointer types.
 pub mod owned;
 #[cfg(feature = "stats")]
 /// Allocation statistic gathering and reporting.
 pub mod stats;
+#[cfg(all(feature = "vmem", unix))]
+/// Allocators managing virtual memory directly.
+pub mod vmem;
 
 pub use marker::*;
 pub use type_props::*;
 
-use crate::helpers::layout_or_sz_align;
+use crate::helpers::array_layout;
 use core::{
     alloc::{GlobalAlloc, Layout},
     cmp::Ordering,
@@ -89,7 +104,7 @@ use core::{
 
 /// Helpers which tend to be useful in other libraries as well.
 pub mod helpers {
-    use crate::Alloc;
+    use crate::{Alloc, AllocError};
     use core::{alloc::Layout, mem::forget, num::NonZeroUsize, ops::Deref, ptr::NonNull};
 
     /// Returns a [`NonNull`] which has the given alignment as its address.
@@ -124,6 +139,236 @@ pub mod helpers {
         }
     }
 
+    /// Gets a layout with space for `n` count of `T`.
+    ///
+    /// This is usable in constants, so a fixed allocation plan can be checked at compile time:
+    ///
+    /// ```
+    /// # use core::alloc::Layout;
+    /// # use memapi::helpers::{array_layout, layout_or_panic};
+    /// const NODES: Layout = layout_or_panic(array_layout::<[u64; 4]>(1024));
+    /// assert_eq!(NODES.size(), 32 * 1024);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::LayoutError`] if the layout's size would overflow `isize::MAX`.
+    #[inline]
+    pub const fn array_layout<T>(n: usize) -> Result<Layout, AllocError> {
+        match layout_or_sz_align::<T>(n) {
+            Ok(layout) => Ok(layout),
+            Err((sz, align)) => Err(AllocError::LayoutError(sz, align)),
+        }
+    }
+
+    /// Checks that `size` and `align` form a valid layout.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::LayoutError`] if `align` isn't a power of two, or `size` rounded up to it
+    ///   would overflow `isize::MAX`.
+    #[inline]
+    pub const fn validate_layout(size: usize, align: usize) -> Result<Layout, AllocError> {
+        match Layout::from_size_align(size, align) {
+            Ok(layout) => Ok(layout),
+            Err(_) => Err(AllocError::LayoutError(size, align)),
+        }
+    }
+
+    /// Unwraps a layout computed at compile time, failing compilation if it was invalid.
+    ///
+    /// `Result::unwrap` isn't usable in constants, so this fills in for it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `res` is an error.
+    #[track_caller]
+    #[must_use]
+    #[inline]
+    // by value, so the helpers' results can be passed straight in
+    #[allow(clippy::needless_pass_by_value)]
+    pub const fn layout_or_panic(res: Result<Layout, AllocError>) -> Layout {
+        match res {
+            Ok(layout) => layout,
+            Err(_) => panic!("invalid layout"),
+        }
+    }
+
+    /// Rounds `n` up to the next multiple of `align`.
+    ///
+    /// This is the address-independent half of [`align_up`], usable in constants for offsets and
+    /// sizes. Returns `None` if `align` is not a power of two or if rounding up would overflow.
+    #[must_use]
+    #[inline]
+    pub const fn align_up_usize(n: usize, align: usize) -> Option<usize> {
+        if !align.is_power_of_two() {
+            return None;
+        }
+        match n.checked_add(align - 1) {
+            Some(n) => Some(n & !(align - 1)),
+            None => None,
+        }
+    }
+
+    /// Builds the layout of a `#[repr(C)]`-style sequence of fields, usable in constants.
+    ///
+    /// Each field is placed at the next offset aligned for it, and [`build`](LayoutBuilder::build)
+    /// pads the result to the largest alignment seen. The first error along the way is kept and
+    /// returned by `build`, so a chain of calls needs no handling in between:
+    ///
+    /// ```
+    /// # use core::alloc::Layout;
+    /// # use memapi::helpers::{LayoutBuilder, layout_or_panic};
+    /// const PACKET: Layout =
+    ///     layout_or_panic(LayoutBuilder::new().field::<u8>().array::<u32>(3).build());
+    /// assert_eq!((PACKET.size(), PACKET.align()), (16, 4));
+    /// ```
+    #[derive(Debug, PartialEq, Eq)]
+    pub struct LayoutBuilder {
+        size: usize,
+        align: usize,
+        err: Option<AllocError>,
+    }
+
+    impl LayoutBuilder {
+        /// Creates a builder with no fields.
+        #[must_use]
+        #[inline]
+        pub const fn new() -> LayoutBuilder {
+            LayoutBuilder {
+                size: 0,
+                align: 1,
+                err: None,
+            }
+        }
+
+        /// Appends a field of `layout`, returning the builder and the field's offset.
+        ///
+        /// The offset is meaningless if the builder has failed.
+        #[must_use]
+        pub const fn push(self, layout: Layout) -> (LayoutBuilder, usize) {
+            if self.err.is_some() {
+                return (self, 0);
+            }
+            let align = if layout.align() > self.align {
+                layout.align()
+            } else {
+                self.align
+            };
+            let Some(offset) = align_up_usize(self.size, layout.align()) else {
+                return (self.fail(AllocError::ArithmeticOverflow), 0);
+            };
+            let Some(size) = offset.checked_add(layout.size()) else {
+                return (self.fail(AllocError::ArithmeticOverflow), 0);
+            };
+            if let Err(e) = validate_layout(size, align) {
+                return (self.fail(e), 0);
+            }
+            (
+                LayoutBuilder {
+                    size,
+                    align,
+                    err: None,
+                },
+                offset,
+            )
+        }
+
+        /// Appends a field of type `T`.
+        #[must_use]
+        #[inline]
+        pub const fn field<T>(self) -> LayoutBuilder {
+            self.push(Layout::new::<T>()).0
+        }
+
+        /// Appends an array of `n` elements of `T`.
+        #[must_use]
+        #[inline]
+        pub const fn array<T>(self, n: usize) -> LayoutBuilder {
+            match array_layout::<T>(n) {
+                Ok(layout) => self.push(layout).0,
+                Err(e) => self.fail(e),
+            }
+        }
+
+        /// Finishes the layout, padded to its alignment.
+        ///
+        /// # Errors
+        ///
+        /// Returns the first error recorded while building, if any:
+        ///
+        /// - [`AllocError::ArithmeticOverflow`] if aligning or adding a field in
+        ///   [`push`](LayoutBuilder::push) overflowed a `usize`.
+        /// - [`AllocError::LayoutError`] if a pushed field grew the layout past `isize::MAX`, or an
+        ///   [`array`](LayoutBuilder::array)'s layout would have, as reported by [`array_layout`].
+        ///
+        /// Otherwise:
+        ///
+        /// - [`AllocError::LayoutError`] if the layout grew past `isize::MAX`.
+        #[inline]
+        pub const fn build(self) -> Result<Layout, AllocError> {
+            if let Some(e) = self.err {
+                return Err(e);
+            }
+            match validate_layout(self.size, self.align) {
+                Ok(layout) => Ok(layout.pad_to_align()),
+                Err(e) => Err(e),
+            }
+        }
+
+        const fn fail(self, e: AllocError) -> LayoutBuilder {
+            LayoutBuilder {
+                err: Some(e),
+                ..self
+            }
+        }
+    }
+
+    impl Default for LayoutBuilder {
+        #[inline]
+        fn default() -> LayoutBuilder {
+            LayoutBuilder::new()
+        }
+    }
+
+    /// Rounds `ptr` up to the next multiple of `align`, keeping its provenance.
+    ///
+    /// Returns `None` if `align` is not a power of two or if rounding up would overflow the
+    /// address space.
+    ///
+    /// This can't be `const`, as reading a pointer's address isn't allowed in constants; use
+    /// [`align_up_usize`] to align plain addresses or offsets at compile time.
+    #[must_use]
+    #[inline]
+    pub fn align_up(ptr: NonNull<u8>, align: usize) -> Option<NonNull<u8>> {
+        if !align.is_power_of_two() {
+            return None;
+        }
+        let addr = ptr.addr().get();
+        let aligned = align_up_usize(addr, align)?;
+        // `aligned >= addr > 0`, so this can't produce null
+        Some(unsafe { NonNull::new_unchecked(ptr.as_ptr().wrapping_add(aligned - addr)) })
+    }
+
+    /// Carves a block fitting `layout` off the front of `region`, advancing `region` past it.
+    ///
+    /// Any padding needed to align the block is skipped and lost. Returns `None`, leaving `region`
+    /// untouched, if the aligned block doesn't fit.
+    #[must_use]
+    #[inline]
+    pub fn carve(region: &mut NonNull<[u8]>, layout: Layout) -> Option<NonNull<u8>> {
+        let start = region.cast::<u8>();
+        let block = align_up(start, layout.align())?;
+        let used = (block.addr().get() - start.addr().get()).checked_add(layout.size())?;
+        if used > region.len() {
+            return None;
+        }
+        // `used` is within the region, so the new start can't wrap around to null
+        let rest = unsafe { NonNull::new_unchecked(start.as_ptr().wrapping_add(used)) };
+        *region = NonNull::slice_from_raw_parts(rest, region.len() - used);
+        Some(block)
+    }
+
     /// A RAII guard that owns a single allocation and ensures it is deallocated unless explicitly
     /// released.
     ///
@@ -378,8 +623,7 @@ pub trait Alloc {
     #[track_caller]
     #[inline]
     fn alloc_slice<T>(&self, len: usize) -> Result<NonNull<[T]>, AllocError> {
-        let layout = layout_or_sz_align::<T>(len)
-            .map_err(|(sz, align)| AllocError::LayoutError(sz, align))?;
+        let layout = array_layout::<T>(len)?;
         self.alloc(layout)
             .map(|ptr| NonNull::slice_from_raw_parts(ptr.cast(), len))
             .map_err(|_| AllocError::AllocFailed(layout))
@@ -404,8 +648,7 @@ pub trait Alloc {
     #[track_caller]
     #[inline]
     fn alloc_slice_zeroed<T>(&self, len: usize) -> Result<NonNull<[T]>, AllocError> {
-        let layout = layout_or_sz_align::<T>(len)
-            .map_err(|(sz, align)| AllocError::LayoutError(sz, align))?;
+        let layout = array_layout::<T>(len)?;
         self.alloc_zeroed(layout)
             .map(|ptr| NonNull::slice_from_raw_parts(ptr.cast(), len))
             .map_err(|_| AllocError::AllocFailed(layout))
@@ -432,8 +675,7 @@ pub trait Alloc {
     #[track_caller]
     #[inline]
     fn alloc_slice_filled<T>(&self, len: usize, n: u8) -> Result<NonNull<[T]>, AllocError> {
-        let layout = layout_or_sz_align::<T>(len)
-            .map_err(|(sz, align)| AllocError::LayoutError(sz, align))?;
+        let layout = array_layout::<T>(len)?;
         self.alloc_filled(layout, n)
             .map(|ptr| NonNull::slice_from_raw_parts(ptr.cast(), len))
             .map_err(|_| AllocError::AllocFailed(layout))
@@ -468,8 +710,7 @@ pub trait Alloc {
         len: usize,
         pattern: F,
     ) -> Result<NonNull<[T]>, AllocError> {
-        let layout = layout_or_sz_align::<T>(len)
-            .map_err(|(sz, align)| AllocError::LayoutError(sz, align))?;
+        let layout = array_layout::<T>(len)?;
         self.alloc_patterned(layout, pattern)
             .map(|ptr| NonNull::slice_from_raw_parts(ptr.cast(), len))
             .map_err(|_| AllocError::AllocFailed(layout))
@@ -486,6 +727,10 @@ pub trait Alloc {
 
     /// Deallocates a previously allocated block.
     ///
+    /// The layout used is `n` elements of `T`, the same one [`alloc_slice`](Alloc::alloc_slice)
+    /// allocates with and [`Layout::for_value`] gives for a `[T]` of length `n`, so a slice can be
+    /// freed through either.
+    ///
     /// # Safety
     ///
     /// - `ptr` must point to a block of memory allocated using this allocator.
@@ -786,6 +1031,35 @@ pub trait Alloc {
             shrink_unchecked(self, ptr, old_layout, new_layout)
         }
     }
+
+    /// Asks the allocator to make sure at least `bytes` of capacity are available, so that later
+    /// allocations up to that size don't have to grow the allocator or fault in memory.
+    ///
+    /// This only moves allocation cost up front; it never allocates a block for the caller. The
+    /// default implementation does nothing, as allocators without capacity of their own, like
+    /// [`DefaultAlloc`], have nothing to reserve.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if the capacity can't be made available.
+    #[track_caller]
+    #[inline]
+    fn reserve(&self, bytes: usize) -> Result<(), AllocError> {
+        let _ = bytes;
+        Ok(())
+    }
+
+    /// Returns the allocator's natural allocation granularity in bytes, such as the page size
+    /// for one that maps pages itself.
+    ///
+    /// Requests are still served at any size, but a size that's a multiple of this wastes less
+    /// of the underlying block, so callers sizing buffers or bulk reservations can round up to
+    /// it. This is always nonzero. The default implementation returns `1`.
+    #[must_use]
+    #[inline]
+    fn granularity(&self) -> usize {
+        1
+    }
 }
 
 #[cfg(any(feature = "std", feature = "jemalloc_support"))]
@@ -1069,6 +1343,17 @@ pub(crate) mod nightly {
         unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
             (**self).dealloc(ptr, layout);
         }
+
+        #[track_caller]
+        #[inline]
+        fn reserve(&self, bytes: usize) -> Result<(), AllocError> {
+            (**self).reserve(bytes)
+        }
+
+        #[inline]
+        fn granularity(&self) -> usize {
+            (**self).granularity()
+        }
     }
 }
 
@@ -1185,6 +1470,14 @@ pub(crate) mod fallback {
         unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
             (*self).dealloc(ptr, layout);
         }
+
+        fn reserve(&self, bytes: usize) -> Result<(), AllocError> {
+            (*self).reserve(bytes)
+        }
+
+        fn granularity(&self) -> usize {
+            (*self).granularity()
+        }
     }
 }
 
@@ -1249,12 +1542,64 @@ pub enum AllocError {
     GrowSmallerNewLayout(usize, usize),
     /// Attempted to shrink to a larger layout.
     ShrinkBiggerNewLayout(usize, usize),
+    /// The allocator refused the request by policy, for the given reason, regardless of how much
+    /// memory is available.
+    Unsupported(&'static str),
     #[cfg(feature = "resize_in_place")]
     /// Resizing in-place was found to be impossible.
     // Note that this variant means the allocator supports resizing in-place, but it failed.
     CannotResizeInPlace,
 }
 
+impl AllocError {
+    /// Returns `true` if the error came from the allocator running out of resources, so the same
+    /// request could succeed later or from another allocator.
+    ///
+    /// Only [`AllocFailed`](AllocError::AllocFailed) is transient. Retrying anything else fails
+    /// the same way every time.
+    #[must_use]
+    #[inline]
+    pub const fn is_transient(&self) -> bool {
+        // exhaustive so that new variants have to be classified
+        match self {
+            AllocError::AllocFailed(_) => true,
+            AllocError::ArithmeticOverflow
+            | AllocError::LayoutError(..)
+            | AllocError::ZeroSizedLayout(_)
+            | AllocError::GrowSmallerNewLayout(..)
+            | AllocError::ShrinkBiggerNewLayout(..)
+            | AllocError::Unsupported(_) => false,
+            #[cfg(feature = "resize_in_place")]
+            AllocError::CannotResizeInPlace => false,
+        }
+    }
+
+    /// Returns `true` if the error means the request itself was invalid, which is a bug in the
+    /// caller rather than resource exhaustion.
+    ///
+    /// This covers overflowing sizes, invalid layouts, and resizes in the wrong direction.
+    /// [`ZeroSizedLayout`](AllocError::ZeroSizedLayout) isn't one, since it's a normal outcome for
+    /// generic code which callers are expected to handle, and neither is failing to resize in
+    /// place, which a caller handles by moving the allocation instead. Neither is
+    /// [`Unsupported`](AllocError::Unsupported), since a policy usually guards against bad input
+    /// rather than bad code.
+    #[must_use]
+    #[inline]
+    pub const fn is_programmer_error(&self) -> bool {
+        match self {
+            AllocError::ArithmeticOverflow
+            | AllocError::LayoutError(..)
+            | AllocError::GrowSmallerNewLayout(..)
+            | AllocError::ShrinkBiggerNewLayout(..) => true,
+            AllocError::ZeroSizedLayout(_)
+            | AllocError::AllocFailed(_)
+            | AllocError::Unsupported(_) => false,
+            #[cfg(feature = "resize_in_place")]
+            AllocError::CannotResizeInPlace => false,
+        }
+    }
+}
+
 impl Display for AllocError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
@@ -1274,6 +1619,7 @@ impl Display for AllocError {
                 f,
                 "attempted to shrink from a size of {old} to a larger size of {new}"
             ),
+            AllocError::Unsupported(reason) => write!(f, "unsupported allocation: {reason}"),
             #[cfg(feature = "resize_in_place")]
             AllocError::CannotResizeInPlace => write!(f, "cannot resize in place"),
         }
@@ -1282,6 +1628,48 @@ impl Display for AllocError {
 
 impl Error for AllocError {}
 
+/// Extension methods for handling the result of an allocation.
+pub trait AllocResultExt<T> {
+    /// Returns the contained value, treating an allocation failure as out-of-memory.
+    ///
+    /// # Panics
+    ///
+    /// On [`AllocError::AllocFailed`], this calls
+    /// [`handle_alloc_error`](alloc::alloc::handle_alloc_error), which by default aborts. Any other
+    /// error is a bug in the caller rather than memory exhaustion, so it panics with the error
+    /// instead.
+    #[track_caller]
+    fn unwrap_or_oom(self) -> T;
+
+    /// Returns `true` if the result is an [`AllocError::AllocFailed`].
+    fn is_oom(&self) -> bool;
+
+    /// Returns `true` if the result is an [`AllocError::LayoutError`].
+    fn is_layout_err(&self) -> bool;
+}
+
+impl<T> AllocResultExt<T> for Result<T, AllocError> {
+    #[track_caller]
+    #[inline]
+    fn unwrap_or_oom(self) -> T {
+        match self {
+            Ok(v) => v,
+            Err(AllocError::AllocFailed(layout)) => alloc::alloc::handle_alloc_error(layout),
+            Err(e) => panic!("allocation failed: {e}"),
+        }
+    }
+
+    #[inline]
+    fn is_oom(&self) -> bool {
+        matches!(self, Err(AllocError::AllocFailed(_)))
+    }
+
+    #[inline]
+    fn is_layout_err(&self) -> bool {
+        matches!(self, Err(AllocError::LayoutError(..)))
+    }
+}
+
 /// Internal helper to grow the allocation at `ptr` by deallocating using `old_layout` and
 /// reallocating using `new_layout`, filling new bytes using `pattern.`
 #[inline]
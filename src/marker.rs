@@ -31,6 +31,56 @@ unsafe impl UnsizedCopy for Path {}
 #[cfg(feature = "bytemuck")]
 unsafe impl<D: bytemuck::Pod> UnsizedCopy for D {}
 
+/// Unsafe marker trait for types for which the all-zero bit pattern is a valid value.
+///
+/// # Safety
+///
+/// Implementing `Zeroable` indicates that a value of the type consisting entirely of zeroed bytes
+/// is valid, so zeroed memory can be used as an initialized `Self` without writing to it.
+pub unsafe trait Zeroable {}
+
+#[cfg(not(feature = "bytemuck"))]
+macro_rules! impl_zeroable {
+    ($($t:ty),* $(,)?) => {
+        $(unsafe impl Zeroable for $t {})*
+    };
+}
+
+#[cfg(not(feature = "bytemuck"))]
+impl_zeroable!(
+    (),
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64,
+    bool,
+    char,
+);
+#[cfg(not(feature = "bytemuck"))]
+// zero is the null pointer, which is a valid raw pointer.
+unsafe impl<T> Zeroable for *const T {}
+#[cfg(not(feature = "bytemuck"))]
+unsafe impl<T> Zeroable for *mut T {}
+#[cfg(not(feature = "bytemuck"))]
+// an array of zeroed `T` is zeroed
+unsafe impl<T: Zeroable, const N: usize> Zeroable for [T; N] {}
+#[cfg(not(feature = "bytemuck"))]
+// any bit pattern is a valid `MaybeUninit`
+unsafe impl<T> Zeroable for core::mem::MaybeUninit<T> {}
+
+#[cfg(feature = "bytemuck")]
+unsafe impl<Z: bytemuck::Zeroable> Zeroable for Z {}
+
 #[cfg(feature = "metadata")]
 /// Trait indicating that a type has no metadata.
 ///
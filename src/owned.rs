@@ -8,10 +8,16 @@ use core::{
     alloc::Layout,
     error::Error,
     fmt::{self, Debug, Display, Formatter},
+    marker::PhantomData,
     mem::{ManuallyDrop, MaybeUninit, forget, transmute},
-    ops::{Deref, DerefMut},
+    ops::{Bound, Deref, DerefMut, RangeBounds},
     ptr::{self, NonNull, replace},
     slice,
+    sync::atomic::{
+        AtomicUsize,
+        Ordering::{Acquire, Relaxed, Release},
+        fence,
+    },
 };
 
 /// An error which can be soft or hard.
@@ -125,6 +131,39 @@ impl<T, A: Alloc> OwnedBuf<T, A> {
         }
     }
 
+    /// Creates a new owned buffer of `T` from the elements of `iter`, in the given allocator.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    /// - [`AllocError::LayoutError`] if the computed layout is invalid.
+    /// - [`AllocError::ZeroSizedLayout`] if `T` is zero-sized and `iter` yields any elements.
+    #[track_caller]
+    pub fn from_iter_in<I: IntoIterator<Item = T>>(
+        iter: I,
+        alloc: A,
+    ) -> Result<OwnedBuf<T, A>, AllocError> {
+        let iter = iter.into_iter();
+        let mut buf = OwnedBuf::new_unallocated_in(alloc);
+        if let Err(e) = buf.expand_to_fit(iter.size_hint().0) {
+            buf.drop_and_dealloc();
+            return Err(e);
+        }
+        for val in iter {
+            if buf.init == buf.size {
+                // double so iterators with a bad size hint don't reallocate per element
+                if let Err(e) = buf.expand_to_fit((buf.size * 2).max(4)) {
+                    buf.drop_and_dealloc();
+                    return Err(e);
+                }
+            }
+            unsafe {
+                buf.init_next_unchecked(val);
+            }
+        }
+        Ok(buf)
+    }
+
     /// Breaks the owned buffer into its raw data.
     pub fn into_raw_parts(self) -> (NonNull<T>, usize, usize, A) {
         let me = ManuallyDrop::new(self);
@@ -154,6 +193,36 @@ impl<T, A: Alloc> OwnedBuf<T, A> {
         }
     }
 
+    /// Moves the buffer into `new_alloc`, keeping its capacity and initialized elements, and
+    /// deallocates the old buffer.
+    ///
+    /// This lets a buffer outlive the allocator it was built in, such as an arena, by moving it
+    /// somewhere longer-lived. The elements are moved bitwise, without being cloned or dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error along with the original buffer, untouched, if allocating in
+    /// `new_alloc` fails.
+    #[track_caller]
+    pub fn realloc_in<B: Alloc>(
+        self,
+        new_alloc: B,
+    ) -> Result<OwnedBuf<T, B>, (AllocError, OwnedBuf<T, A>)> {
+        if self.buf == NonNull::dangling() {
+            return Ok(OwnedBuf::new_unallocated_in(new_alloc));
+        }
+        let new_buf = match new_alloc.alloc_slice::<T>(self.size) {
+            Ok(ptr) => ptr.cast::<T>(),
+            Err(e) => return Err((e, self)),
+        };
+        let (buf, init, size, alloc) = self.into_raw_parts();
+        unsafe {
+            buf.copy_to_nonoverlapping(new_buf, init);
+            alloc.dealloc_n(buf, size);
+            Ok(OwnedBuf::from_raw_parts(new_buf, init, size, new_alloc))
+        }
+    }
+
     /// Returns the total number of elements in the buffer.
     #[inline]
     pub const fn size(&self) -> usize {
@@ -427,7 +496,43 @@ impl<T, A: Alloc> OwnedBuf<T, A> {
         self.init += 1;
     }
 
-    // TODO: add slice replacing and extending
+    // TODO: add slice replacing
+
+    /// Clones and appends every element of `slice`, growing at most once.
+    ///
+    /// Growth at least doubles the buffer's size, so repeated small extends take amortized
+    /// constant time per element. If a clone panics, the elements cloned before it stay
+    /// initialized.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::ArithmeticOverflow`] if the new length would overflow a `usize`.
+    ///
+    /// If growth was necessary:
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails.
+    /// - [`AllocError::LayoutError`] if the computed layout is invalid.
+    /// - [`AllocError::ZeroSizedLayout`] if `T` is zero-sized.
+    #[track_caller]
+    pub fn extend_from_slice(&mut self, slice: &[T]) -> Result<(), AllocError>
+    where
+        T: Clone,
+    {
+        let len = self
+            .init
+            .checked_add(slice.len())
+            .ok_or(AllocError::ArithmeticOverflow)?;
+        if len > self.size {
+            // double like `from_iter_in`, so repeated small extends don't copy every time
+            self.expand_to_fit(len.max(self.size.saturating_mul(2)))?;
+        }
+        for val in slice {
+            unsafe {
+                self.init_next_unchecked(val.clone());
+            }
+        }
+        Ok(())
+    }
 
     #[allow(clippy::type_complexity)]
     /// Attempts to insert `slice` at `idx`, growing if necessary.
@@ -605,6 +710,166 @@ impl<T, A: Alloc> OwnedBuf<T, A> {
         Ok(new_buf)
     }
 
+    /// Replaces the initialized elements in `range` with the elements of `replace_with`, like
+    /// [`Vec::splice`](alloc::vec::Vec::splice) but dropping the removed elements instead of
+    /// yielding them.
+    ///
+    /// Replacement elements are written straight into the gap the removed ones leave, so the
+    /// elements after the range are moved at most once while the iterator's
+    /// [`size_hint`](Iterator::size_hint) lower bound is accurate, plus once more for any elements
+    /// past it. The buffer is grown to exactly the size needed.
+    ///
+    /// If the iterator or a destructor panics, or growing fails, the buffer is left holding the
+    /// elements before the range, the replacements written so far, and the elements after the
+    /// range, in order. Removed elements are always dropped exactly once.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if growing the buffer fails.
+    /// - [`AllocError::LayoutError`] if the computed layout is invalid.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range starts after it ends or ends past the initialized elements.
+    #[track_caller]
+    pub fn splice<R: RangeBounds<usize>, I: IntoIterator<Item = T>>(
+        &mut self,
+        range: R,
+        replace_with: I,
+    ) -> Result<(), AllocError> {
+        let start = match range.start_bound() {
+            Bound::Included(&n) => n,
+            Bound::Excluded(&n) => n.checked_add(1).expect("range start overflowed"),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&n) => n.checked_add(1).expect("range end overflowed"),
+            Bound::Excluded(&n) => n,
+            Bound::Unbounded => self.init,
+        };
+        assert!(
+            start <= end,
+            "splice range starts at {start} but ends at {end}"
+        );
+        assert!(
+            end <= self.init,
+            "splice range end {end} is out of bounds for {} elements",
+            self.init
+        );
+
+        let tail_len = self.init - end;
+        self.init = start;
+        let mut gap = SpliceGap {
+            buf: self,
+            write: start,
+            tail_start: end,
+            tail_len,
+        };
+        unsafe {
+            NonNull::slice_from_raw_parts(gap.buf.buf.add(start), end - start).drop_in_place();
+        }
+
+        let mut iter = replace_with.into_iter();
+        loop {
+            // fill whatever space is left before the tail
+            while gap.write < gap.tail_start {
+                let Some(val) = iter.next() else {
+                    return Ok(());
+                };
+                unsafe { gap.buf.buf.add(gap.write).write(val) };
+                gap.write += 1;
+            }
+            let lower = iter.size_hint().0;
+            if lower == 0 {
+                break;
+            }
+            gap.widen(lower)?;
+        }
+
+        // the hint ran out, so collect the rest to move the tail only once more
+        let mut rest = iter.collect::<alloc::vec::Vec<T>>();
+        if !rest.is_empty() {
+            gap.widen(rest.len())?;
+            unsafe {
+                rest.as_ptr()
+                    .copy_to_nonoverlapping(gap.buf.buf.add(gap.write).as_ptr(), rest.len());
+                gap.write += rest.len();
+                rest.set_len(0);
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops every initialized element, keeping the allocated buffer.
+    ///
+    /// If a destructor panics, the remaining elements are still dropped and the buffer is left
+    /// empty.
+    #[track_caller]
+    #[inline]
+    pub fn clear(&mut self) {
+        let init = self.init;
+        // emptied first so a panicking destructor can't lead to elements being dropped twice
+        self.init = 0;
+        unsafe {
+            NonNull::slice_from_raw_parts(self.buf, init).drop_in_place();
+        }
+    }
+
+    /// Keeps only the initialized elements for which `f` returns `true`, dropping the rest in
+    /// place and shifting the survivors down to close the gaps. Their order is preserved.
+    ///
+    /// If `f` or a destructor panics, the buffer is left holding the elements kept so far followed
+    /// by every element not yet visited. The element whose destructor panicked counts as removed.
+    #[track_caller]
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, mut f: F) {
+        let mut c = Compactor::new(self, 0);
+        while c.read < c.len {
+            let cur = unsafe { c.base.add(c.read) };
+            if f(unsafe { cur.as_ref() }) {
+                c.keep(cur);
+            } else {
+                c.read += 1;
+                unsafe { cur.drop_in_place() };
+            }
+        }
+    }
+
+    /// Removes consecutive initialized elements which `same_bucket` considers equal, keeping the
+    /// first of each run.
+    ///
+    /// `same_bucket(a, b)` is passed the element being considered as `a` and the last kept element
+    /// as `b`. Panic safety matches that of [`retain`](OwnedBuf::retain).
+    #[track_caller]
+    pub fn dedup_by<F: FnMut(&mut T, &mut T) -> bool>(&mut self, mut same_bucket: F) {
+        if self.init <= 1 {
+            return;
+        }
+        let mut c = Compactor::new(self, 1);
+        while c.read < c.len {
+            let (cur, mut prev) = unsafe { (c.base.add(c.read), c.base.add(c.write - 1)) };
+            // `cur` is never `prev`, as `write` never passes `read`
+            if same_bucket(unsafe { &mut *cur.as_ptr() }, unsafe { prev.as_mut() }) {
+                c.read += 1;
+                unsafe { cur.drop_in_place() };
+            } else {
+                c.keep(cur);
+            }
+        }
+    }
+
+    /// Removes consecutive equal initialized elements, keeping the first of each run.
+    ///
+    /// If a comparison or destructor panics, the buffer is left as described for
+    /// [`retain`](OwnedBuf::retain).
+    #[track_caller]
+    #[inline]
+    pub fn dedup(&mut self)
+    where
+        T: PartialEq,
+    {
+        self.dedup_by(|a, b| a == b);
+    }
+
     /// Gets a pointer to the element at the given `idx` if it is initialized.
     #[inline]
     pub const fn get_ptr(&self, idx: usize) -> Option<NonNull<T>> {
@@ -1126,6 +1391,51 @@ impl<T, A: Alloc> OwnedBuf<T, A> {
         unsafe { slice::from_raw_parts_mut(self.buf.as_ptr().cast(), self.size) }
     }
 
+    /// Gets a raw pointer to the start of the buffer, like [`Vec::as_ptr`](alloc::vec::Vec).
+    ///
+    /// The pointer is dangling but non-null if the buffer is unallocated, and is invalidated by
+    /// anything which reallocates the buffer.
+    #[inline]
+    pub const fn as_ptr(&self) -> *const T {
+        self.buf.as_ptr()
+    }
+
+    /// Gets a raw mutable pointer to the start of the buffer, like
+    /// [`Vec::as_mut_ptr`](alloc::vec::Vec).
+    ///
+    /// The pointer is dangling but non-null if the buffer is unallocated, and is invalidated by
+    /// anything which reallocates the buffer.
+    #[inline]
+    pub const fn as_mut_ptr(&mut self) -> *mut T {
+        self.buf.as_ptr()
+    }
+
+    /// Gets a mutable slice of the spare capacity past the initialized elements, like
+    /// [`Vec::spare_capacity_mut`](alloc::vec::Vec).
+    ///
+    /// This allows filling the buffer directly, for example by reading into it, then committing
+    /// the filled elements with [`set_len`](OwnedBuf::set_len). The slice holds exactly
+    /// `size() - initialized()` elements.
+    #[inline]
+    pub const fn spare_capacity_mut(&mut self) -> &mut [MaybeUninit<T>] {
+        self.uninit_buf_mut()
+    }
+
+    /// Sets the number of initialized elements, like [`Vec::set_len`](alloc::vec::Vec).
+    ///
+    /// # Safety
+    ///
+    /// - `len` must not exceed [`size`](OwnedBuf::size).
+    /// - The elements at `initialized()..len` must have been initialized, usually through
+    ///   [`spare_capacity_mut`](OwnedBuf::spare_capacity_mut), before calling this.
+    /// - If `len` is less than [`initialized`](OwnedBuf::initialized), the elements past it are
+    ///   forgotten rather than dropped.
+    #[inline]
+    pub const unsafe fn set_len(&mut self, len: usize) {
+        debug_assert!(len <= self.size, "`len` exceeds the buffer's size");
+        self.init = len;
+    }
+
     /// Gets a pointer to the initialized portion of the buffer if it exists. Otherwise, gets an
     /// uninitialized pointer to the entire buffer.
     ///
@@ -1282,6 +1592,105 @@ impl<T, A: Alloc> BorrowMut<[MaybeUninit<T>]> for OwnedBuf<T, A> {
     }
 }
 
+/// Compacts an [`OwnedBuf`]'s elements toward its start, tracking the elements kept so far
+/// (`..write`) and the ones yet to be visited (`read..len`).
+///
+/// The buffer is marked empty while this is alive. Dropping it, whether normally or during a
+/// panic, shifts the unvisited elements down behind the kept ones and restores the length.
+struct Compactor<'b, T> {
+    base: NonNull<T>,
+    init: &'b mut usize,
+    read: usize,
+    write: usize,
+    len: usize,
+}
+
+impl<'b, T> Compactor<'b, T> {
+    /// Starts compacting `buf`, treating its first `start` elements as already kept.
+    fn new<A: Alloc>(buf: &'b mut OwnedBuf<T, A>, start: usize) -> Compactor<'b, T> {
+        let len = core::mem::replace(&mut buf.init, 0);
+        Compactor {
+            base: buf.buf,
+            init: &mut buf.init,
+            read: start,
+            write: start,
+            len,
+        }
+    }
+
+    /// Keeps the element at `read`, which is `cur`, moving it down to `write`.
+    fn keep(&mut self, cur: NonNull<T>) {
+        if self.read != self.write {
+            unsafe { cur.copy_to_nonoverlapping(self.base.add(self.write), 1) };
+        }
+        self.read += 1;
+        self.write += 1;
+    }
+}
+
+impl<T> Drop for Compactor<'_, T> {
+    fn drop(&mut self) {
+        let rest = self.len - self.read;
+        if self.read != self.write {
+            unsafe {
+                self.base
+                    .add(self.read)
+                    .copy_to(self.base.add(self.write), rest);
+            }
+        }
+        *self.init = self.write + rest;
+    }
+}
+
+/// The gap left in an [`OwnedBuf`] during a splice, between the replacements written so far and
+/// the elements after the spliced range. Dropping it closes the gap.
+struct SpliceGap<'b, T, A: Alloc> {
+    buf: &'b mut OwnedBuf<T, A>,
+    write: usize,
+    tail_start: usize,
+    tail_len: usize,
+}
+
+impl<T, A: Alloc> SpliceGap<'_, T, A> {
+    /// Grows the buffer if needed and moves the tail up so `extra` more elements fit in the gap.
+    fn widen(&mut self, extra: usize) -> Result<(), AllocError> {
+        let tail_end = self.tail_start + self.tail_len;
+        let needed = self
+            .write
+            .checked_add(extra)
+            .and_then(|n| n.checked_add(self.tail_len))
+            .ok_or(AllocError::ArithmeticOverflow)?;
+        // growing copies only the initialized prefix, so count the gap as part of it meanwhile
+        let start = core::mem::replace(&mut self.buf.init, tail_end);
+        let res = self.buf.expand_to_fit(needed);
+        self.buf.init = start;
+        res?;
+        let new_start = self.write + extra;
+        unsafe {
+            self.buf
+                .buf
+                .add(self.tail_start)
+                .copy_to(self.buf.buf.add(new_start), self.tail_len);
+        }
+        self.tail_start = new_start;
+        Ok(())
+    }
+}
+
+impl<T, A: Alloc> Drop for SpliceGap<'_, T, A> {
+    fn drop(&mut self) {
+        if self.tail_start != self.write {
+            unsafe {
+                self.buf
+                    .buf
+                    .add(self.tail_start)
+                    .copy_to(self.buf.buf.add(self.write), self.tail_len);
+            }
+        }
+        self.buf.init = self.write + self.tail_len;
+    }
+}
+
 macro_rules! spec_impl {
     ($($extra_token:tt)?) => {
         impl<T: Clone, A: Alloc + Default> From<&[T]> for OwnedBuf<T, A> {
@@ -1330,6 +1739,21 @@ impl<T: Copy, A: Alloc + Default> From<&mut [T]> for OwnedBuf<T, A> {
     }
 }
 
+impl<T, A: Alloc + Default> FromIterator<T> for OwnedBuf<T, A> {
+    /// Collects `iter` into a buffer in the default allocator, like
+    /// [`from_iter_in`](OwnedBuf::from_iter_in).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `from_iter_in` fails, including when `T` is zero-sized and `iter` yields any
+    /// elements.
+    #[track_caller]
+    #[inline]
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> OwnedBuf<T, A> {
+        OwnedBuf::from_iter_in(iter, A::default()).expect("`<FromIterator<T>>::from_iter` failed")
+    }
+}
+
 /// An unowned buffer of multiple `T`.
 pub struct Buf<'s, T> {
     /// The buffer.
@@ -1504,3 +1928,171 @@ impl<T> Buf<'_, T> {
         }
     }
 }
+
+/// The block shared by the clones of an [`AllocArc`].
+#[repr(C)]
+struct ArcInner<T, A> {
+    strong: AtomicUsize,
+    alloc: A,
+    value: T,
+}
+
+/// A thread-safe reference-counted pointer, like [`Arc`](alloc::sync::Arc), allocated using `A`.
+///
+/// The reference count, the allocator, and the value share a single block. Cloning an
+/// `AllocArc` only bumps the count, and dropping the last clone drops the value, then frees the
+/// block through the allocator stored in it. This gives `Arc` semantics over a pool or arena
+/// rather than the global heap; with `A = &P`, the pointers borrow the allocator `P` and can't
+/// outlive it.
+pub struct AllocArc<T, A: Alloc = DefaultAlloc> {
+    ptr: NonNull<ArcInner<T, A>>,
+    _marker: PhantomData<ArcInner<T, A>>,
+}
+
+// SAFETY: like `Arc`, the value is shared between threads and dropped on whichever drops last,
+// and so is the allocator, which also frees the block from that thread.
+unsafe impl<T: Send + Sync, A: Alloc + Send + Sync> Send for AllocArc<T, A> {}
+// SAFETY: see above.
+unsafe impl<T: Send + Sync, A: Alloc + Send + Sync> Sync for AllocArc<T, A> {}
+
+impl<T> AllocArc<T> {
+    /// Creates a new reference-counted `value` in the default allocator.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails. `value` is dropped in this case.
+    #[track_caller]
+    #[inline]
+    pub fn new(value: T) -> Result<AllocArc<T>, AllocError> {
+        AllocArc::new_in(value, DefaultAlloc)
+    }
+}
+
+impl<T, A: Alloc> AllocArc<T, A> {
+    /// Creates a new reference-counted `value`, allocated using `alloc`.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if allocation fails. `value` and `alloc` are dropped in this
+    ///   case.
+    #[track_caller]
+    pub fn new_in(value: T, alloc: A) -> Result<AllocArc<T, A>, AllocError> {
+        let ptr = alloc.alloc(Layout::new::<ArcInner<T, A>>())?.cast();
+        unsafe {
+            ptr.write(ArcInner {
+                strong: AtomicUsize::new(1),
+                alloc,
+                value,
+            });
+        }
+        Ok(AllocArc {
+            ptr,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Returns the number of pointers sharing this value.
+    #[must_use]
+    #[inline]
+    pub fn strong_count(this: &AllocArc<T, A>) -> usize {
+        this.inner().strong.load(Acquire)
+    }
+
+    /// Returns `true` if both pointers share the same value.
+    #[must_use]
+    #[inline]
+    pub fn ptr_eq(this: &AllocArc<T, A>, other: &AllocArc<T, A>) -> bool {
+        this.ptr == other.ptr
+    }
+
+    /// Returns the allocator the value was allocated with.
+    #[must_use]
+    #[inline]
+    pub fn allocator(this: &AllocArc<T, A>) -> &A {
+        &this.inner().alloc
+    }
+
+    /// Returns the value if `this` is its only pointer, freeing the block. Otherwise, returns
+    /// `this` unchanged.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err(this)` if the value is shared.
+    pub fn try_unwrap(this: AllocArc<T, A>) -> Result<T, AllocArc<T, A>> {
+        if this
+            .inner()
+            .strong
+            .compare_exchange(1, 0, Acquire, Relaxed)
+            .is_err()
+        {
+            return Err(this);
+        }
+        let this = ManuallyDrop::new(this);
+        unsafe {
+            let inner = this.ptr.as_ptr();
+            let value = ptr::read(&raw const (*inner).value);
+            let alloc = ptr::read(&raw const (*inner).alloc);
+            alloc.dealloc(this.ptr.cast(), Layout::new::<ArcInner<T, A>>());
+            Ok(value)
+        }
+    }
+
+    #[inline]
+    const fn inner(&self) -> &ArcInner<T, A> {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T, A: Alloc> Clone for AllocArc<T, A> {
+    #[inline]
+    fn clone(&self) -> AllocArc<T, A> {
+        // new pointers are made from existing ones, so no ordering is needed, as with `Arc`
+        let old = self.inner().strong.fetch_add(1, Relaxed);
+        assert!(
+            old <= isize::MAX as usize,
+            "`AllocArc` reference count overflowed"
+        );
+        AllocArc {
+            ptr: self.ptr,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, A: Alloc> Deref for AllocArc<T, A> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.inner().value
+    }
+}
+
+impl<T, A: Alloc> AsRef<T> for AllocArc<T, A> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+impl<T: Debug, A: Alloc> Debug for AllocArc<T, A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+impl<T, A: Alloc> Drop for AllocArc<T, A> {
+    fn drop(&mut self) {
+        if self.inner().strong.fetch_sub(1, Release) != 1 {
+            return;
+        }
+        // synchronize with every other pointer's release before dropping the value
+        fence(Acquire);
+        unsafe {
+            let inner = self.ptr.as_ptr();
+            let alloc = ptr::read(&raw const (*inner).alloc);
+            ptr::drop_in_place(&raw mut (*inner).value);
+            alloc.dealloc(self.ptr.cast(), Layout::new::<ArcInner<T, A>>());
+        }
+    }
+}
@@ -2,17 +2,21 @@ use crate::{Alloc, AllocError, stats::AllocRes::{Fail, Succ}, DefaultAlloc};
 use alloc::{alloc::Layout, boxed::Box, format, rc::Rc, string::ToString, sync::Arc};
 use core::{
     fmt::{self, Display, Formatter},
+    panic::Location,
     ptr::{NonNull, null_mut},
     sync::atomic::{
         AtomicUsize,
-        Ordering::{Acquire, Release},
+        Ordering::{AcqRel, Acquire, Relaxed, Release},
     },
 };
 #[cfg(feature = "std")]
 use std::{
+    backtrace::Backtrace,
+    collections::{BTreeMap, HashMap},
     fs::File,
     io::{Stdout, Write, stdout},
-    sync::{Mutex, MutexGuard},
+    sync::{Mutex, MutexGuard, PoisonError},
+    vec::Vec,
 };
 
 /// A wrapper that delegates all `Alloc` calls to `A` and logs
@@ -77,6 +81,106 @@ impl StatsLogger for AtomicUsize {
     atomic_total_ops!(self);
 }
 
+/// A logger which counts the bytes allocated and the failed operations, without logging each
+/// one.
+///
+/// It also records the peak of the bytes allocated, which a [`Stats`] using it reports through
+/// [`AllocPeak`].
+#[derive(Debug, Default)]
+pub struct CountingLog {
+    /// The total number of bytes allocated.
+    pub total: AtomicUsize,
+    /// The number of failed allocations and resizes.
+    pub failures: AtomicUsize,
+    /// The highest `total` has reached since creation or the last peak reset.
+    pub peak: AtomicUsize,
+}
+
+impl CountingLog {
+    /// Creates a new [`CountingLog`] with every count at zero.
+    #[must_use]
+    #[inline]
+    pub const fn new() -> CountingLog {
+        CountingLog {
+            total: AtomicUsize::new(0),
+            failures: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl StatsLogger for CountingLog {
+    fn log(&self, _stat: AllocRes) {}
+
+    #[inline]
+    fn inc_total_bytes_allocated(&self, bytes: usize) -> usize {
+        let res = self.total.fetch_add(bytes, AcqRel) + bytes;
+        self.peak.fetch_max(res, AcqRel);
+        res
+    }
+
+    #[inline]
+    fn dec_total_bytes_allocated(&self, bytes: usize) -> usize {
+        self.total.fetch_sub(bytes, AcqRel) - bytes
+    }
+
+    #[inline]
+    fn total(&self) -> usize {
+        self.total.load(Acquire)
+    }
+
+    #[inline]
+    fn inc_failures(&self) -> u64 {
+        (self.failures.fetch_add(1, AcqRel) + 1) as u64
+    }
+
+    #[inline]
+    fn alloc_failures(&self) -> u64 {
+        self.failures.load(Acquire) as u64
+    }
+}
+
+/// An allocator which records the high-water mark of the bytes it has live.
+///
+/// This lets monitoring code measure the peak memory of each phase of work, like a request, the
+/// same way for any instrumented allocator: reset the peak when the phase starts and read it when
+/// it ends.
+pub trait AllocPeak {
+    /// Returns the most bytes which have been live at once since creation or the last
+    /// [`reset_peak`](AllocPeak::reset_peak).
+    fn peak_bytes(&self) -> usize;
+
+    /// Lowers the peak to the bytes currently live.
+    ///
+    /// Allocations made concurrently with the reset are never lost from the new peak, though one
+    /// both made and freed during it may be.
+    fn reset_peak(&self);
+}
+
+/// Resets `peak` to `live`, keeping any higher peak recorded concurrently.
+fn reset_peak_to_live(peak: &AtomicUsize, live: impl Fn() -> usize) {
+    let mut old = peak.load(Acquire);
+    // a failed exchange means an allocation raised the peak meanwhile, so retry with its total
+    while let Err(raised) = peak.compare_exchange_weak(old, live(), AcqRel, Acquire) {
+        old = raised;
+    }
+    // allocations which added to `live` after it was read record their own peak, but one which
+    // added before and found the old, higher peak didn't
+    peak.fetch_max(live(), AcqRel);
+}
+
+impl<A> AllocPeak for Stats<A, CountingLog> {
+    #[inline]
+    fn peak_bytes(&self) -> usize {
+        self.1.peak.load(Acquire)
+    }
+
+    #[inline]
+    fn reset_peak(&self) {
+        reset_peak_to_live(&self.1.peak, || self.1.total.load(Acquire));
+    }
+}
+
 #[cfg(feature = "std")]
 // file stat-only logger (no byte-count)
 impl StatsLogger for Mutex<File> {
@@ -113,6 +217,12 @@ macro_rules! delegate_logger {
             fn total(&self) -> usize {
                 (**self).total()
             }
+            fn inc_failures(&self) -> u64 {
+                (**self).inc_failures()
+            }
+            fn alloc_failures(&self) -> u64 {
+                (**self).alloc_failures()
+            }
         }
     };
 }
@@ -299,6 +409,22 @@ pub trait StatsLogger {
 
     /// Returns the total number of bytes allocated.
     fn total(&self) -> usize;
+
+    /// Records a failed allocation or resize and returns the new failure count.
+    ///
+    /// Loggers which don't count failures ignore this and return 0.
+    #[inline]
+    fn inc_failures(&self) -> u64 {
+        0
+    }
+
+    /// Returns the number of failed allocations and resizes recorded.
+    ///
+    /// A wrapped allocator which fails often, even if a fallback recovers, is likely undersized.
+    #[inline]
+    fn alloc_failures(&self) -> u64 {
+        0
+    }
 }
 
 /// The result of an allocation operation, containing statistics on the operation.
@@ -509,6 +635,7 @@ fn allocate<A: Alloc, L: StatsLogger, F: Fn(&A, Layout) -> Result<NonNull<u8>, A
             Ok(ptr)
         }
         Err(e) => {
+            slf.1.inc_failures();
             slf.1.log(Fail(AllocStat::Alloc {
                 region: MemoryRegion {
                     ptr: null_mut(),
@@ -553,6 +680,7 @@ fn grow<
             Ok(new_ptr)
         }
         Err(e) => {
+            slf.1.inc_failures();
             slf.1.log(Fail(AllocStat::new_realloc(
                 ptr,
                 null_mut(),
@@ -617,6 +745,17 @@ impl<A: Alloc, L: StatsLogger> Alloc for Stats<A, L> {
         }));
     }
 
+    #[track_caller]
+    #[inline]
+    fn reserve(&self, bytes: usize) -> Result<(), AllocError> {
+        self.0.reserve(bytes)
+    }
+
+    #[inline]
+    fn granularity(&self) -> usize {
+        self.0.granularity()
+    }
+
     #[track_caller]
     unsafe fn grow(
         &self,
@@ -710,6 +849,7 @@ impl<A: Alloc, L: StatsLogger> Alloc for Stats<A, L> {
                 Ok(new_ptr)
             }
             Err(e) => {
+                self.1.inc_failures();
                 self.1.log(Fail(AllocStat::new_realloc(
                     ptr,
                     null_mut(),
@@ -723,3 +863,931 @@ impl<A: Alloc, L: StatsLogger> Alloc for Stats<A, L> {
         }
     }
 }
+
+/// A live allocation recorded by a [`SnapshotAlloc`].
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotEntry {
+    /// The order in which the allocation was made, unique within its allocator.
+    pub serial: u64,
+    /// The allocation's address.
+    pub addr: usize,
+    /// The allocation's size in bytes.
+    pub size: usize,
+    /// Where the allocation was made.
+    pub caller: &'static Location<'static>,
+}
+
+/// An owned list of the allocations which were live when it was taken, sorted by address.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    entries: Vec<SnapshotEntry>,
+}
+
+#[cfg(feature = "std")]
+impl Snapshot {
+    /// Returns the recorded allocations.
+    #[must_use]
+    #[inline]
+    pub fn entries(&self) -> &[SnapshotEntry] {
+        &self.entries
+    }
+
+    /// Returns the total size in bytes of the recorded allocations.
+    #[must_use]
+    pub fn total_bytes(&self) -> usize {
+        self.entries.iter().map(|e| e.size).sum()
+    }
+
+    /// Computes the allocations which were made or freed between snapshot `a` and snapshot `b`.
+    ///
+    /// Both snapshots should be taken from the same allocator. An address which was freed and
+    /// reused in between shows up as both removed and added.
+    #[must_use]
+    pub fn diff(a: &Snapshot, b: &Snapshot) -> SnapshotDiff {
+        // entries are sorted by address, and a serial identifies one allocation
+        let missing_from = |snap: &Snapshot, e: &SnapshotEntry| {
+            snap.entries
+                .binary_search_by_key(&e.addr, |other| other.addr)
+                .map_or(true, |i| snap.entries[i].serial != e.serial)
+        };
+        let added = b
+            .entries
+            .iter()
+            .filter(|e| missing_from(a, e))
+            .copied()
+            .collect();
+        let removed = a
+            .entries
+            .iter()
+            .filter(|e| missing_from(b, e))
+            .copied()
+            .collect();
+        SnapshotDiff { added, removed }
+    }
+}
+
+/// The allocations made and freed between two [`Snapshot`]s.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotDiff {
+    /// Allocations live in the later snapshot but not the earlier one.
+    pub added: Vec<SnapshotEntry>,
+    /// Allocations live in the earlier snapshot but not the later one.
+    pub removed: Vec<SnapshotEntry>,
+}
+
+#[cfg(feature = "std")]
+impl SnapshotDiff {
+    /// Returns `true` if no allocations were made or freed.
+    #[must_use]
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// A wrapper that delegates all `Alloc` calls to `A`, tracking the live allocations and where
+/// they were made so [`Snapshot`]s of them can be taken.
+///
+/// Taking a snapshot before and after an operation and [`diff`](Snapshot::diff)ing them shows
+/// exactly what the operation left allocated.
+#[cfg(feature = "std")]
+pub struct SnapshotAlloc<A> {
+    inner: A,
+    live: Mutex<(u64, BTreeMap<usize, SnapshotEntry>)>,
+}
+
+#[cfg(feature = "std")]
+impl SnapshotAlloc<DefaultAlloc> {
+    /// Creates a new snapshotting allocator over the default allocator.
+    #[must_use]
+    #[inline]
+    pub const fn new() -> SnapshotAlloc<DefaultAlloc> {
+        SnapshotAlloc::new_in(DefaultAlloc)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for SnapshotAlloc<DefaultAlloc> {
+    #[inline]
+    fn default() -> SnapshotAlloc<DefaultAlloc> {
+        SnapshotAlloc::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A> SnapshotAlloc<A> {
+    /// Creates a new snapshotting allocator over `inner`.
+    #[must_use]
+    #[inline]
+    pub const fn new_in(inner: A) -> SnapshotAlloc<A> {
+        SnapshotAlloc {
+            inner,
+            live: Mutex::new((0, BTreeMap::new())),
+        }
+    }
+
+    /// Returns a reference to the underlying allocator.
+    #[must_use]
+    #[inline]
+    pub const fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Takes a snapshot of the currently live allocations.
+    #[must_use]
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            entries: self.lock().1.values().copied().collect(),
+        }
+    }
+
+    #[inline]
+    fn lock(&self) -> MutexGuard<'_, (u64, BTreeMap<usize, SnapshotEntry>)> {
+        self.live.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[track_caller]
+    fn track(
+        &self,
+        res: Result<NonNull<u8>, AllocError>,
+        layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        if let Ok(ptr) = res {
+            let caller = Location::caller();
+            let mut live = self.lock();
+            let serial = live.0;
+            live.0 += 1;
+            let addr = ptr.addr().get();
+            live.1.insert(
+                addr,
+                SnapshotEntry {
+                    serial,
+                    addr,
+                    size: layout.size(),
+                    caller,
+                },
+            );
+        }
+        res
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A: Alloc> Alloc for SnapshotAlloc<A> {
+    #[track_caller]
+    #[inline]
+    fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.track(self.inner.alloc(layout), layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.track(self.inner.alloc_zeroed(layout), layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+        self.track(self.inner.alloc_filled(layout, n), layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_patterned<F: Fn(usize) -> u8 + Clone>(
+        &self,
+        layout: Layout,
+        pattern: F,
+    ) -> Result<NonNull<u8>, AllocError> {
+        self.track(self.inner.alloc_patterned(layout, pattern), layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.lock().1.remove(&ptr.addr().get());
+        unsafe {
+            self.inner.dealloc(ptr, layout);
+        }
+    }
+
+    #[track_caller]
+    #[inline]
+    fn reserve(&self, bytes: usize) -> Result<(), AllocError> {
+        self.inner.reserve(bytes)
+    }
+
+    #[inline]
+    fn granularity(&self) -> usize {
+        self.inner.granularity()
+    }
+}
+
+/// A watermark crossed by a [`WatermarkAlloc`]'s live bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watermark {
+    /// Live bytes rose above the high watermark.
+    High,
+    /// Live bytes fell below the low watermark, after having been above the high one.
+    Low,
+}
+
+/// A wrapper that delegates all `Alloc` calls to `A`, tracking the bytes it has live and calling
+/// `on_cross` when they cross a watermark, so callers can shed load as memory fills.
+///
+/// Crossings are edge-triggered: `on_cross` is called once with [`Watermark::High`] when live
+/// bytes rise above `high`, then not again until they've fallen below `low` and it's been called
+/// with [`Watermark::Low`]. The gap between the watermarks keeps usage hovering around one of them
+/// from firing repeatedly. The callback is also given the live byte count which crossed.
+///
+/// The callback runs on whichever thread made the crossing allocation or deallocation, and may be
+/// called concurrently with allocations on other threads. Whether an operation crosses is decided
+/// atomically with its update of the live bytes, so crossings always alternate between `High` and
+/// `Low`, and [`is_high`](WatermarkAlloc::is_high) never disagrees with the last crossing. The
+/// callback for a crossing is made after the fact, though, so if the next crossing happens on
+/// another thread before it returns, the two calls can overlap or finish in either order. Resizes
+/// count as an allocation of the new block followed by freeing the old one.
+///
+/// Live bytes are counted up to `isize::MAX`, the same limit as the size of a single allocation.
+///
+/// The peak of the live bytes is also recorded, and can be read and reset through [`AllocPeak`].
+pub struct WatermarkAlloc<A, F: Fn(Watermark, usize)> {
+    inner: A,
+    low: usize,
+    high: usize,
+    on_cross: F,
+    /// The live bytes, shifted left by one, with the low bit set while above the high watermark.
+    state: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl<A, F: Fn(Watermark, usize)> WatermarkAlloc<A, F> {
+    /// Creates a new watermark-tracking allocator over `inner`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `low` is greater than `high`.
+    #[track_caller]
+    #[must_use]
+    #[inline]
+    pub const fn new_in(inner: A, low: usize, high: usize, on_cross: F) -> WatermarkAlloc<A, F> {
+        assert!(
+            low <= high,
+            "the low watermark must not be above the high one"
+        );
+        WatermarkAlloc {
+            inner,
+            low,
+            high,
+            on_cross,
+            state: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns a reference to the underlying allocator.
+    #[must_use]
+    #[inline]
+    pub const fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Returns the number of bytes currently allocated through this allocator.
+    #[must_use]
+    #[inline]
+    pub fn live_bytes(&self) -> usize {
+        self.state.load(Relaxed) >> 1
+    }
+
+    /// Returns `true` if live bytes have risen above the high watermark and not yet fallen below
+    /// the low one.
+    #[must_use]
+    #[inline]
+    pub fn is_high(&self) -> bool {
+        self.state.load(Acquire) & 1 != 0
+    }
+
+    /// Applies `f` to the live bytes, and returns the new count and the watermark crossed, if
+    /// any. The crossing is decided in the same exchange as the update, so exactly one thread
+    /// reports each crossing, and no other thread can cross back in between.
+    fn update(&self, f: impl Fn(usize) -> usize) -> (usize, Option<Watermark>) {
+        let step = |state: usize| {
+            let live = f(state >> 1);
+            let was_above = state & 1 != 0;
+            let above = if was_above {
+                live >= self.low
+            } else {
+                live > self.high
+            };
+            (live, was_above, above)
+        };
+        let (Ok(old) | Err(old)) = self.state.fetch_update(AcqRel, Acquire, |state| {
+            let (live, _, above) = step(state);
+            Some(live << 1 | usize::from(above))
+        });
+        let (live, was_above, above) = step(old);
+        let crossed = match (was_above, above) {
+            (false, true) => Some(Watermark::High),
+            (true, false) => Some(Watermark::Low),
+            _ => None,
+        };
+        (live, crossed)
+    }
+
+    fn track(
+        &self,
+        res: Result<NonNull<u8>, AllocError>,
+        layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        if res.is_ok() {
+            let (live, crossed) = self.update(|live| live + layout.size());
+            self.peak.fetch_max(live, AcqRel);
+            if let Some(mark) = crossed {
+                (self.on_cross)(mark, live);
+            }
+        }
+        res
+    }
+}
+
+impl<A, F: Fn(Watermark, usize)> AllocPeak for WatermarkAlloc<A, F> {
+    #[inline]
+    fn peak_bytes(&self) -> usize {
+        self.peak.load(Acquire)
+    }
+
+    #[inline]
+    fn reset_peak(&self) {
+        reset_peak_to_live(&self.peak, || self.live_bytes());
+    }
+}
+
+impl<A: Alloc, F: Fn(Watermark, usize)> Alloc for WatermarkAlloc<A, F> {
+    #[track_caller]
+    #[inline]
+    fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.track(self.inner.alloc(layout), layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.track(self.inner.alloc_zeroed(layout), layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+        self.track(self.inner.alloc_filled(layout, n), layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_patterned<P: Fn(usize) -> u8 + Clone>(
+        &self,
+        layout: Layout,
+        pattern: P,
+    ) -> Result<NonNull<u8>, AllocError> {
+        self.track(self.inner.alloc_patterned(layout, pattern), layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe {
+            self.inner.dealloc(ptr, layout);
+        }
+        let (live, crossed) = self.update(|live| live - layout.size());
+        if let Some(mark) = crossed {
+            (self.on_cross)(mark, live);
+        }
+    }
+
+    #[track_caller]
+    #[inline]
+    fn reserve(&self, bytes: usize) -> Result<(), AllocError> {
+        self.inner.reserve(bytes)
+    }
+
+    #[inline]
+    fn granularity(&self) -> usize {
+        self.inner.granularity()
+    }
+}
+
+/// A wrapper that delegates all `Alloc` calls to `A`, reporting only the allocations of at least
+/// `threshold` bytes.
+///
+/// Logging every allocation is too slow and noisy to leave on in production, but the few very
+/// large allocations are usually the ones worth looking at. Each successful allocation at or above
+/// the threshold calls `on_whale` with its layout and the location it was made from; smaller ones
+/// cost a single comparison. Resizes report the new block if it's large enough.
+pub struct WhaleAlloc<A, F: Fn(Layout, &'static Location<'static>)> {
+    inner: A,
+    threshold: usize,
+    on_whale: F,
+    count: AtomicUsize,
+    bytes: AtomicUsize,
+}
+
+impl<A, F: Fn(Layout, &'static Location<'static>)> WhaleAlloc<A, F> {
+    /// Creates a new whale-reporting allocator over `inner`, calling `on_whale` for allocations
+    /// of at least `threshold` bytes.
+    #[must_use]
+    #[inline]
+    pub const fn new_in(inner: A, threshold: usize, on_whale: F) -> WhaleAlloc<A, F> {
+        WhaleAlloc {
+            inner,
+            threshold,
+            on_whale,
+            count: AtomicUsize::new(0),
+            bytes: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns a reference to the underlying allocator.
+    #[must_use]
+    #[inline]
+    pub const fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Returns the size in bytes at and above which allocations are reported.
+    #[must_use]
+    #[inline]
+    pub const fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Returns the number of allocations which have been reported.
+    #[must_use]
+    #[inline]
+    pub fn whales(&self) -> usize {
+        self.count.load(Relaxed)
+    }
+
+    /// Returns the total size in bytes of the allocations which have been reported, including
+    /// ones since freed.
+    #[must_use]
+    #[inline]
+    pub fn whale_bytes(&self) -> usize {
+        self.bytes.load(Relaxed)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn track(
+        &self,
+        res: Result<NonNull<u8>, AllocError>,
+        layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        if res.is_ok() && layout.size() >= self.threshold {
+            self.count.fetch_add(1, Relaxed);
+            self.bytes.fetch_add(layout.size(), Relaxed);
+            (self.on_whale)(layout, Location::caller());
+        }
+        res
+    }
+}
+
+impl<A: Alloc, F: Fn(Layout, &'static Location<'static>)> Alloc for WhaleAlloc<A, F> {
+    #[track_caller]
+    #[inline]
+    fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.track(self.inner.alloc(layout), layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.track(self.inner.alloc_zeroed(layout), layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+        self.track(self.inner.alloc_filled(layout, n), layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_patterned<P: Fn(usize) -> u8 + Clone>(
+        &self,
+        layout: Layout,
+        pattern: P,
+    ) -> Result<NonNull<u8>, AllocError> {
+        self.track(self.inner.alloc_patterned(layout, pattern), layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe {
+            self.inner.dealloc(ptr, layout);
+        }
+    }
+
+    #[track_caller]
+    #[inline]
+    fn reserve(&self, bytes: usize) -> Result<(), AllocError> {
+        self.inner.reserve(bytes)
+    }
+
+    #[inline]
+    fn granularity(&self) -> usize {
+        self.inner.granularity()
+    }
+}
+
+/// A wrapper that delegates all `Alloc` calls to `A`, accumulating the bytes allocated under each
+/// distinct call stack into a profile which can be rendered as a flame graph.
+///
+/// [`write_folded`](FoldedStackAlloc::write_folded) emits the profile in the collapsed-stack
+/// format read by `inferno` and `FlameGraph`: one `outer;...;inner bytes` line per stack.
+///
+/// Capturing a backtrace is slow, so only every `sample_every`-th allocation is captured, and its
+/// size is scaled by the rate so the totals still estimate all allocations. Frames are only
+/// named if debug info is available. Deallocations aren't subtracted; the profile shows where
+/// bytes were allocated, not what's live.
+#[cfg(feature = "std")]
+pub struct FoldedStackAlloc<A> {
+    inner: A,
+    sample_every: usize,
+    max_depth: usize,
+    seen: AtomicUsize,
+    stacks: Mutex<HashMap<String, usize>>,
+}
+
+#[cfg(feature = "std")]
+impl<A> FoldedStackAlloc<A> {
+    /// Creates a new profiling allocator over `inner`, capturing one in every `sample_every`
+    /// allocations. A rate of zero is treated as one.
+    #[must_use]
+    #[inline]
+    pub fn new_in(inner: A, sample_every: usize) -> FoldedStackAlloc<A> {
+        FoldedStackAlloc {
+            inner,
+            sample_every: sample_every.max(1),
+            max_depth: 64,
+            seen: AtomicUsize::new(0),
+            stacks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Limits captured stacks to their innermost `max_depth` frames. Defaults to 64.
+    #[must_use]
+    #[inline]
+    pub const fn with_max_depth(mut self, max_depth: usize) -> FoldedStackAlloc<A> {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Returns a reference to the underlying allocator.
+    #[must_use]
+    #[inline]
+    pub const fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Returns the estimated total bytes allocated across all recorded stacks.
+    #[must_use]
+    pub fn total_bytes(&self) -> usize {
+        self.lock().values().sum()
+    }
+
+    /// Writes the profile in collapsed-stack format, one stack per line, sorted by stack.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from writing to `w`.
+    pub fn write_folded<W: Write>(&self, mut w: W) -> std::io::Result<()> {
+        let mut stacks: Vec<(String, usize)> =
+            self.lock().iter().map(|(s, &b)| (s.clone(), b)).collect();
+        stacks.sort_unstable();
+        for (stack, bytes) in stacks {
+            writeln!(w, "{stack} {bytes}")?;
+        }
+        Ok(())
+    }
+
+    /// Clears the recorded profile.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    #[inline]
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, usize>> {
+        self.stacks.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn sample(
+        &self,
+        res: Result<NonNull<u8>, AllocError>,
+        layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        if res.is_ok()
+            && self
+                .seen
+                .fetch_add(1, Relaxed)
+                .is_multiple_of(self.sample_every)
+        {
+            let stack = fold(&Backtrace::force_capture(), self.max_depth);
+            *self.lock().entry(stack).or_insert(0) +=
+                layout.size().saturating_mul(self.sample_every);
+        }
+        res
+    }
+}
+
+/// Folds a backtrace into `outer;...;inner` form, dropping the capture machinery and this
+/// module's frames.
+#[cfg(feature = "std")]
+fn fold(bt: &Backtrace, max_depth: usize) -> String {
+    // `Backtrace` has no stable frame accessors, so read the names from its rendering, where
+    // each frame starts with a line of the form `  N: name`
+    let rendered = bt.to_string();
+    let frames: Vec<&str> = rendered
+        .lines()
+        .filter_map(|line| {
+            let (idx, name) = line.trim_start().split_once(": ")?;
+            idx.bytes()
+                .all(|b| b.is_ascii_digit())
+                .then_some(name.trim())
+        })
+        .filter(|name| {
+            // match on the path only, generic arguments may name this module legitimately
+            let path = name.trim_start_matches('<');
+            !path.starts_with("std::backtrace") && !path.starts_with("memapi::stats::")
+        })
+        .take(max_depth)
+        .collect();
+    let mut folded = String::new();
+    for (i, name) in frames.iter().rev().enumerate() {
+        if i != 0 {
+            folded.push(';');
+        }
+        // `;` separates frames and the final space separates the count
+        folded.extend(name.chars().map(|c| match c {
+            ';' => ':',
+            c => c,
+        }));
+    }
+    if folded.is_empty() {
+        folded.push_str("[unknown]");
+    }
+    folded
+}
+
+#[cfg(feature = "std")]
+impl<A: Alloc> Alloc for FoldedStackAlloc<A> {
+    #[track_caller]
+    #[inline]
+    fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.sample(self.inner.alloc(layout), layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.sample(self.inner.alloc_zeroed(layout), layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+        self.sample(self.inner.alloc_filled(layout, n), layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_patterned<F: Fn(usize) -> u8 + Clone>(
+        &self,
+        layout: Layout,
+        pattern: F,
+    ) -> Result<NonNull<u8>, AllocError> {
+        self.sample(self.inner.alloc_patterned(layout, pattern), layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe {
+            self.inner.dealloc(ptr, layout);
+        }
+    }
+
+    #[track_caller]
+    #[inline]
+    fn reserve(&self, bytes: usize) -> Result<(), AllocError> {
+        self.inner.reserve(bytes)
+    }
+
+    #[inline]
+    fn granularity(&self) -> usize {
+        self.inner.granularity()
+    }
+}
+
+/// The running statistics of the allocation sizes an [`AnomalyAlloc`] has seen.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SizeStats {
+    /// The number of allocations included.
+    pub count: u64,
+    /// The mean size in bytes.
+    pub mean: f64,
+    /// The population standard deviation of the sizes, in bytes.
+    pub std_dev: f64,
+}
+
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Default)]
+struct Welford {
+    count: u64,
+    mean: f64,
+    /// The sum of squared differences from the mean.
+    m2: f64,
+}
+
+#[cfg(feature = "std")]
+impl Welford {
+    fn push(&mut self, x: f64) {
+        self.count += 1;
+        let delta = x - self.mean;
+        #[allow(clippy::cast_precision_loss)]
+        let n = self.count as f64;
+        self.mean += delta / n;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    fn stats(self) -> SizeStats {
+        #[allow(clippy::cast_precision_loss)]
+        let var = if self.count == 0 {
+            0.0
+        } else {
+            self.m2 / self.count as f64
+        };
+        SizeStats {
+            count: self.count,
+            mean: self.mean,
+            std_dev: var.sqrt(),
+        }
+    }
+}
+
+/// A wrapper that delegates all `Alloc` calls to `A`, calling `on_anomaly` for allocations whose
+/// size is a statistical outlier among those seen so far.
+///
+/// An allocation is an outlier if it's larger than the mean size by more than `sigmas` standard
+/// deviations. These usually come from a bug, like a corrupt length field read from untrusted
+/// input, and `on_anomaly` is called with the layout and the statistics it was judged against
+/// *before* the allocation is attempted, so it gets reported even if it then exhausts memory.
+///
+/// The mean and deviation are kept online with Welford's algorithm, so the overhead is a lock and
+/// a few floating-point operations per allocation. Nothing is reported until `warmup`
+/// allocations have been seen, and outliers aren't added to the statistics, so a run of huge
+/// allocations keeps being reported rather than making itself normal. Resizes count as an
+/// allocation of the new size.
+#[cfg(feature = "std")]
+pub struct AnomalyAlloc<A, F: Fn(Layout, SizeStats)> {
+    inner: A,
+    sigmas: f64,
+    warmup: u64,
+    on_anomaly: F,
+    sizes: Mutex<Welford>,
+    anomalies: AtomicUsize,
+}
+
+#[cfg(feature = "std")]
+impl<A, F: Fn(Layout, SizeStats)> AnomalyAlloc<A, F> {
+    /// Creates a new anomaly-detecting allocator over `inner`, reporting allocations more than
+    /// `sigmas` standard deviations above the mean to `on_anomaly` once 32 have been seen.
+    #[must_use]
+    #[inline]
+    pub const fn new_in(inner: A, sigmas: f64, on_anomaly: F) -> AnomalyAlloc<A, F> {
+        AnomalyAlloc {
+            inner,
+            sigmas,
+            warmup: 32,
+            on_anomaly,
+            sizes: Mutex::new(Welford {
+                count: 0,
+                mean: 0.0,
+                m2: 0.0,
+            }),
+            anomalies: AtomicUsize::new(0),
+        }
+    }
+
+    /// Sets how many allocations must be seen before any are reported.
+    #[must_use]
+    #[inline]
+    pub const fn with_warmup(mut self, warmup: u64) -> AnomalyAlloc<A, F> {
+        self.warmup = warmup;
+        self
+    }
+
+    /// Returns a reference to the underlying allocator.
+    #[must_use]
+    #[inline]
+    pub const fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Returns the current statistics of the allocation sizes seen, excluding outliers.
+    #[must_use]
+    pub fn stats(&self) -> SizeStats {
+        self.lock().stats()
+    }
+
+    /// Returns the number of outliers which have been reported.
+    #[must_use]
+    #[inline]
+    pub fn anomalies(&self) -> usize {
+        self.anomalies.load(Relaxed)
+    }
+
+    #[inline]
+    fn lock(&self) -> MutexGuard<'_, Welford> {
+        self.sizes.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Judges `layout` against the statistics so far, reporting it if it's an outlier and
+    /// recording it otherwise.
+    fn check(&self, layout: Layout) {
+        #[allow(clippy::cast_precision_loss)]
+        let size = layout.size() as f64;
+        let outlier = {
+            let mut sizes = self.lock();
+            let stats = sizes.stats();
+            if stats.count >= self.warmup && size > self.sigmas.mul_add(stats.std_dev, stats.mean) {
+                Some(stats)
+            } else {
+                sizes.push(size);
+                None
+            }
+        };
+        // called unlocked, so the callback can allocate through this allocator
+        if let Some(stats) = outlier {
+            self.anomalies.fetch_add(1, Relaxed);
+            (self.on_anomaly)(layout, stats);
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A: Alloc, F: Fn(Layout, SizeStats)> Alloc for AnomalyAlloc<A, F> {
+    #[track_caller]
+    #[inline]
+    fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.check(layout);
+        self.inner.alloc(layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.check(layout);
+        self.inner.alloc_zeroed(layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+        self.check(layout);
+        self.inner.alloc_filled(layout, n)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_patterned<P: Fn(usize) -> u8 + Clone>(
+        &self,
+        layout: Layout,
+        pattern: P,
+    ) -> Result<NonNull<u8>, AllocError> {
+        self.check(layout);
+        self.inner.alloc_patterned(layout, pattern)
+    }
+
+    #[track_caller]
+    #[inline]
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe {
+            self.inner.dealloc(ptr, layout);
+        }
+    }
+
+    #[track_caller]
+    #[inline]
+    fn reserve(&self, bytes: usize) -> Result<(), AllocError> {
+        self.inner.reserve(bytes)
+    }
+
+    #[inline]
+    fn granularity(&self) -> usize {
+        self.inner.granularity()
+    }
+}
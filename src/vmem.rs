@@ -0,0 +1,1050 @@
+#[cfg(feature = "debug_checks")]
+use crate::debug_checks::IntegrityError;
+use crate::{Alloc, AllocError, helpers::dangling_nonnull};
+use core::{
+    alloc::Layout,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    ptr::NonNull,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Mutex, MutexGuard, PoisonError},
+    vec::Vec,
+};
+
+/// An allocator over one large reserved range of address space, committing pages only while
+/// they hold live allocations.
+///
+/// The whole range is reserved up front with `mmap(PROT_NONE)`, which costs address space but no
+/// physical memory. Each allocation is placed first-fit in the range's free space and makes the
+/// pages it touches accessible; once the last allocation on a page is freed, the page is released
+/// back to the OS and made inaccessible again. Physical usage therefore tracks live data, while
+/// every allocation shares the stable [`base`](ReserveCommitAlloc::base) address, which makes the
+/// range suitable for structures storing offsets from it instead of pointers.
+///
+/// Freed space is coalesced and reused. Allocation fails once no free gap in the range fits the
+/// request, and the range is unmapped when the allocator is dropped.
+///
+/// [`reserve`](Alloc::reserve) commits and prefaults the pages of the first free gap which fits
+/// the requested bytes at page alignment, and holds them committed until the next call, so
+/// allocations placed there don't fault. `reserve(0)` releases the held pages.
+pub struct ReserveCommitAlloc {
+    base: NonNull<u8>,
+    reserved: usize,
+    page: usize,
+    state: Mutex<State>,
+}
+
+struct State {
+    /// Free gaps in the range, as offset to length, never adjacent.
+    free: BTreeMap<usize, usize>,
+    /// The number of live allocations touching each page, plus one for pages held by `reserve`.
+    users: Vec<u32>,
+    /// The pages held committed by the last `reserve`.
+    held: core::ops::Range<usize>,
+    committed: usize,
+}
+
+// SAFETY: the range is owned by the allocator and all bookkeeping is behind the mutex.
+unsafe impl Send for ReserveCommitAlloc {}
+// SAFETY: see above.
+unsafe impl Sync for ReserveCommitAlloc {}
+
+impl ReserveCommitAlloc {
+    /// Reserves at least `bytes` of address space, rounded up to whole pages.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::ArithmeticOverflow`] if rounding `bytes` up overflows.
+    /// - [`AllocError::AllocFailed`] if the range couldn't be reserved.
+    pub fn new(bytes: usize) -> Result<ReserveCommitAlloc, AllocError> {
+        let page = page_size();
+        let reserved = bytes
+            .max(1)
+            .checked_next_multiple_of(page)
+            .ok_or(AllocError::ArithmeticOverflow)?;
+        let failed = || {
+            AllocError::AllocFailed(unsafe { Layout::from_size_align_unchecked(reserved, page) })
+        };
+        let base = unsafe {
+            libc::mmap(
+                core::ptr::null_mut(),
+                reserved,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+                -1,
+                0,
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return Err(failed());
+        }
+        Ok(ReserveCommitAlloc {
+            base: NonNull::new(base.cast()).ok_or_else(failed)?,
+            reserved,
+            page,
+            state: Mutex::new(State {
+                free: BTreeMap::from([(0, reserved)]),
+                users: vec![0; reserved / page],
+                held: 0..0,
+                committed: 0,
+            }),
+        })
+    }
+
+    /// Returns the start of the reserved range. It never moves.
+    #[must_use]
+    #[inline]
+    pub const fn base(&self) -> NonNull<u8> {
+        self.base
+    }
+
+    /// Returns the size of the reserved range.
+    #[must_use]
+    #[inline]
+    pub const fn reserved_bytes(&self) -> usize {
+        self.reserved
+    }
+
+    /// Returns the number of bytes currently committed, always a whole number of pages.
+    #[must_use]
+    #[inline]
+    pub fn committed_bytes(&self) -> usize {
+        self.lock().committed
+    }
+
+    /// Allocates `value` in the range, returning its offset from the base instead of its address.
+    ///
+    /// # Errors
+    ///
+    /// - [`AllocError::AllocFailed`] if no free gap fits a `T`.
+    /// - [`AllocError::ZeroSizedLayout`] if `T` is zero-sized.
+    #[track_caller]
+    pub fn alloc_relative<T>(&self, value: T) -> Result<RelativePtr<T>, AllocError> {
+        let ptr = self.alloc(Layout::new::<T>())?.cast::<T>();
+        unsafe { ptr.write(value) };
+        Ok(RelativePtr::from_offset(
+            ptr.addr().get() - self.base.addr().get(),
+        ))
+    }
+
+    /// Resolves `rel` to an address in this allocator's range.
+    ///
+    /// Returns `None` if a `T` at the offset wouldn't lie within the range or be aligned. This
+    /// doesn't check that a `T` was actually allocated there.
+    #[must_use]
+    pub fn resolve<T>(&self, rel: RelativePtr<T>) -> Option<NonNull<T>> {
+        let end = rel.offset.checked_add(size_of::<T>())?;
+        if end > self.reserved
+            || !(self.base.addr().get() + rel.offset).is_multiple_of(align_of::<T>())
+        {
+            return None;
+        }
+        Some(unsafe { self.base.add(rel.offset).cast() })
+    }
+
+    /// Gets the offset of `ptr` from this allocator's base, or `None` if it's outside the range.
+    #[must_use]
+    pub fn relative<T>(&self, ptr: NonNull<T>) -> Option<RelativePtr<T>> {
+        let offset = ptr.addr().get().checked_sub(self.base.addr().get())?;
+        (offset.checked_add(size_of::<T>())? <= self.reserved)
+            .then(|| RelativePtr::from_offset(offset))
+    }
+
+    /// Drops and deallocates the `T` at `rel`.
+    ///
+    /// # Safety
+    ///
+    /// `rel` must refer to a live `T` allocated by this allocator, for example by
+    /// [`alloc_relative`](ReserveCommitAlloc::alloc_relative).
+    #[track_caller]
+    pub unsafe fn dealloc_relative<T>(&self, rel: RelativePtr<T>) {
+        unsafe {
+            let ptr = self.base.add(rel.offset).cast::<T>();
+            ptr.drop_in_place();
+            self.dealloc(ptr.cast(), Layout::new::<T>());
+        }
+    }
+
+    /// Walks the allocator's free gaps and page counts, checking they're consistent.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first inconsistency found:
+    /// - [`IntegrityError::BadSize`] for an empty free gap.
+    /// - [`IntegrityError::OutOfBounds`] for a gap past the end of the range.
+    /// - [`IntegrityError::Overlapping`] or [`IntegrityError::Uncoalesced`] for a gap overlapping
+    ///   or touching the previous one.
+    /// - [`IntegrityError::Accounting`] for a page inside a free gap which still counts users, or
+    ///   a committed total which disagrees with the page counts.
+    #[cfg(feature = "debug_checks")]
+    pub fn verify_integrity(&self) -> Result<(), IntegrityError> {
+        let base = self.base.addr().get();
+        let state = self.lock();
+        let mut prev_end = None;
+        for (&gap, &len) in &state.free {
+            let addr = base + gap;
+            if len == 0 {
+                return Err(IntegrityError::BadSize { addr, size: len });
+            }
+            if gap.checked_add(len).is_none_or(|end| end > self.reserved) {
+                return Err(IntegrityError::OutOfBounds { addr, size: len });
+            }
+            match prev_end {
+                Some(end) if end > gap => return Err(IntegrityError::Overlapping { addr }),
+                Some(end) if end == gap => return Err(IntegrityError::Uncoalesced { addr }),
+                _ => {}
+            }
+            prev_end = Some(gap + len);
+            // pages lying entirely within the gap can't hold any allocation, only a reservation
+            for p in gap.div_ceil(self.page)..(gap + len) / self.page {
+                if state.users[p] != u32::from(state.held.contains(&p)) {
+                    return Err(IntegrityError::Accounting {
+                        addr: base + p * self.page,
+                    });
+                }
+            }
+        }
+        let in_use = state.users.iter().filter(|&&u| u != 0).count();
+        if in_use * self.page != state.committed {
+            return Err(IntegrityError::Accounting { addr: base });
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns the pages touched by `size` bytes at `offset`.
+    fn pages(&self, offset: usize, size: usize) -> core::ops::Range<usize> {
+        offset / self.page..(offset + size).div_ceil(self.page)
+    }
+
+    /// Finds and claims a free gap for `layout`, returning its offset.
+    fn claim(&self, state: &mut State, layout: Layout) -> Option<usize> {
+        let base = self.base.addr().get();
+        let (gap, gap_len, start) = state.free.iter().find_map(|(&gap, &gap_len)| {
+            let start = (base + gap).checked_next_multiple_of(layout.align())? - base;
+            (start + layout.size() <= gap + gap_len).then_some((gap, gap_len, start))
+        })?;
+        let end = start + layout.size();
+        state.free.remove(&gap);
+        if start > gap {
+            state.free.insert(gap, start - gap);
+        }
+        if gap + gap_len > end {
+            state.free.insert(end, gap + gap_len - end);
+        }
+        Some(start)
+    }
+
+    /// Returns `size` bytes at `offset` to the free gaps, merging with its neighbors.
+    fn release(state: &mut State, mut offset: usize, mut size: usize) {
+        if let Some((&prev, &prev_len)) = state.free.range(..offset).next_back()
+            && prev + prev_len == offset
+        {
+            state.free.remove(&prev);
+            offset = prev;
+            size += prev_len;
+        }
+        if let Some(next_len) = state.free.remove(&(offset + size)) {
+            size += next_len;
+        }
+        state.free.insert(offset, size);
+    }
+
+    /// Commits every page in `pages` which has no users yet.
+    fn commit(&self, state: &mut State, pages: core::ops::Range<usize>) -> bool {
+        for p in pages.clone() {
+            if state.users[p] == 0 && !self.protect(p, libc::PROT_READ | libc::PROT_WRITE) {
+                // roll back the pages committed so far
+                self.uncommit(state, pages.start..p);
+                return false;
+            }
+            if state.users[p] == 0 {
+                state.committed += self.page;
+            }
+            state.users[p] += 1;
+        }
+        true
+    }
+
+    /// Drops a user from every page in `pages`, decommitting those left with none.
+    fn uncommit(&self, state: &mut State, pages: core::ops::Range<usize>) {
+        for p in pages {
+            state.users[p] -= 1;
+            if state.users[p] == 0 {
+                unsafe {
+                    let page = self.base.add(p * self.page).as_ptr().cast();
+                    // releases the physical memory; it reads as zero if committed again
+                    libc::madvise(page, self.page, libc::MADV_DONTNEED);
+                }
+                self.protect(p, libc::PROT_NONE);
+                state.committed -= self.page;
+            }
+        }
+    }
+
+    fn protect(&self, page: usize, prot: libc::c_int) -> bool {
+        unsafe {
+            libc::mprotect(
+                self.base.add(page * self.page).as_ptr().cast(),
+                self.page,
+                prot,
+            ) == 0
+        }
+    }
+}
+
+impl Alloc for ReserveCommitAlloc {
+    #[track_caller]
+    fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 {
+            return Err(AllocError::ZeroSizedLayout(unsafe {
+                dangling_nonnull(layout.align())
+            }));
+        }
+        let mut state = self.lock();
+        let Some(offset) = self.claim(&mut state, layout) else {
+            return Err(AllocError::AllocFailed(layout));
+        };
+        if !self.commit(&mut state, self.pages(offset, layout.size())) {
+            Self::release(&mut state, offset, layout.size());
+            return Err(AllocError::AllocFailed(layout));
+        }
+        Ok(unsafe { self.base.add(offset) })
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.alloc_filled(layout, 0)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+        // pages shared with other allocations may hold stale bytes, so always fill
+        let ptr = self.alloc(layout)?;
+        unsafe { ptr.write_bytes(n, layout.size()) };
+        Ok(ptr)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_patterned<F: Fn(usize) -> u8 + Clone>(
+        &self,
+        layout: Layout,
+        pattern: F,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let ptr = self.alloc(layout)?;
+        for i in 0..layout.size() {
+            unsafe { ptr.add(i).write(pattern(i)) };
+        }
+        Ok(ptr)
+    }
+
+    #[track_caller]
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() == 0 {
+            return;
+        }
+        let offset = ptr.addr().get() - self.base.addr().get();
+        let mut state = self.lock();
+        self.uncommit(&mut state, self.pages(offset, layout.size()));
+        Self::release(&mut state, offset, layout.size());
+    }
+
+    #[track_caller]
+    fn reserve(&self, bytes: usize) -> Result<(), AllocError> {
+        let mut state = self.lock();
+        let held = core::mem::replace(&mut state.held, 0..0);
+        if bytes == 0 {
+            self.uncommit(&mut state, held);
+            return Ok(());
+        }
+        let layout = Layout::from_size_align(bytes, self.page)
+            .map_err(|_| AllocError::LayoutError(bytes, self.page))?;
+        let base = self.base.addr().get();
+        let Some(start) = state.free.iter().find_map(|(&gap, &gap_len)| {
+            let start = (base + gap).checked_next_multiple_of(self.page)? - base;
+            (start + bytes <= gap + gap_len).then_some(start)
+        }) else {
+            state.held = held;
+            return Err(AllocError::AllocFailed(layout));
+        };
+        // commit the new pages before releasing the old, so pages in both stay committed
+        let pages = self.pages(start, bytes);
+        if !self.commit(&mut state, pages.clone()) {
+            state.held = held;
+            return Err(AllocError::AllocFailed(layout));
+        }
+        self.uncommit(&mut state, held);
+        for p in pages.clone() {
+            // the gap is free, so its bytes can be written without disturbing any allocation
+            unsafe { self.base.add(p * self.page).write_volatile(0) };
+        }
+        state.held = pages;
+        Ok(())
+    }
+
+    /// Memory is committed a page at a time.
+    #[inline]
+    fn granularity(&self) -> usize {
+        self.page
+    }
+}
+
+impl Drop for ReserveCommitAlloc {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.base.as_ptr().cast(), self.reserved);
+        }
+    }
+}
+
+impl core::fmt::Debug for ReserveCommitAlloc {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ReserveCommitAlloc")
+            .field("base", &self.base)
+            .field("reserved", &self.reserved)
+            .field("committed", &self.committed_bytes())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A pointer to a `T` stored as its offset from the base of a [`ReserveCommitAlloc`]'s range.
+///
+/// Offsets stay meaningful when the range is at a different address, as after persisting the
+/// range's contents and loading them into another one, so structures linked by relative pointers
+/// can be relocated or saved as-is. A relative pointer isn't tied to the allocator it came from,
+/// and is only resolved to an address on request with [`ReserveCommitAlloc::resolve`].
+///
+/// It serializes as a plain integer, with [`offset`](RelativePtr::offset) and
+/// [`from_offset`](RelativePtr::from_offset) or the little-endian byte conversions.
+pub struct RelativePtr<T> {
+    offset: usize,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> RelativePtr<T> {
+    /// Creates a relative pointer to the given offset from a range's base.
+    #[must_use]
+    #[inline]
+    pub const fn from_offset(offset: usize) -> RelativePtr<T> {
+        RelativePtr {
+            offset,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the offset from the range's base.
+    #[must_use]
+    #[inline]
+    pub const fn offset(self) -> usize {
+        self.offset
+    }
+
+    /// Returns the offset as little-endian bytes, the same size on every target.
+    #[must_use]
+    #[inline]
+    pub const fn to_le_bytes(self) -> [u8; 8] {
+        (self.offset as u64).to_le_bytes()
+    }
+
+    /// Reads an offset written by [`to_le_bytes`](RelativePtr::to_le_bytes).
+    ///
+    /// Returns `None` if the offset doesn't fit in a `usize` on this target.
+    #[must_use]
+    #[inline]
+    pub fn from_le_bytes(bytes: [u8; 8]) -> Option<RelativePtr<T>> {
+        usize::try_from(u64::from_le_bytes(bytes))
+            .ok()
+            .map(RelativePtr::from_offset)
+    }
+
+    /// Returns a relative pointer to the `U` `bytes` past this one, such as a field of the `T`.
+    #[must_use]
+    #[inline]
+    pub const fn byte_add<U>(self, bytes: usize) -> Option<RelativePtr<U>> {
+        match self.offset.checked_add(bytes) {
+            Some(offset) => Some(RelativePtr::from_offset(offset)),
+            None => None,
+        }
+    }
+}
+
+// manual impls so `T` doesn't need to implement these
+impl<T> Clone for RelativePtr<T> {
+    #[inline]
+    fn clone(&self) -> RelativePtr<T> {
+        *self
+    }
+}
+
+impl<T> Copy for RelativePtr<T> {}
+
+impl<T> PartialEq for RelativePtr<T> {
+    #[inline]
+    fn eq(&self, other: &RelativePtr<T>) -> bool {
+        self.offset == other.offset
+    }
+}
+
+impl<T> Eq for RelativePtr<T> {}
+
+impl<T> Hash for RelativePtr<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.offset.hash(state);
+    }
+}
+
+impl<T> core::fmt::Debug for RelativePtr<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "RelativePtr(+{:#x})", self.offset)
+    }
+}
+
+/// An allocator mapping memory straight from the OS with `mmap`.
+///
+/// Allocations larger than half a page get mappings of their own, unmapped when they're freed.
+/// Smaller ones are packed into shared single-page mappings, with a count of live allocations per
+/// page: freeing a small allocation only updates that count, and the page is unmapped once every
+/// allocation in it has been freed. Small-object churn thus costs one `munmap` per page instead of
+/// one per free. [`pending_bytes`](MmapAlloc::pending_bytes) reports the freed bytes still held
+/// in mapped pages.
+///
+/// [`reserve`](Alloc::reserve) maps and prefaults spare pages ahead of time, which new shared
+/// pages are then taken from before mapping more, so small allocations filling that many bytes
+/// don't have to map or fault in memory. Large allocations always get fresh mappings.
+///
+/// Shared and spare pages still mapped when the allocator is dropped are unmapped then.
+pub struct MmapAlloc {
+    page: usize,
+    state: Mutex<MmapState>,
+}
+
+#[derive(Default)]
+struct MmapState {
+    /// Shared pages, by address.
+    pages: HashMap<usize, SharedPage>,
+    /// The page new small allocations are carved from, and the offset of its free space.
+    current: Option<(NonNull<u8>, usize)>,
+    /// Prefaulted pages mapped by `reserve`, not yet shared.
+    spare: Vec<NonNull<u8>>,
+}
+
+struct SharedPage {
+    base: NonNull<u8>,
+    live: u32,
+    freed: usize,
+}
+
+// SAFETY: the shared pages are owned by the allocator and all bookkeeping is behind the mutex.
+unsafe impl Send for MmapAlloc {}
+// SAFETY: see above.
+unsafe impl Sync for MmapAlloc {}
+
+impl MmapAlloc {
+    /// Creates a new allocator with no pages mapped.
+    #[must_use]
+    #[inline]
+    pub fn new() -> MmapAlloc {
+        MmapAlloc {
+            page: page_size(),
+            state: Mutex::new(MmapState::default()),
+        }
+    }
+
+    /// Returns the number of freed bytes in shared pages which haven't been unmapped yet, because
+    /// their page still holds live allocations.
+    #[must_use]
+    pub fn pending_bytes(&self) -> usize {
+        self.lock().pages.values().map(|p| p.freed).sum()
+    }
+
+    /// Returns the number of shared pages currently mapped.
+    #[must_use]
+    pub fn mapped_pages(&self) -> usize {
+        self.lock().pages.len()
+    }
+
+    /// Returns the number of spare pages mapped by [`reserve`](Alloc::reserve) which small
+    /// allocations haven't used yet.
+    #[must_use]
+    pub fn spare_pages(&self) -> usize {
+        self.lock().spare.len()
+    }
+
+    /// Makes the allocation at `ptr` read-only, so writes to it fault until it's
+    /// [`thaw`](MmapAlloc::thaw)ed.
+    ///
+    /// Only allocations with mappings of their own can be frozen, as protection applies to whole
+    /// pages: those larger than half a page. The protection covers the allocation's whole mapping,
+    /// its size rounded up to a multiple of the page size. Returns `false`, leaving the allocation
+    /// writable, if it's a small allocation sharing a page or the protection couldn't be changed.
+    ///
+    /// A frozen allocation may be deallocated directly; unmapping doesn't need write access.
+    ///
+    /// # Safety
+    ///
+    /// - `ptr` must be a live allocation of this allocator, and `layout` must describe it.
+    /// - Nothing may write to the allocation while it's frozen, including through resizing, which
+    ///   copies into the new block but may also write to the old one.
+    #[must_use]
+    pub unsafe fn freeze(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        self.protect(ptr, layout, libc::PROT_READ)
+    }
+
+    /// Makes an allocation frozen with [`freeze`](MmapAlloc::freeze) writable again.
+    ///
+    /// Returns `false` if it's a small allocation sharing a page or the protection couldn't be
+    /// changed.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a live allocation of this allocator, and `layout` must describe it.
+    #[must_use]
+    pub unsafe fn thaw(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        self.protect(ptr, layout, libc::PROT_READ | libc::PROT_WRITE)
+    }
+
+    /// Walks the shared pages' counts, checking they're consistent.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first inconsistency found:
+    /// - [`IntegrityError::BadSize`] for a page with more freed bytes than fit in a page.
+    /// - [`IntegrityError::Accounting`] for a page recorded under the wrong address, or a current
+    ///   page which isn't recorded.
+    #[cfg(feature = "debug_checks")]
+    pub fn verify_integrity(&self) -> Result<(), IntegrityError> {
+        let state = self.lock();
+        for (&addr, page) in &state.pages {
+            if page.base.addr().get() != addr || addr % self.page != 0 {
+                return Err(IntegrityError::Accounting { addr });
+            }
+            if page.freed > self.page {
+                return Err(IntegrityError::BadSize {
+                    addr,
+                    size: page.freed,
+                });
+            }
+        }
+        if let Some((current, used)) = state.current {
+            let addr = current.addr().get();
+            if !state.pages.contains_key(&addr) {
+                return Err(IntegrityError::Accounting { addr });
+            }
+            if used > self.page {
+                return Err(IntegrityError::BadSize { addr, size: used });
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the protection of a large allocation's mapping.
+    fn protect(&self, ptr: NonNull<u8>, layout: Layout, prot: libc::c_int) -> bool {
+        if layout.size() == 0 || self.is_small(layout) {
+            return false;
+        }
+        let len = layout.size().next_multiple_of(self.page);
+        unsafe { libc::mprotect(ptr.as_ptr().cast(), len, prot) == 0 }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MmapState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    const fn is_small(&self, layout: Layout) -> bool {
+        layout.size() <= self.page / 2 && layout.align() <= self.page / 2
+    }
+
+    /// Carves a small allocation from the current page, mapping a new one if it doesn't fit.
+    fn alloc_small(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        let mut state = self.lock();
+        let fits = state.current.and_then(|(page, used)| {
+            let start = used.checked_next_multiple_of(layout.align())?;
+            (start + layout.size() <= self.page).then_some((page, start))
+        });
+        let (page, start) = if let Some(fit) = fits {
+            fit
+        } else {
+            let page = match state.spare.pop() {
+                Some(page) => page,
+                None => map(self.page).ok_or(AllocError::AllocFailed(layout))?,
+            };
+            let info = SharedPage {
+                base: page,
+                live: 0,
+                freed: 0,
+            };
+            state.pages.insert(page.addr().get(), info);
+            (page, 0)
+        };
+        state.current = Some((page, start + layout.size()));
+        if let Some(info) = state.pages.get_mut(&page.addr().get()) {
+            info.live += 1;
+        }
+        Ok(unsafe { page.add(start) })
+    }
+
+    fn dealloc_small(&self, ptr: NonNull<u8>, layout: Layout) {
+        let page = ptr.addr().get() & !(self.page - 1);
+        let mut state = self.lock();
+        let current = state.current;
+        let Some(info) = state.pages.get_mut(&page) else {
+            return;
+        };
+        info.live -= 1;
+        info.freed += layout.size();
+        if info.live != 0 {
+            return;
+        }
+        // the current page is reused from the start rather than unmapped
+        if let Some((current, _)) = current
+            && current.addr().get() == page
+        {
+            info.freed = 0;
+            state.current = Some((current, 0));
+        } else {
+            let base = info.base;
+            state.pages.remove(&page);
+            unsafe { unmap(base, self.page) };
+        }
+    }
+}
+
+impl Default for MmapAlloc {
+    #[inline]
+    fn default() -> MmapAlloc {
+        MmapAlloc::new()
+    }
+}
+
+impl Alloc for MmapAlloc {
+    #[track_caller]
+    fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 {
+            return Err(AllocError::ZeroSizedLayout(unsafe {
+                dangling_nonnull(layout.align())
+            }));
+        }
+        if self.is_small(layout) {
+            return self.alloc_small(layout);
+        }
+        if layout.align() > self.page {
+            // mappings are only guaranteed to be page-aligned
+            return Err(AllocError::AllocFailed(layout));
+        }
+        let len = layout
+            .size()
+            .checked_next_multiple_of(self.page)
+            .ok_or(AllocError::ArithmeticOverflow)?;
+        map(len).ok_or(AllocError::AllocFailed(layout))
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.alloc_filled(layout, 0)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+        // reused shared pages may hold stale bytes
+        let ptr = self.alloc(layout)?;
+        unsafe { ptr.write_bytes(n, layout.size()) };
+        Ok(ptr)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_patterned<F: Fn(usize) -> u8 + Clone>(
+        &self,
+        layout: Layout,
+        pattern: F,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let ptr = self.alloc(layout)?;
+        for i in 0..layout.size() {
+            unsafe { ptr.add(i).write(pattern(i)) };
+        }
+        Ok(ptr)
+    }
+
+    #[track_caller]
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() == 0 {
+            return;
+        }
+        if self.is_small(layout) {
+            self.dealloc_small(ptr, layout);
+        } else {
+            unsafe { unmap(ptr, layout.size().next_multiple_of(self.page)) };
+        }
+    }
+
+    /// Maps spare pages until they hold at least `bytes`, writing to each so it's faulted in.
+    #[track_caller]
+    fn reserve(&self, bytes: usize) -> Result<(), AllocError> {
+        let needed = bytes.div_ceil(self.page);
+        let mut state = self.lock();
+        while state.spare.len() < needed {
+            let page = map(self.page).ok_or(AllocError::AllocFailed(unsafe {
+                Layout::from_size_align_unchecked(self.page, self.page)
+            }))?;
+            unsafe { page.write_volatile(0) };
+            state.spare.push(page);
+        }
+        Ok(())
+    }
+
+    /// Large allocations take whole pages, so sizes past half a page should be a multiple of one.
+    #[inline]
+    fn granularity(&self) -> usize {
+        self.page
+    }
+}
+
+impl Drop for MmapAlloc {
+    fn drop(&mut self) {
+        let state = self.state.get_mut().unwrap_or_else(PoisonError::into_inner);
+        for page in state.pages.values() {
+            unsafe { unmap(page.base, self.page) };
+        }
+        for &page in &state.spare {
+            unsafe { unmap(page, self.page) };
+        }
+    }
+}
+
+impl core::fmt::Debug for MmapAlloc {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MmapAlloc")
+            .field("mapped_pages", &self.mapped_pages())
+            .field("pending_bytes", &self.pending_bytes())
+            .field("spare_pages", &self.spare_pages())
+            .finish_non_exhaustive()
+    }
+}
+
+/// An allocator mapping memory straight from the OS, with each allocation's pages interleaved
+/// round-robin across every online NUMA node.
+///
+/// Every allocation gets a mapping of its own, rounded up to whole pages, bound with
+/// `MPOL_INTERLEAVE` so its pages are placed on each node in turn as they're first touched. Spreading
+/// a buffer over all nodes maximizes the aggregate memory bandwidth available to it, which suits
+/// large, shared data accessed uniformly by threads on every node, like a lookup table hammered
+/// by all cores. It hurts data mostly used by one thread, which would be better off entirely on
+/// that thread's node, since most of its accesses then cross the interconnect. Interleaving only
+/// works at page granularity, so small allocations waste most of a page each and should come from
+/// another allocator.
+///
+/// Allocation fails if the policy can't be applied, such as when a node has gone offline. On a
+/// machine with a single node the policy is accepted but has no effect.
+#[cfg(all(feature = "numa", target_os = "linux"))]
+pub struct InterleavedAlloc {
+    page: usize,
+    nodes: Vec<usize>,
+    /// The bitmask of `nodes` passed to `mbind`.
+    mask: Vec<libc::c_ulong>,
+}
+
+#[cfg(all(feature = "numa", target_os = "linux"))]
+impl InterleavedAlloc {
+    /// Creates a new allocator interleaving across the nodes currently online, or only node 0 if
+    /// they can't be read from sysfs.
+    #[must_use]
+    pub fn new() -> InterleavedAlloc {
+        let nodes = std::fs::read_to_string("/sys/devices/system/node/online")
+            .ok()
+            .and_then(|list| parse_node_list(list.trim()))
+            .unwrap_or_else(|| std::vec![0]);
+        InterleavedAlloc::on_nodes(nodes)
+    }
+
+    /// Creates a new allocator interleaving across only `nodes`, or only node 0 if it's empty.
+    #[must_use]
+    pub fn on_nodes(mut nodes: Vec<usize>) -> InterleavedAlloc {
+        if nodes.is_empty() {
+            nodes.push(0);
+        }
+        nodes.sort_unstable();
+        nodes.dedup();
+        let bits = libc::c_ulong::BITS as usize;
+        let mut mask = std::vec![0; nodes[nodes.len() - 1] / bits + 1];
+        for &node in &nodes {
+            mask[node / bits] |= 1 << (node % bits);
+        }
+        InterleavedAlloc {
+            page: page_size(),
+            nodes,
+            mask,
+        }
+    }
+
+    /// Returns the nodes allocations are interleaved across, in ascending order.
+    #[must_use]
+    #[inline]
+    pub fn nodes(&self) -> &[usize] {
+        &self.nodes
+    }
+
+    /// Binds the mapping of `len` bytes at `ptr` to the interleave policy.
+    fn interleave(&self, ptr: NonNull<u8>, len: usize) -> bool {
+        /// The `mbind` mode for interleaving, from `<linux/mempolicy.h>`.
+        const MPOL_INTERLEAVE: libc::c_int = 3;
+        // the kernel reads one bit fewer than `maxnode`
+        let maxnode = self.mask.len() * libc::c_ulong::BITS as usize + 1;
+        let res = unsafe {
+            libc::syscall(
+                libc::SYS_mbind,
+                ptr.as_ptr(),
+                len,
+                MPOL_INTERLEAVE,
+                self.mask.as_ptr(),
+                maxnode,
+                0,
+            )
+        };
+        res == 0
+    }
+}
+
+#[cfg(all(feature = "numa", target_os = "linux"))]
+impl Default for InterleavedAlloc {
+    #[inline]
+    fn default() -> InterleavedAlloc {
+        InterleavedAlloc::new()
+    }
+}
+
+#[cfg(all(feature = "numa", target_os = "linux"))]
+impl Alloc for InterleavedAlloc {
+    #[track_caller]
+    fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 {
+            return Err(AllocError::ZeroSizedLayout(unsafe {
+                dangling_nonnull(layout.align())
+            }));
+        }
+        if layout.align() > self.page {
+            return Err(AllocError::AllocFailed(layout));
+        }
+        let len = layout
+            .size()
+            .checked_next_multiple_of(self.page)
+            .ok_or(AllocError::ArithmeticOverflow)?;
+        let ptr = map(len).ok_or(AllocError::AllocFailed(layout))?;
+        // the policy must be set before the pages are first touched to take effect
+        if !self.interleave(ptr, len) {
+            unsafe { unmap(ptr, len) };
+            return Err(AllocError::AllocFailed(layout));
+        }
+        Ok(ptr)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        // fresh mappings are already zeroed
+        self.alloc(layout)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+        let ptr = self.alloc(layout)?;
+        unsafe { ptr.write_bytes(n, layout.size()) };
+        Ok(ptr)
+    }
+
+    #[track_caller]
+    #[inline]
+    fn alloc_patterned<F: Fn(usize) -> u8 + Clone>(
+        &self,
+        layout: Layout,
+        pattern: F,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let ptr = self.alloc(layout)?;
+        for i in 0..layout.size() {
+            unsafe { ptr.add(i).write(pattern(i)) };
+        }
+        Ok(ptr)
+    }
+
+    #[track_caller]
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() != 0 {
+            unsafe { unmap(ptr, layout.size().next_multiple_of(self.page)) };
+        }
+    }
+
+    /// Every allocation takes whole pages, so sizes should be a multiple of one.
+    #[inline]
+    fn granularity(&self) -> usize {
+        self.page
+    }
+}
+
+#[cfg(all(feature = "numa", target_os = "linux"))]
+impl core::fmt::Debug for InterleavedAlloc {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("InterleavedAlloc")
+            .field("nodes", &self.nodes)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Parses a sysfs node list like `0-3,5`.
+#[cfg(all(feature = "numa", target_os = "linux"))]
+fn parse_node_list(list: &str) -> Option<Vec<usize>> {
+    let mut nodes = Vec::new();
+    for part in list.split(',') {
+        match part.split_once('-') {
+            Some((lo, hi)) => nodes.extend(lo.parse::<usize>().ok()?..=hi.parse().ok()?),
+            None => nodes.push(part.parse().ok()?),
+        }
+    }
+    (!nodes.is_empty()).then_some(nodes)
+}
+
+/// Maps `len` bytes of fresh, zeroed, readable and writable memory.
+fn map(len: usize) -> Option<NonNull<u8>> {
+    let ptr = unsafe {
+        libc::mmap(
+            core::ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+    };
+    if ptr == libc::MAP_FAILED {
+        None
+    } else {
+        NonNull::new(ptr.cast())
+    }
+}
+
+/// Unmaps `len` bytes at `ptr`.
+///
+/// # Safety
+///
+/// `ptr` must be the start of a mapping of exactly `len` bytes made by [`map`].
+unsafe fn unmap(ptr: NonNull<u8>, len: usize) {
+    unsafe {
+        libc::munmap(ptr.as_ptr().cast(), len);
+    }
+}
+
+/// Returns the system's page size.
+fn page_size() -> usize {
+    // SAFETY: `sysconf` has no preconditions
+    let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    usize::try_from(size).unwrap_or(4096)
+}
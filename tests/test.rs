@@ -1,8 +1,16 @@
-use core::alloc::Layout;
+#![cfg_attr(
+    all(feature = "clone_to_uninit", feature = "metadata"),
+    feature(clone_to_uninit)
+)]
+
+use core::{alloc::Layout, num::NonZeroUsize, ptr::NonNull};
+use memapi::helpers::{
+    LayoutBuilder, align_up, align_up_usize, array_layout, carve, layout_or_panic, validate_layout,
+};
 use memapi::unstable_util::{
     pad_layout_for, pad_layout_to_align, repeat_layout, repeat_layout_packed,
 };
-use memapi::{Alloc, AllocError, DefaultAlloc};
+use memapi::{Alloc, AllocError, AllocResultExt, DefaultAlloc};
 
 #[test]
 fn test_alloc_and_dealloc() {
@@ -227,6 +235,156 @@ fn test_repeat_layout_variants() {
     assert_eq!(rep_packed.align(), 4);
 }
 
+#[test]
+fn test_align_up() {
+    let p = NonNull::<u8>::without_provenance(NonZeroUsize::new(0x1001).unwrap());
+    assert_eq!(align_up(p, 1), Some(p));
+    assert_eq!(align_up(p, 16).map(|p| p.addr().get()), Some(0x1010));
+    assert_eq!(align_up(p, 0x1000).map(|p| p.addr().get()), Some(0x2000));
+    // already aligned stays put
+    let a = NonNull::<u8>::without_provenance(NonZeroUsize::new(0x2000).unwrap());
+    assert_eq!(align_up(a, 0x1000), Some(a));
+    // non-power-of-two alignments are rejected
+    assert!(align_up(p, 0).is_none());
+    assert!(align_up(p, 3).is_none());
+    // rounding past the top of the address space
+    let top = NonNull::<u8>::without_provenance(NonZeroUsize::new(usize::MAX - 2).unwrap());
+    assert!(align_up(top, 8).is_none());
+}
+
+#[test]
+fn test_carve() {
+    let mut backing = [0u64; 8];
+    let base = NonNull::from(&mut backing).cast::<u8>();
+    let mut region = NonNull::slice_from_raw_parts(base, 64);
+
+    let a = carve(&mut region, Layout::new::<u8>()).unwrap();
+    assert_eq!(a, base);
+    assert_eq!(region.len(), 63);
+
+    // skips 7 bytes of padding to reach 8-alignment
+    let b = carve(&mut region, Layout::new::<u64>()).unwrap();
+    assert_eq!(b.addr().get(), base.addr().get() + 8);
+    assert_eq!(region.len(), 48);
+
+    // doesn't fit: region is left untouched
+    let before = region;
+    assert!(carve(&mut region, Layout::from_size_align(49, 1).unwrap()).is_none());
+    assert_eq!(region, before);
+
+    // exactly fills the rest
+    let c = carve(&mut region, Layout::from_size_align(48, 8).unwrap()).unwrap();
+    assert_eq!(c.addr().get(), base.addr().get() + 16);
+    assert_eq!(region.len(), 0);
+    assert!(carve(&mut region, Layout::new::<u8>()).is_none());
+    // zero-sized blocks still fit in an empty region
+    assert!(carve(&mut region, Layout::new::<()>()).is_some());
+}
+
+#[test]
+fn test_const_layout_helpers() {
+    // all of these are evaluated at compile time
+    const NODES: Layout = layout_or_panic(array_layout::<u64>(1024));
+    const HEADER: (LayoutBuilder, usize) = LayoutBuilder::new().field::<u8>().push(NODES);
+    const PADDED: Option<usize> = align_up_usize(13, 8);
+    assert_eq!(NODES, Layout::new::<[u64; 1024]>());
+    assert_eq!(HEADER.1, 8);
+    assert_eq!(
+        HEADER.0.build(),
+        Ok(Layout::from_size_align(8200, 8).unwrap())
+    );
+    assert_eq!(PADDED, Some(16));
+
+    assert_eq!(align_up_usize(16, 16), Some(16));
+    assert_eq!(align_up_usize(1, 3), None);
+    assert_eq!(align_up_usize(usize::MAX, 2), None);
+    assert_eq!(
+        array_layout::<u64>(usize::MAX),
+        Err(AllocError::LayoutError(8, 8))
+    );
+    assert_eq!(validate_layout(4, 3), Err(AllocError::LayoutError(4, 3)));
+
+    // the tail is padded out to the largest alignment
+    let l = LayoutBuilder::new()
+        .field::<u32>()
+        .field::<u8>()
+        .build()
+        .unwrap();
+    assert_eq!((l.size(), l.align()), (8, 4));
+    assert_eq!(LayoutBuilder::new().build(), Ok(Layout::new::<()>()));
+    // the first error sticks
+    assert_eq!(
+        LayoutBuilder::new()
+            .array::<u16>(usize::MAX)
+            .field::<u8>()
+            .build(),
+        Err(AllocError::LayoutError(2, 2))
+    );
+    assert_eq!(
+        LayoutBuilder::new()
+            .array::<u8>(isize::MAX as usize)
+            .field::<u16>()
+            .build(),
+        Err(AllocError::LayoutError(isize::MAX as usize + 3, 2))
+    );
+}
+
+#[test]
+fn test_alloc_error_classification() {
+    let layout = Layout::new::<u64>();
+    let oom = AllocError::AllocFailed(layout);
+    assert!(oom.is_transient());
+    assert!(!oom.is_programmer_error());
+
+    for bug in [
+        AllocError::ArithmeticOverflow,
+        AllocError::LayoutError(1, 3),
+        AllocError::GrowSmallerNewLayout(8, 4),
+        AllocError::ShrinkBiggerNewLayout(4, 8),
+    ] {
+        assert!(bug.is_programmer_error(), "{bug}");
+        assert!(!bug.is_transient(), "{bug}");
+    }
+
+    let zst = AllocError::ZeroSizedLayout(NonNull::dangling());
+    assert!(!zst.is_transient());
+    assert!(!zst.is_programmer_error());
+
+    let refused = AllocError::Unsupported("too big");
+    assert!(!refused.is_transient());
+    assert!(!refused.is_programmer_error());
+    assert_eq!(refused.to_string(), "unsupported allocation: too big");
+}
+
+#[test]
+fn test_alloc_result_ext() {
+    let layout = Layout::new::<u64>();
+    let ok: Result<u8, AllocError> = Ok(1);
+    assert!(!ok.is_oom());
+    assert!(!ok.is_layout_err());
+    assert_eq!(ok.unwrap_or_oom(), 1);
+
+    let oom: Result<u8, AllocError> = Err(AllocError::AllocFailed(layout));
+    assert!(oom.is_oom());
+    assert!(!oom.is_layout_err());
+
+    let bad: Result<u8, AllocError> = Err(AllocError::LayoutError(1, 3));
+    assert!(bad.is_layout_err());
+    assert!(!bad.is_oom());
+}
+
+#[test]
+#[should_panic(expected = "computed invalid layout")]
+fn test_unwrap_or_oom_panics_on_programmer_error() {
+    let bad: Result<u8, AllocError> = Err(AllocError::LayoutError(1, 3));
+    let _ = bad.unwrap_or_oom();
+}
+
+#[test]
+fn test_default_reserve_is_noop() {
+    assert_eq!(DefaultAlloc.reserve(usize::MAX), Ok(()));
+}
+
 #[cfg(feature = "alloc_ext")]
 mod alloc_ext_tests {
     use super::*;
@@ -292,227 +450,2909 @@ mod alloc_ext_tests {
             allocator.dealloc(dptr.cast(), Layout::array::<u32>(len).unwrap());
         }
     }
-}
-
-#[cfg(all(feature = "stats", feature = "std"))]
-mod stats_gathering_tests {
-    use core::{
-        alloc::Layout,
-        sync::atomic::{AtomicUsize, Ordering},
-    };
-    use memapi::{
-        Alloc,
-        stats::{FmtLog, Stats},
-    };
 
     #[test]
-    fn test_stats_counts_correct() {
-        let logger = AtomicUsize::new(0);
-        let stats_alloc = Stats::new(&logger);
-        let layout = Layout::from_size_align(16, 8).unwrap();
-
-        let ptr = stats_alloc.alloc(layout).expect("alloc failed");
-        assert_eq!(
-            logger.load(Ordering::SeqCst),
-            layout.size(),
-            "expected total bytes = {} after alloc",
-            layout.size()
-        );
+    fn test_alloc_zeroable_slice() {
+        let allocator = DefaultAlloc;
+        let ptr = allocator.alloc_zeroable_slice::<u64>(1 << 16).unwrap();
+        let slice: &[u64] = unsafe { ptr.as_ref() };
+        assert_eq!(slice.len(), 1 << 16);
+        assert!(slice.iter().all(|&v| v == 0));
+        unsafe {
+            allocator.dealloc_n(ptr.cast::<u64>(), 1 << 16);
+        }
 
-        unsafe { stats_alloc.dealloc(ptr, layout) };
-        assert_eq!(
-            logger.load(Ordering::SeqCst),
-            0,
-            "expected total bytes = 0 after dealloc"
-        );
+        let ptr = allocator.alloc_zeroable_slice::<[f32; 3]>(4).unwrap();
+        assert_eq!(unsafe { ptr.as_ref() }, &[[0.0; 3]; 4]);
+        unsafe {
+            allocator.dealloc_n(ptr.cast::<[f32; 3]>(), 4);
+        }
     }
 
     #[test]
-    fn test_stats_str_logger_test() {
-        let logger = FmtLog::new(String::new());
-        let stats_alloc = Stats::new(&logger);
+    fn test_alloc_write_for_ffi_round_trip() {
+        use std::rc::Rc;
 
-        let layout = Layout::from_size_align(16, 8).unwrap();
+        #[repr(C)]
+        struct Handle {
+            id: u32,
+            owner: Rc<()>,
+        }
 
-        let ptr = stats_alloc.alloc(layout).unwrap();
+        // stands in for foreign code holding the pointer and handing it back later
+        extern "C" fn foreign_bump(h: *mut Handle) -> *mut Handle {
+            unsafe { (*h).id += 1 };
+            h
+        }
 
+        let allocator = DefaultAlloc;
+        let owner = Rc::new(());
+        let raw = allocator
+            .alloc_write_for_ffi(Handle {
+                id: 7,
+                owner: owner.clone(),
+            })
+            .unwrap();
+        let back = foreign_bump(raw);
+        assert_eq!(unsafe { (*back).id }, 8);
+        assert_eq!(Rc::strong_count(&owner), 2);
         unsafe {
-            stats_alloc.dealloc(ptr, layout);
+            allocator.reclaim_from_ffi(back);
+            // null is ignored
+            allocator.reclaim_from_ffi(core::ptr::null_mut::<Handle>());
         }
-
-        assert_eq!(
-            *logger.get_log(),
-            format!(
-                "Successful initial allocation of 16 bytes with alignment 8 at {ptr:p}, and newly \
-                allocated bytes being uninitialized. (16 total bytes allocated)\nDeallocation of \
-                16 bytes with alignment 8 at {ptr:p}. (0 total bytes allocated)\n"
-            )
-        );
+        assert_eq!(Rc::strong_count(&owner), 1);
     }
-}
 
-#[cfg(feature = "owned")]
-mod owned_tests {
-    use super::*;
-    use memapi::owned::{OwnedBuf, VariableError};
+    #[test]
+    fn test_alloc_write_layout() {
+        let allocator = DefaultAlloc;
+        let layout = Layout::from_size_align(64, 16).unwrap();
+        let ptr = unsafe { allocator.alloc_write_layout(0xDEAD_BEEFu32, layout) }.unwrap();
+        assert_eq!(ptr.addr().get() % 16, 0);
+        unsafe {
+            assert_eq!(ptr.read(), 0xDEAD_BEEF);
+            // the trailing space is usable
+            ptr.cast::<u8>().add(63).write(1);
+            allocator.dealloc(ptr.cast(), layout);
+        }
+    }
 
     #[test]
-    fn test_new_and_basic_properties() {
-        // create a buffer of 5 bytes
-        let mut buf = OwnedBuf::<u8, DefaultAlloc>::new_in(5, DefaultAlloc).unwrap();
-        assert_eq!(buf.size(), 5);
-        assert_eq!(buf.initialized(), 0);
-        assert_eq!(buf.buf().len(), 5);
-        assert_eq!(buf.uninit_buf().len(), 5);
-        assert_eq!(buf.init_buf().len(), 0);
+    fn test_vec_into_alloc() {
+        use std::rc::Rc;
 
-        // fill it with try_init_next
-        for i in 0..5u8 {
-            buf.try_init_next(i).unwrap();
-        }
-        assert_eq!(buf.initialized(), 5);
-        // values must match
-        #[allow(clippy::cast_possible_truncation)]
-        for (i, &val) in buf.init_buf().iter().enumerate() {
-            assert_eq!(val, i as u8);
+        let allocator = DefaultAlloc;
+        let counter = Rc::new(());
+        let v: Vec<Rc<()>> = (0..5).map(|_| Rc::clone(&counter)).collect();
+        let ptr = allocator.vec_into_alloc(v).unwrap();
+        // moved, not cloned, and not dropped by the vector
+        assert_eq!(Rc::strong_count(&counter), 6);
+        assert_eq!(ptr.len(), 5);
+        assert!(
+            unsafe { ptr.as_ref() }
+                .iter()
+                .all(|rc| Rc::ptr_eq(rc, &counter))
+        );
+        unsafe {
+            allocator.drop_and_dealloc(ptr);
         }
-        // further try_init_next returns Err with the value back
-        let v = 0xFF;
-        assert_eq!(buf.try_init_next(v).unwrap_err(), v);
-
-        buf.drop_and_dealloc();
+        assert_eq!(Rc::strong_count(&counter), 1);
     }
 
     #[test]
-    fn test_remove_and_replace_and_get() {
-        let mut buf = OwnedBuf::<u32, DefaultAlloc>::new_in(5, DefaultAlloc).unwrap();
-        for i in 0..5 {
-            buf.try_init_next(i).unwrap();
+    fn test_or_variants_convert_errors() {
+        #[derive(Debug, PartialEq)]
+        enum AppError {
+            Alloc(AllocError),
+        }
+        impl From<AllocError> for AppError {
+            fn from(e: AllocError) -> AppError {
+                AppError::Alloc(e)
+            }
         }
-        assert_eq!(buf.initialized(), 5);
-
-        // remove_last
-        assert_eq!(buf.remove_last(), Some(4));
-        assert_eq!(buf.initialized(), 4);
-
-        // remove at index
-        assert_eq!(buf.remove(1), Some(1));
-        assert_eq!(buf.initialized(), 3);
-        // out of range remove returns None
-        assert!(buf.remove(10).is_none());
 
-        // replace_last
-        let old = buf.replace_last(100).unwrap();
-        assert_eq!(old, 3);
-        assert_eq!(buf.init_buf().last().copied(), Some(100));
+        fn build<A: Alloc>(allocator: &A) -> Result<(NonNull<u32>, NonNull<[u8]>), AppError> {
+            let a = allocator.alloc_write_or::<_, AppError>(7u32)?;
+            let b = allocator.alloc_clone_slice_to_or::<_, AppError>(&[1u8, 2, 3])?;
+            Ok((a, b))
+        }
 
-        // replace at index
-        let old2 = buf.replace(1, 50).unwrap();
-        assert_eq!(old2, 2);
-        assert_eq!(*buf.get(1).unwrap(), 50);
-        // get out of bounds
-        assert!(buf.get(10).is_none());
-        assert!(buf.get_ptr(10).is_none());
-        assert!(buf.get_ptr(1).is_some());
+        let allocator = DefaultAlloc;
+        let (a, b) = build(&allocator).unwrap();
+        unsafe {
+            assert_eq!(a.read(), 7);
+            assert_eq!(b.as_ref(), [1, 2, 3]);
+            allocator.drop_and_dealloc(a);
+            allocator.drop_and_dealloc(b);
+        }
 
-        buf.drop_and_dealloc();
+        let res: Result<_, AppError> = allocator.alloc_slice_with_or(usize::MAX, |_| 0u64);
+        assert!(matches!(
+            res,
+            Err(AppError::Alloc(AllocError::LayoutError(..)))
+        ));
+        let res: Result<_, AppError> = allocator.alloc_write_or(());
+        assert!(matches!(
+            res,
+            Err(AppError::Alloc(AllocError::ZeroSizedLayout(_)))
+        ));
     }
 
     #[test]
-    fn test_into_and_from_raw_parts() {
-        let mut buf = OwnedBuf::<i16, DefaultAlloc>::new_in(4, DefaultAlloc).unwrap();
-        for i in 0..4 {
-            buf.try_init_next(i * 10).unwrap();
+    fn test_alloc_cyclic() {
+        struct Node {
+            next: NonNull<Node>,
+            val: u32,
         }
-        let init = buf.initialized();
-        let size = buf.size();
-        let (ptr, got_init, got_size, alloc) = buf.into_raw_parts();
-        assert_eq!(got_init, init);
-        assert_eq!(got_size, size);
-        // rebuild
-        let buf2 = unsafe { OwnedBuf::from_raw_parts(ptr, got_init, got_size, alloc) };
-        assert_eq!(buf2.initialized(), init);
-        assert_eq!(buf2.size(), size);
-        // values are intact
-        #[allow(clippy::cast_possible_truncation)]
-        #[allow(clippy::cast_possible_wrap)]
-        for (i, &v) in buf2.init_buf().iter().enumerate() {
-            assert_eq!(v, (i as i16) * 10);
+
+        let allocator = DefaultAlloc;
+        let node = allocator
+            .alloc_cyclic(|this| Node { next: this, val: 9 })
+            .unwrap();
+        unsafe {
+            assert_eq!(node.as_ref().next, node);
+            assert_eq!(node.as_ref().next.as_ref().val, 9);
+            allocator.drop_and_dealloc(node);
         }
 
-        buf2.drop_and_dealloc();
+        // the address is usable, only the contents aren't
+        let ptr = allocator
+            .alloc_cyclic(|this: NonNull<usize>| this.addr().get())
+            .unwrap();
+        assert_eq!(unsafe { ptr.read() }, ptr.addr().get());
+        unsafe { allocator.drop_and_dealloc(ptr) };
     }
 
     #[test]
-    fn test_try_insert_and_try_insert_grow() {
-        let mut buf = OwnedBuf::<u8, DefaultAlloc>::new_in(3, DefaultAlloc).unwrap();
-        // fill two
-        buf.try_init_next(1).unwrap();
-        buf.try_init_next(2).unwrap();
-        assert_eq!(buf.initialized(), 2);
+    fn test_alloc_header_tail() {
+        use std::rc::Rc;
 
-        // try_insert within capacity
-        buf.try_insert(1, 99).unwrap();
-        assert_eq!(buf.initialized(), 3);
-        assert_eq!(*buf.get(1).unwrap(), 99);
+        let counter = Rc::new(());
+        let mut ht = DefaultAlloc
+            .alloc_header_tail::<(u8, String), Rc<()>>((7, String::from("hdr")), 1)
+            .unwrap();
+        assert_eq!((ht.tail_len(), ht.tail_capacity()), (0, 1));
+        for _ in 0..9 {
+            ht.push_tail(Rc::clone(&counter)).unwrap();
+        }
+        // growing doubled the capacity, carrying the header along
+        assert_eq!((ht.tail_len(), ht.tail_capacity()), (9, 16));
+        assert_eq!(ht.header(), &(7, String::from("hdr")));
+        assert_eq!(ht.tail().as_ptr().addr() % align_of::<Rc<()>>(), 0);
+        assert_eq!(Rc::strong_count(&counter), 10);
 
-        // further try_insert fails and returns Err
-        assert_eq!(buf.try_insert(0, 77).unwrap_err(), 77);
+        ht.header_mut().1.push('!');
+        ht.realloc_tail(4).unwrap();
+        assert_eq!((ht.tail_len(), ht.tail_capacity()), (4, 4));
+        assert_eq!(Rc::strong_count(&counter), 5);
+        assert_eq!(ht.header().1, "hdr!");
 
-        // try_insert_grow: start with capacity 1 buffer
-        let mut buf2 = OwnedBuf::<u8, DefaultAlloc>::new_in(1, DefaultAlloc).unwrap();
-        buf2.try_init_next(10).unwrap();
-        assert_eq!(buf2.size(), 1);
-        // this will grow
-        buf2.init_next_grow(20).unwrap();
-        assert!(buf2.size() >= 2);
-        assert_eq!(buf2.initialized(), 2);
-        assert_eq!(buf2.init_buf(), &[10, 20]);
+        drop(ht);
+        assert_eq!(Rc::strong_count(&counter), 1);
 
-        buf.drop_and_dealloc();
-        buf2.drop_and_dealloc();
+        // the tail is padded out to its alignment after a small header
+        let mut ht = DefaultAlloc.alloc_header_tail::<u8, u64>(1, 2).unwrap();
+        ht.push_tail(u64::MAX).unwrap();
+        assert_eq!(
+            ht.tail().as_ptr().addr() - (&raw const *ht.header()).addr(),
+            8
+        );
+        assert_eq!(ht.tail(), &[u64::MAX]);
+
+        assert!(matches!(
+            DefaultAlloc.alloc_header_tail::<(), u8>((), 0),
+            Err(memapi::AllocError::ZeroSizedLayout(_))
+        ));
     }
 
     #[test]
-    fn test_remove_slice() {
-        let mut buf = OwnedBuf::<u32, DefaultAlloc>::new_in(16, DefaultAlloc).unwrap();
-        for i in 0..16 {
-            buf.try_init_next(i).unwrap();
+    fn test_drop_registry() {
+        use core::cell::RefCell;
+        use memapi::DropRegistry;
+
+        struct Noisy<'a>(u32, &'a RefCell<Vec<u32>>);
+        impl Drop for Noisy<'_> {
+            fn drop(&mut self) {
+                self.1.borrow_mut().push(self.0);
+            }
         }
-        // remove slice of length 3 at index 1
-        let maybe_slice = buf.remove_slice(3, 3);
-        assert!(maybe_slice.is_some());
-        let slice_res = maybe_slice.unwrap().unwrap();
-        assert_eq!(slice_res.initialized(), 3);
-        assert_eq!(slice_res.size(), 3);
-        assert_eq!(slice_res.init_buf(), &[3, 4, 5]);
-        // original buffer should have had 6 init, now 13 left
-        assert_eq!(buf.initialized(), 13);
 
-        buf.drop_and_dealloc();
-        slice_res.drop_and_dealloc();
+        let log = RefCell::new(Vec::new());
+        let reg = DropRegistry::new();
+        let ptrs = (0..3)
+            .map(|i| {
+                DefaultAlloc
+                    .alloc_write_registered(Noisy(i, &log), &reg)
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        // values without drop glue aren't registered
+        let plain = DefaultAlloc.alloc_write_registered(5u64, &reg).unwrap();
+        assert_eq!(reg.len(), 3);
+
+        reg.run_all();
+        assert_eq!(*log.borrow(), [2, 1, 0]);
+        assert!(reg.is_empty());
+        unsafe {
+            for ptr in ptrs {
+                DefaultAlloc.dealloc_typed(ptr);
+            }
+            DefaultAlloc.dealloc_typed(plain);
+        }
+
+        // whatever is left runs when the registry is dropped
+        log.borrow_mut().clear();
+        let ptr = {
+            let reg = DropRegistry::new();
+            DefaultAlloc
+                .alloc_write_registered(Noisy(9, &log), &reg)
+                .unwrap()
+        };
+        assert_eq!(*log.borrow(), [9]);
+        unsafe { DefaultAlloc.dealloc_typed(ptr) };
     }
 
     #[test]
-    fn test_debug_and_display_errors() {
-        // Debug impl for OwnedBuf
-        let buf = OwnedBuf::<u8, DefaultAlloc>::new_unallocated_in(DefaultAlloc);
-        let s = format!("{buf:?}");
-        assert!(s.contains("OwnedBuf"));
+    fn test_alloc_copy_bytes_nt() {
+        let allocator = DefaultAlloc;
+        #[allow(clippy::cast_possible_truncation)]
+        let big = (0..100_003u32).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        for src in [&big[..], &big[1..], &big[..40]] {
+            let copy = allocator.alloc_copy_bytes_nt(src).unwrap();
+            assert_eq!(unsafe { copy.as_ref() }, src);
+            unsafe { allocator.dealloc_n(copy.cast::<u8>(), src.len()) };
+        }
+        assert!(matches!(
+            allocator.alloc_copy_bytes_nt(&[]),
+            Err(memapi::AllocError::ZeroSizedLayout(_))
+        ));
+    }
 
-        // VariableError debug and display
-        let soft: VariableError<&str, &str> = VariableError::Soft("oops");
-        let hard: VariableError<&str, &str> = VariableError::Hard("boom");
-        let ds = format!("{soft:?}");
-        let dh = format!("{hard:?}");
-        assert!(ds.contains("Soft"));
-        assert!(dh.contains("Hard"));
+    #[cfg(feature = "metadata")]
+    #[test]
+    fn test_alloc_copy_ref_to_aligned() {
+        use memapi::AllocError;
 
-        let ls = format!("{soft}");
-        let lh = format!("{hard}");
-        assert!(ls.contains("oops"));
-        assert!(lh.contains("boom"));
+        let allocator = DefaultAlloc;
+        let floats = [1.0f32, 2.5, -3.0];
+        for align in [4, 32, 4096] {
+            let copy = allocator
+                .alloc_copy_ref_to_aligned(&floats[..], align)
+                .unwrap();
+            assert_eq!(copy.cast::<u8>().addr().get() % align, 0);
+            assert_eq!(unsafe { copy.as_ref() }, floats);
+            unsafe {
+                allocator.dealloc(
+                    copy.cast(),
+                    Layout::from_size_align(size_of_val(&floats), align).unwrap(),
+                );
+            }
+        }
+
+        assert_eq!(
+            allocator.alloc_copy_ref_to_aligned(&floats[..], 2),
+            Err(AllocError::LayoutError(12, 2))
+        );
+        assert_eq!(
+            allocator.alloc_copy_ref_to_aligned("str", 24),
+            Err(AllocError::LayoutError(3, 24))
+        );
+    }
+
+    #[test]
+    fn test_alloc_slice_until() {
+        use std::{
+            panic::{AssertUnwindSafe, catch_unwind},
+            rc::Rc,
+        };
+
+        let allocator = DefaultAlloc;
+        let mut src = ["a", "b", "c"].into_iter();
+        let ptr = allocator
+            .alloc_slice_until(8, |_| src.next().map(String::from))
+            .unwrap();
+        assert_eq!(unsafe { ptr.as_ref() }, ["a", "b", "c"]);
+        unsafe { allocator.drop_and_dealloc(ptr) };
+
+        let full = allocator.alloc_slice_until(4, Some).unwrap();
+        assert_eq!(unsafe { full.as_ref() }, [0, 1, 2, 3]);
+        unsafe { allocator.drop_and_dealloc(full) };
+
+        assert!(matches!(
+            allocator.alloc_slice_until(4, |_| None::<u32>),
+            Err(AllocError::ZeroSizedLayout(_))
+        ));
+
+        let counter = Rc::new(());
+        let res = catch_unwind(AssertUnwindSafe(|| {
+            allocator.alloc_slice_until(8, |i| {
+                assert!(i != 3, "source failed");
+                Some(Rc::clone(&counter))
+            })
+        }));
+        assert!(res.is_err());
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn test_alloc_slice_from_template() {
+        use std::{
+            panic::{AssertUnwindSafe, catch_unwind},
+            rc::Rc,
+        };
+
+        let allocator = DefaultAlloc;
+        let template = (String::from("row"), 0usize);
+        let ptr = allocator
+            .alloc_slice_from_template(3, &template, |(_, n), i| *n = i * 10)
+            .unwrap();
+        assert_eq!(
+            unsafe { ptr.as_ref() },
+            [
+                (String::from("row"), 0),
+                (String::from("row"), 10),
+                (String::from("row"), 20)
+            ]
+        );
+        unsafe { allocator.drop_and_dealloc(ptr) };
+
+        // the element being patched is dropped along with the finished ones
+        let counter = Rc::new(());
+        let res = catch_unwind(AssertUnwindSafe(|| {
+            allocator.alloc_slice_from_template(8, &counter, |_, i| {
+                assert!(i != 5, "patch failed");
+            })
+        }));
+        assert!(res.is_err());
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn test_alloc_slice_partitioned() {
+        use std::{
+            panic::{AssertUnwindSafe, catch_unwind},
+            rc::Rc,
+        };
+
+        let allocator = DefaultAlloc;
+        let (ptr, mid) = allocator
+            .alloc_slice_partitioned(7, |i| (i % 2 == 0, i))
+            .unwrap();
+        assert_eq!(mid, 4);
+        assert_eq!(unsafe { ptr.as_ref() }, [0, 2, 4, 6, 5, 3, 1]);
+        unsafe { allocator.dealloc_n(ptr.cast::<usize>(), ptr.len()) };
+
+        // elements written at both ends are dropped on panic
+        let counter = Rc::new(());
+        let res = catch_unwind(AssertUnwindSafe(|| {
+            allocator.alloc_slice_partitioned(8, |i| {
+                assert!(i != 5, "partition failed");
+                (i % 3 == 0, counter.clone())
+            })
+        }));
+        assert!(res.is_err());
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn test_alloc_repeat() {
+        use std::{
+            panic::{AssertUnwindSafe, catch_unwind},
+            rc::Rc,
+        };
+
+        let allocator = DefaultAlloc;
+        // lengths on and off powers of two exercise the final partial copy
+        for len in [1, 2, 7, 8, 33] {
+            let ptr = allocator
+                .alloc_repeat_copy((len as u16, 0xAAu8), len)
+                .unwrap();
+            assert_eq!(ptr.len(), len);
+            assert!(
+                unsafe { ptr.as_ref() }
+                    .iter()
+                    .all(|&e| e == (len as u16, 0xAA))
+            );
+            unsafe { allocator.drop_and_dealloc(ptr) };
+        }
+
+        let ptr = allocator.alloc_repeat(String::from("ab"), 3).unwrap();
+        assert_eq!(unsafe { ptr.as_ref() }, ["ab", "ab", "ab"]);
+        unsafe { allocator.drop_and_dealloc(ptr) };
+
+        // the value is moved into the last slot, so it's cloned one fewer time than `len`
+        let counter = Rc::new(());
+        let ptr = allocator.alloc_repeat(counter.clone(), 4).unwrap();
+        assert_eq!(Rc::strong_count(&counter), 5);
+        unsafe { allocator.drop_and_dealloc(ptr) };
+        assert_eq!(Rc::strong_count(&counter), 1);
+
+        // clones made before a panicking one are dropped
+        struct Bomb(Rc<()>, u8);
+        impl Clone for Bomb {
+            fn clone(&self) -> Bomb {
+                assert!(Rc::strong_count(&self.0) < 4, "clone failed");
+                Bomb(self.0.clone(), self.1)
+            }
+        }
+        let res = catch_unwind(AssertUnwindSafe(|| {
+            allocator.alloc_repeat(Bomb(counter.clone(), 1), 8)
+        }));
+        assert!(res.is_err());
+        assert_eq!(Rc::strong_count(&counter), 1);
+
+        // empty requests are refused up front, even by an allocator handing out empty blocks
+        struct Lenient;
+        impl Alloc for Lenient {
+            fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+                match layout.size() {
+                    0 => Ok(NonNull::dangling()),
+                    _ => DefaultAlloc.alloc(layout),
+                }
+            }
+            fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+                DefaultAlloc.alloc_zeroed(layout)
+            }
+            fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+                DefaultAlloc.alloc_filled(layout, n)
+            }
+            fn alloc_patterned<F: Fn(usize) -> u8 + Clone>(
+                &self,
+                layout: Layout,
+                pattern: F,
+            ) -> Result<NonNull<u8>, AllocError> {
+                DefaultAlloc.alloc_patterned(layout, pattern)
+            }
+            unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+                if layout.size() != 0 {
+                    unsafe { DefaultAlloc.dealloc(ptr, layout) };
+                }
+            }
+        }
+        assert!(matches!(
+            Lenient.alloc_repeat_copy(0u64, 0),
+            Err(AllocError::ZeroSizedLayout(_))
+        ));
+        assert!(matches!(
+            Lenient.alloc_repeat(String::new(), 0),
+            Err(AllocError::ZeroSizedLayout(_))
+        ));
+        assert!(matches!(
+            Lenient.alloc_repeat((), 4),
+            Err(AllocError::ZeroSizedLayout(_))
+        ));
+    }
+
+    #[test]
+    fn test_alloc_soa() {
+        use std::{
+            panic::{AssertUnwindSafe, catch_unwind},
+            rc::Rc,
+        };
+
+        struct Point {
+            x: f32,
+            y: f32,
+            z: f32,
+        }
+
+        let allocator = DefaultAlloc;
+        let points = [
+            Point {
+                x: 1.0,
+                y: 2.0,
+                z: 3.0,
+            },
+            Point {
+                x: 4.0,
+                y: 5.0,
+                z: 6.0,
+            },
+        ];
+        let (xs, ys, zs) = allocator
+            .alloc_soa_3(&points, |p| p.x, |p| p.y, |p| p.z)
+            .unwrap();
+        unsafe {
+            assert_eq!(xs.as_ref(), [1.0, 4.0]);
+            assert_eq!(ys.as_ref(), [2.0, 5.0]);
+            assert_eq!(zs.as_ref(), [3.0, 6.0]);
+            allocator.drop_and_dealloc(xs);
+            allocator.drop_and_dealloc(ys);
+            allocator.drop_and_dealloc(zs);
+        }
+
+        let (sums, labels) = allocator
+            .alloc_soa_2(&points, |p| p.x + p.y + p.z, |p| format!("{}", p.x))
+            .unwrap();
+        unsafe {
+            assert_eq!(sums.as_ref(), [6.0, 15.0]);
+            assert_eq!(labels.as_ref(), ["1", "4"]);
+            allocator.drop_and_dealloc(sums);
+            allocator.drop_and_dealloc(labels);
+        }
+
+        // a panic in a later projection drops the arrays already built
+        let counter = Rc::new(());
+        let res = catch_unwind(AssertUnwindSafe(|| {
+            allocator.alloc_soa_3(
+                &points,
+                |_| counter.clone(),
+                |_| counter.clone(),
+                |p| {
+                    assert!(p.x < 2.0, "projection failed");
+                    counter.clone()
+                },
+            )
+        }));
+        assert!(res.is_err());
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn test_drop_prefix_and_dealloc_slice() {
+        use std::rc::Rc;
+
+        let allocator = DefaultAlloc;
+        let counter = Rc::new(());
+        let ptr = allocator.alloc_slice::<Rc<()>>(8).unwrap();
+        let base = ptr.cast::<Rc<()>>();
+        for i in 0..3 {
+            unsafe { base.add(i).write(Rc::clone(&counter)) };
+        }
+        assert_eq!(Rc::strong_count(&counter), 4);
+        unsafe {
+            allocator.drop_prefix_and_dealloc_slice(NonNull::slice_from_raw_parts(base, 8), 3);
+        }
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn test_place() {
+        use core::mem::MaybeUninit;
+        use std::rc::Rc;
+
+        let allocator = DefaultAlloc;
+        let counter = Rc::new(());
+
+        let mut slot = MaybeUninit::uninit();
+        let mut placed = allocator
+            .place(&mut slot, Rc::clone(&counter), false)
+            .unwrap();
+        assert!(!placed.is_heap());
+        *placed = Rc::clone(&counter);
+        assert_eq!(Rc::strong_count(&counter), 2);
+        let placed = placed.into_heap().unwrap_err();
+        drop(placed);
+        assert_eq!(Rc::strong_count(&counter), 1);
+
+        let mut slot = MaybeUninit::uninit();
+        let placed = allocator
+            .place(&mut slot, Rc::clone(&counter), true)
+            .unwrap();
+        assert!(placed.is_heap());
+        assert!(Rc::ptr_eq(&placed, &counter));
+        drop(placed);
+        assert_eq!(Rc::strong_count(&counter), 1);
+
+        let mut slot = MaybeUninit::uninit();
+        let escaped = allocator
+            .place(&mut slot, Rc::clone(&counter), true)
+            .unwrap()
+            .into_heap()
+            .unwrap();
+        assert_eq!(Rc::strong_count(&counter), 2);
+        unsafe {
+            allocator.drop_and_dealloc(escaped);
+        }
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+}
+
+#[cfg(all(feature = "stats", feature = "std"))]
+mod stats_gathering_tests {
+    use core::{
+        alloc::Layout,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+    use memapi::{
+        Alloc,
+        stats::{FmtLog, Stats},
+    };
+
+    #[inline(never)]
+    fn allocate_in_named_frame<A: Alloc>(alloc: &A, layout: Layout) {
+        let p = alloc.alloc(layout).unwrap();
+        unsafe { alloc.dealloc(p, layout) };
+    }
+
+    #[test]
+    fn test_folded_stacks() {
+        use memapi::{DefaultAlloc, stats::FoldedStackAlloc};
+
+        let alloc = FoldedStackAlloc::new_in(DefaultAlloc, 1);
+        for _ in 0..3 {
+            allocate_in_named_frame(&alloc, Layout::new::<[u8; 100]>());
+        }
+        assert_eq!(alloc.total_bytes(), 300);
+
+        let mut out = Vec::new();
+        alloc.write_folded(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let (stack, bytes) = out.lines().next().unwrap().rsplit_once(' ').unwrap();
+        assert_eq!(bytes, "300");
+        // outermost frame first, the profiler's own frames left out
+        assert!(
+            stack
+                .split(';')
+                .all(|f| !f.trim_start_matches('<').starts_with("memapi::stats::"))
+        );
+        if stack != "[unknown]" {
+            assert!(stack.contains("allocate_in_named_frame"), "{stack}");
+        }
+
+        // sampled allocations are scaled back up
+        let sampled = FoldedStackAlloc::new_in(DefaultAlloc, 4);
+        for _ in 0..8 {
+            allocate_in_named_frame(&sampled, Layout::new::<u64>());
+        }
+        assert_eq!(sampled.total_bytes(), 64);
+        sampled.clear();
+        assert_eq!(sampled.total_bytes(), 0);
+    }
+
+    #[test]
+    fn test_watermarks_are_edge_triggered() {
+        use memapi::{
+            DefaultAlloc,
+            stats::{Watermark, WatermarkAlloc},
+        };
+        use std::sync::Mutex;
+
+        let events = Mutex::new(Vec::new());
+        let alloc = WatermarkAlloc::new_in(DefaultAlloc, 64, 256, |mark, live| {
+            events.lock().unwrap().push((mark, live));
+        });
+        let block = Layout::new::<[u8; 100]>();
+
+        let a = alloc.alloc(block).unwrap();
+        let b = alloc.alloc(block).unwrap();
+        assert!(events.lock().unwrap().is_empty());
+        let c = alloc.alloc_zeroed(block).unwrap();
+        // further allocations while high don't fire again
+        let d = alloc.alloc(block).unwrap();
+        assert!(alloc.is_high());
+        assert_eq!(*events.lock().unwrap(), [(Watermark::High, 300)]);
+
+        unsafe {
+            alloc.dealloc(d, block);
+            alloc.dealloc(c, block);
+            // between the watermarks, still high
+            alloc.dealloc(b, block);
+            assert!(alloc.is_high());
+            alloc.dealloc(a, block);
+        }
+        assert!(!alloc.is_high());
+        assert_eq!(alloc.live_bytes(), 0);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [(Watermark::High, 300), (Watermark::Low, 0)]
+        );
+    }
+
+    #[test]
+    fn test_watermark_crossings_alternate_across_threads() {
+        use memapi::{
+            DefaultAlloc,
+            stats::{Watermark, WatermarkAlloc},
+        };
+
+        let highs = AtomicUsize::new(0);
+        let lows = AtomicUsize::new(0);
+        let alloc = WatermarkAlloc::new_in(DefaultAlloc, 128, 1024, |mark, live| match mark {
+            Watermark::High => {
+                assert!(live > 1024);
+                highs.fetch_add(1, Ordering::Relaxed);
+            }
+            Watermark::Low => {
+                assert!(live < 128);
+                lows.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        let block = Layout::new::<[u8; 64]>();
 
+        // each thread holds up to 256 bytes, so together they keep crossing both watermarks
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..2000 {
+                        let ptrs = [(); 4].map(|()| alloc.alloc(block).unwrap());
+                        for p in ptrs {
+                            unsafe { alloc.dealloc(p, block) };
+                        }
+                    }
+                });
+            }
+        });
+
+        assert_eq!(alloc.live_bytes(), 0);
+        assert!(!alloc.is_high());
+        assert_eq!(highs.load(Ordering::Relaxed), lows.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_whales_report_their_caller() {
+        use memapi::{DefaultAlloc, stats::WhaleAlloc};
+        use std::sync::Mutex;
+
+        let seen = Mutex::new(Vec::new());
+        let alloc = WhaleAlloc::new_in(DefaultAlloc, 1024, |layout, caller| {
+            seen.lock().unwrap().push((layout.size(), caller.line()));
+        });
+        let small = Layout::new::<[u8; 1023]>();
+        let big = Layout::new::<[u8; 1024]>();
+
+        let a = alloc.alloc(small).unwrap();
+        let line = line!() + 1;
+        let b = alloc.alloc_zeroed(big).unwrap();
+        // growing allocates the new block through the wrapper
+        let a = unsafe { alloc.grow(a, small, Layout::new::<[u8; 4096]>()) }.unwrap();
+        let grow_line = line!() - 1;
+        assert_eq!(*seen.lock().unwrap(), [(1024, line), (4096, grow_line)]);
+        assert_eq!(alloc.whales(), 2);
+        assert_eq!(alloc.whale_bytes(), 5120);
+
+        unsafe {
+            alloc.dealloc(a, Layout::new::<[u8; 4096]>());
+            alloc.dealloc(b, big);
+        }
+        assert_eq!(alloc.whales(), 2);
+    }
+
+    #[test]
+    fn test_size_anomalies_are_reported() {
+        use memapi::{DefaultAlloc, stats::AnomalyAlloc};
+        use std::sync::Mutex;
+
+        let seen = Mutex::new(Vec::new());
+        let alloc = AnomalyAlloc::new_in(DefaultAlloc, 4.0, |layout, stats| {
+            seen.lock().unwrap().push((layout.size(), stats.count));
+        })
+        .with_warmup(8);
+        let mut ptrs = Vec::new();
+        let mut alloc_size = |size| {
+            let layout = Layout::from_size_align(size, 1).unwrap();
+            ptrs.push((alloc.alloc(layout).unwrap(), layout));
+        };
+
+        // nothing is judged until the warmup allocations are in
+        for size in [60, 64, 68, 64, 60, 68, 64, 64] {
+            alloc_size(size);
+        }
+        let stats = alloc.stats();
+        assert_eq!(stats.count, 8);
+        assert!((stats.mean - 64.0).abs() < 1e-9);
+        assert!((stats.std_dev - 8f64.sqrt()).abs() < 1e-9);
+
+        alloc_size(72);
+        alloc_size(1 << 20);
+        // outliers stay out of the statistics, so repeats keep firing
+        alloc_size(1 << 20);
+        alloc_size(64);
+        assert_eq!(*seen.lock().unwrap(), [(1 << 20, 9), (1 << 20, 9)]);
+        assert_eq!(alloc.anomalies(), 2);
+        assert_eq!(alloc.stats().count, 10);
+
+        for (p, layout) in ptrs {
+            unsafe { alloc.dealloc(p, layout) };
+        }
+    }
+
+    #[test]
+    fn test_failures_are_counted() {
+        use memapi::stats::{CountingLog, StatsLogger};
+
+        let alloc = Stats::new(CountingLog::new());
+        let small = Layout::new::<u32>();
+        let p = alloc.alloc(small).unwrap();
+        assert_eq!(alloc.1.alloc_failures(), 0);
+
+        // zero-sized allocations and growing to a smaller layout are both refused
+        assert!(alloc.alloc(Layout::new::<()>()).is_err());
+        assert!(unsafe { alloc.grow(p, Layout::new::<u64>(), small) }.is_err());
+        assert!(unsafe { alloc.shrink(p, small, Layout::new::<u64>()) }.is_err());
+        assert_eq!(alloc.1.alloc_failures(), 3);
+        assert_eq!(alloc.1.total(), 4);
+
+        unsafe { alloc.dealloc(p, small) };
+        assert_eq!(alloc.1.alloc_failures(), 3);
+        assert_eq!(alloc.1.total(), 0);
+        // loggers which don't count failures report none
+        assert_eq!(AtomicUsize::new(0).alloc_failures(), 0);
+    }
+
+    #[test]
+    fn test_peak_bytes_and_reset() {
+        use core::ptr::NonNull;
+        use memapi::{
+            DefaultAlloc,
+            stats::{AllocPeak, CountingLog, WatermarkAlloc},
+        };
+
+        // one phase peaking at 96 bytes, then a lighter one peaking at 48
+        fn run_phases<P: Alloc + AllocPeak>(alloc: &P) {
+            let big = Layout::from_size_align(64, 8).unwrap();
+            let small = Layout::from_size_align(32, 8).unwrap();
+            let kept = alloc.alloc(small).unwrap();
+            let p = alloc.alloc(big).unwrap();
+            unsafe { alloc.dealloc(p, big) };
+            assert_eq!(alloc.peak_bytes(), 96);
+
+            // the reset drops the peak to what's still live
+            alloc.reset_peak();
+            assert_eq!(alloc.peak_bytes(), 32);
+            let q = alloc
+                .alloc(Layout::from_size_align(16, 8).unwrap())
+                .unwrap();
+            unsafe {
+                alloc.dealloc(q, Layout::from_size_align(16, 8).unwrap());
+                alloc.dealloc(kept, small);
+            }
+            assert_eq!(alloc.peak_bytes(), 48);
+            alloc.reset_peak();
+            assert_eq!(alloc.peak_bytes(), 0);
+        }
+
+        run_phases(&Stats::new(CountingLog::new()));
+        run_phases(&WatermarkAlloc::new_in(
+            DefaultAlloc,
+            0,
+            usize::MAX,
+            |_, _| {},
+        ));
+
+        // a reset racing allocations never ends below what's live afterward
+        let alloc = Stats::new(CountingLog::new());
+        let layout = Layout::new::<u64>();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for _ in 0..1000 {
+                    alloc.reset_peak();
+                }
+            });
+            let ptrs: Vec<_> = (0..1000)
+                .map(|_| alloc.alloc(layout).unwrap().addr().get())
+                .collect();
+            assert!(alloc.peak_bytes() >= 8000);
+            for p in ptrs {
+                unsafe { alloc.dealloc(NonNull::new(p as *mut u8).unwrap(), layout) };
+            }
+        });
+    }
+
+    #[test]
+    fn test_stats_counts_correct() {
+        let logger = AtomicUsize::new(0);
+        let stats_alloc = Stats::new(&logger);
+        let layout = Layout::from_size_align(16, 8).unwrap();
+
+        let ptr = stats_alloc.alloc(layout).expect("alloc failed");
+        assert_eq!(
+            logger.load(Ordering::SeqCst),
+            layout.size(),
+            "expected total bytes = {} after alloc",
+            layout.size()
+        );
+
+        unsafe { stats_alloc.dealloc(ptr, layout) };
+        assert_eq!(
+            logger.load(Ordering::SeqCst),
+            0,
+            "expected total bytes = 0 after dealloc"
+        );
+    }
+
+    #[test]
+    fn test_stats_str_logger_test() {
+        let logger = FmtLog::new(String::new());
+        let stats_alloc = Stats::new(&logger);
+
+        let layout = Layout::from_size_align(16, 8).unwrap();
+
+        let ptr = stats_alloc.alloc(layout).unwrap();
+
+        unsafe {
+            stats_alloc.dealloc(ptr, layout);
+        }
+
+        assert_eq!(
+            *logger.get_log(),
+            format!(
+                "Successful initial allocation of 16 bytes with alignment 8 at {ptr:p}, and newly \
+                allocated bytes being uninitialized. (16 total bytes allocated)\nDeallocation of \
+                16 bytes with alignment 8 at {ptr:p}. (0 total bytes allocated)\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        use memapi::stats::{Snapshot, SnapshotAlloc};
+
+        let alloc = SnapshotAlloc::new();
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        let kept = alloc.alloc(layout).unwrap();
+        let freed = alloc.alloc_zeroed(layout).unwrap();
+
+        let before = alloc.snapshot();
+        assert_eq!(before.entries().len(), 2);
+        assert_eq!(before.total_bytes(), 48);
+
+        unsafe { alloc.dealloc(freed, layout) };
+        let line = line!() + 1;
+        let leaked = alloc.alloc(Layout::new::<u64>()).unwrap();
+        let after = alloc.snapshot();
+
+        let diff = Snapshot::diff(&before, &after);
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].addr, freed.addr().get());
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].addr, leaked.addr().get());
+        assert_eq!(diff.added[0].size, 8);
+        assert_eq!(diff.added[0].caller.file(), file!());
+        assert_eq!(diff.added[0].caller.line(), line);
+
+        assert!(Snapshot::diff(&after, &after.clone()).is_empty());
+
+        unsafe {
+            alloc.dealloc(kept, layout);
+            alloc.dealloc(leaked, Layout::new::<u64>());
+        }
+        assert!(alloc.snapshot().entries().is_empty());
+    }
+}
+
+#[cfg(feature = "owned")]
+mod owned_tests {
+    use super::*;
+    use memapi::owned::{OwnedBuf, VariableError};
+
+    #[test]
+    fn test_alloc_arc() {
+        use memapi::owned::AllocArc;
+        use std::sync::Arc;
+
+        let tracker = Arc::new(());
+        let a = AllocArc::new((7u32, tracker.clone())).unwrap();
+        let b = a.clone();
+        assert_eq!(AllocArc::strong_count(&a), 2);
+        assert!(AllocArc::ptr_eq(&a, &b));
+        assert_eq!(b.0, 7);
+
+        // shared values can't be unwrapped
+        let a = AllocArc::try_unwrap(a).unwrap_err();
+        drop(b);
+        let (n, t) = AllocArc::try_unwrap(a).unwrap();
+        assert_eq!(n, 7);
+        drop(t);
+        assert_eq!(Arc::strong_count(&tracker), 1);
+
+        // clones dropped on other threads free the value exactly once
+        let shared = AllocArc::new(tracker.clone()).unwrap();
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                let c = shared.clone();
+                s.spawn(move || assert!(Arc::strong_count(&c) >= 2));
+            }
+        });
+        assert_eq!(AllocArc::strong_count(&shared), 1);
+        drop(shared);
+        assert_eq!(Arc::strong_count(&tracker), 1);
+    }
+
+    #[cfg(all(feature = "alloc_ext", feature = "stats"))]
+    #[test]
+    fn test_alloc_arc_frees_through_its_allocator() {
+        use core::sync::atomic::AtomicUsize;
+        use memapi::{
+            AllocExt,
+            stats::{Stats, StatsLogger},
+        };
+
+        let pool = Stats(DefaultAlloc, AtomicUsize::new(0));
+        let a = pool.alloc_arc([1u64; 4]).unwrap();
+        let b = a.clone();
+        assert!(pool.1.total() >= 32);
+        drop(a);
+        assert!(pool.1.total() >= 32);
+        assert_eq!(*b, [1; 4]);
+        drop(b);
+        assert_eq!(pool.1.total(), 0);
+    }
+
+    #[test]
+    fn test_spare_capacity_and_set_len() {
+        let mut buf = OwnedBuf::<u16>::new(6).unwrap();
+        buf.try_init_next(1).unwrap();
+        assert_eq!(buf.as_ptr(), buf.as_slice().as_ptr());
+
+        // fill part of the spare capacity directly, then commit it
+        let spare = buf.spare_capacity_mut();
+        assert_eq!(spare.len(), 5);
+        spare[0].write(2);
+        spare[1].write(3);
+        unsafe { buf.set_len(3) };
+        assert_eq!(buf.as_slice(), [1, 2, 3]);
+        assert_eq!(buf.spare_capacity_mut().len(), 3);
+
+        unsafe { buf.as_mut_ptr().add(3).write(4) };
+        unsafe { buf.set_len(4) };
+        assert_eq!(buf.as_slice(), [1, 2, 3, 4]);
         buf.drop_and_dealloc();
     }
+
+    #[test]
+    fn test_new_and_basic_properties() {
+        // create a buffer of 5 bytes
+        let mut buf = OwnedBuf::<u8, DefaultAlloc>::new_in(5, DefaultAlloc).unwrap();
+        assert_eq!(buf.size(), 5);
+        assert_eq!(buf.initialized(), 0);
+        assert_eq!(buf.buf().len(), 5);
+        assert_eq!(buf.uninit_buf().len(), 5);
+        assert_eq!(buf.init_buf().len(), 0);
+
+        // fill it with try_init_next
+        for i in 0..5u8 {
+            buf.try_init_next(i).unwrap();
+        }
+        assert_eq!(buf.initialized(), 5);
+        // values must match
+        #[allow(clippy::cast_possible_truncation)]
+        for (i, &val) in buf.init_buf().iter().enumerate() {
+            assert_eq!(val, i as u8);
+        }
+        // further try_init_next returns Err with the value back
+        let v = 0xFF;
+        assert_eq!(buf.try_init_next(v).unwrap_err(), v);
+
+        buf.drop_and_dealloc();
+    }
+
+    #[test]
+    fn test_remove_and_replace_and_get() {
+        let mut buf = OwnedBuf::<u32, DefaultAlloc>::new_in(5, DefaultAlloc).unwrap();
+        for i in 0..5 {
+            buf.try_init_next(i).unwrap();
+        }
+        assert_eq!(buf.initialized(), 5);
+
+        // remove_last
+        assert_eq!(buf.remove_last(), Some(4));
+        assert_eq!(buf.initialized(), 4);
+
+        // remove at index
+        assert_eq!(buf.remove(1), Some(1));
+        assert_eq!(buf.initialized(), 3);
+        // out of range remove returns None
+        assert!(buf.remove(10).is_none());
+
+        // replace_last
+        let old = buf.replace_last(100).unwrap();
+        assert_eq!(old, 3);
+        assert_eq!(buf.init_buf().last().copied(), Some(100));
+
+        // replace at index
+        let old2 = buf.replace(1, 50).unwrap();
+        assert_eq!(old2, 2);
+        assert_eq!(*buf.get(1).unwrap(), 50);
+        // get out of bounds
+        assert!(buf.get(10).is_none());
+        assert!(buf.get_ptr(10).is_none());
+        assert!(buf.get_ptr(1).is_some());
+
+        buf.drop_and_dealloc();
+    }
+
+    #[test]
+    fn test_into_and_from_raw_parts() {
+        let mut buf = OwnedBuf::<i16, DefaultAlloc>::new_in(4, DefaultAlloc).unwrap();
+        for i in 0..4 {
+            buf.try_init_next(i * 10).unwrap();
+        }
+        let init = buf.initialized();
+        let size = buf.size();
+        let (ptr, got_init, got_size, alloc) = buf.into_raw_parts();
+        assert_eq!(got_init, init);
+        assert_eq!(got_size, size);
+        // rebuild
+        let buf2 = unsafe { OwnedBuf::from_raw_parts(ptr, got_init, got_size, alloc) };
+        assert_eq!(buf2.initialized(), init);
+        assert_eq!(buf2.size(), size);
+        // values are intact
+        #[allow(clippy::cast_possible_truncation)]
+        #[allow(clippy::cast_possible_wrap)]
+        for (i, &v) in buf2.init_buf().iter().enumerate() {
+            assert_eq!(v, (i as i16) * 10);
+        }
+
+        buf2.drop_and_dealloc();
+    }
+
+    #[test]
+    fn test_try_insert_and_try_insert_grow() {
+        let mut buf = OwnedBuf::<u8, DefaultAlloc>::new_in(3, DefaultAlloc).unwrap();
+        // fill two
+        buf.try_init_next(1).unwrap();
+        buf.try_init_next(2).unwrap();
+        assert_eq!(buf.initialized(), 2);
+
+        // try_insert within capacity
+        buf.try_insert(1, 99).unwrap();
+        assert_eq!(buf.initialized(), 3);
+        assert_eq!(*buf.get(1).unwrap(), 99);
+
+        // further try_insert fails and returns Err
+        assert_eq!(buf.try_insert(0, 77).unwrap_err(), 77);
+
+        // try_insert_grow: start with capacity 1 buffer
+        let mut buf2 = OwnedBuf::<u8, DefaultAlloc>::new_in(1, DefaultAlloc).unwrap();
+        buf2.try_init_next(10).unwrap();
+        assert_eq!(buf2.size(), 1);
+        // this will grow
+        buf2.init_next_grow(20).unwrap();
+        assert!(buf2.size() >= 2);
+        assert_eq!(buf2.initialized(), 2);
+        assert_eq!(buf2.init_buf(), &[10, 20]);
+
+        buf.drop_and_dealloc();
+        buf2.drop_and_dealloc();
+    }
+
+    #[test]
+    fn test_remove_slice() {
+        let mut buf = OwnedBuf::<u32, DefaultAlloc>::new_in(16, DefaultAlloc).unwrap();
+        for i in 0..16 {
+            buf.try_init_next(i).unwrap();
+        }
+        // remove slice of length 3 at index 1
+        let maybe_slice = buf.remove_slice(3, 3);
+        assert!(maybe_slice.is_some());
+        let slice_res = maybe_slice.unwrap().unwrap();
+        assert_eq!(slice_res.initialized(), 3);
+        assert_eq!(slice_res.size(), 3);
+        assert_eq!(slice_res.init_buf(), &[3, 4, 5]);
+        // original buffer should have had 6 init, now 13 left
+        assert_eq!(buf.initialized(), 13);
+
+        buf.drop_and_dealloc();
+        slice_res.drop_and_dealloc();
+    }
+
+    #[test]
+    fn test_extend_from_slice() {
+        let mut buf = OwnedBuf::<String, DefaultAlloc>::new_unallocated_in(DefaultAlloc);
+        buf.extend_from_slice(&[String::from("a"), String::from("b")])
+            .unwrap();
+        assert_eq!(buf.size(), 2);
+        buf.extend_from_slice(&[String::from("c")]).unwrap();
+        assert_eq!(buf.init_buf(), ["a", "b", "c"]);
+        // growth doubles, so the next extend fits without reallocating
+        assert_eq!(buf.size(), 4);
+        let ptr = buf.as_ptr();
+        buf.extend_from_slice(&[String::from("d")]).unwrap();
+        assert_eq!(buf.as_ptr(), ptr);
+        buf.drop_and_dealloc();
+    }
+
+    #[test]
+    fn test_extend_from_slice_overflow() {
+        use memapi::AllocError;
+
+        // zero-sized elements need no memory, so their counts can reach `usize::MAX`
+        let mut buf = unsafe {
+            OwnedBuf::from_raw_parts(
+                core::ptr::NonNull::<()>::dangling(),
+                usize::MAX - 1,
+                usize::MAX,
+                DefaultAlloc,
+            )
+        };
+        assert!(matches!(
+            buf.extend_from_slice(&[(), ()]),
+            Err(AllocError::ArithmeticOverflow)
+        ));
+        assert_eq!(buf.initialized(), usize::MAX - 1);
+        buf.extend_from_slice(&[()]).unwrap();
+        assert_eq!(buf.initialized(), usize::MAX);
+    }
+
+    #[test]
+    fn test_extend_from_slice_panicking_clone() {
+        use std::panic::{AssertUnwindSafe, catch_unwind};
+
+        struct PanicOnThird(u8);
+        impl Clone for PanicOnThird {
+            fn clone(&self) -> PanicOnThird {
+                assert!(self.0 != 3, "clone failed");
+                PanicOnThird(self.0)
+            }
+        }
+
+        let mut buf = OwnedBuf::<PanicOnThird, DefaultAlloc>::new_in(1, DefaultAlloc).unwrap();
+        let src = [
+            PanicOnThird(1),
+            PanicOnThird(2),
+            PanicOnThird(3),
+            PanicOnThird(4),
+        ];
+        assert!(catch_unwind(AssertUnwindSafe(|| buf.extend_from_slice(&src))).is_err());
+        // the successful clones are kept and space was reserved once for all of them
+        assert_eq!(buf.initialized(), 2);
+        assert_eq!(buf.size(), 4);
+        buf.drop_and_dealloc();
+    }
+
+    #[test]
+    fn test_realloc_in() {
+        use core::{alloc::Layout, ptr::NonNull};
+        use memapi::AllocError;
+        use std::rc::Rc;
+
+        /// Refuses every allocation.
+        struct Refuse;
+        impl Alloc for Refuse {
+            fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+                Err(AllocError::AllocFailed(layout))
+            }
+            fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+                Err(AllocError::AllocFailed(layout))
+            }
+            fn alloc_filled(&self, layout: Layout, _: u8) -> Result<NonNull<u8>, AllocError> {
+                Err(AllocError::AllocFailed(layout))
+            }
+            fn alloc_patterned<F: Fn(usize) -> u8 + Clone>(
+                &self,
+                layout: Layout,
+                _: F,
+            ) -> Result<NonNull<u8>, AllocError> {
+                Err(AllocError::AllocFailed(layout))
+            }
+            unsafe fn dealloc(&self, _: NonNull<u8>, _: Layout) {
+                unreachable!("nothing was allocated");
+            }
+        }
+
+        let counter = Rc::new(());
+        let mut buf = OwnedBuf::new_in(4, DefaultAlloc).unwrap();
+        for _ in 0..3 {
+            buf.try_init_next(Rc::clone(&counter)).unwrap();
+        }
+
+        let (err, buf) = buf.realloc_in(Refuse).unwrap_err();
+        assert!(matches!(err, AllocError::AllocFailed(_)));
+        assert_eq!(buf.initialized(), 3);
+
+        let moved = buf.realloc_in(DefaultAlloc).unwrap();
+        assert_eq!((moved.initialized(), moved.size()), (3, 4));
+        assert_eq!(Rc::strong_count(&counter), 4);
+        assert!(moved.init_buf().iter().all(|rc| Rc::ptr_eq(rc, &counter)));
+        moved.drop_and_dealloc();
+        assert_eq!(Rc::strong_count(&counter), 1);
+
+        let empty = OwnedBuf::<u8>::new_unallocated()
+            .realloc_in(Refuse)
+            .unwrap();
+        assert_eq!(empty.size(), 0);
+    }
+
+    #[test]
+    fn test_from_iter() {
+        let buf = OwnedBuf::from_iter_in(0..10u32, DefaultAlloc).unwrap();
+        assert_eq!(buf.init_buf(), (0..10).collect::<Vec<_>>());
+        buf.drop_and_dealloc();
+
+        // no size hint
+        let buf: OwnedBuf<u32, DefaultAlloc> = (0..100u32).filter(|n| n % 3 == 0).collect();
+        assert_eq!(buf.initialized(), 34);
+        assert_eq!(buf.get(33), Some(&99));
+        buf.drop_and_dealloc();
+
+        let empty = OwnedBuf::from_iter_in(core::iter::empty::<u8>(), DefaultAlloc).unwrap();
+        assert_eq!(empty.initialized(), 0);
+        empty.drop_and_dealloc();
+    }
+
+    #[test]
+    fn test_clear_retain_dedup() {
+        use std::rc::Rc;
+
+        let counter = Rc::new(());
+        let mut buf = OwnedBuf::from_iter_in(0..8u32, DefaultAlloc).unwrap();
+        buf.retain(|n| n % 3 != 0);
+        assert_eq!(buf.init_buf(), [1, 2, 4, 5, 7]);
+
+        let mut dups = OwnedBuf::from_iter_in([1, 1, 2, 3, 3, 3, 1], DefaultAlloc).unwrap();
+        dups.dedup();
+        assert_eq!(dups.init_buf(), [1, 2, 3, 1]);
+
+        let mut rcs =
+            OwnedBuf::from_iter_in((0..6).map(|_| Rc::clone(&counter)), DefaultAlloc).unwrap();
+        let mut i = 0;
+        rcs.retain(|_| {
+            i += 1;
+            i % 2 == 0
+        });
+        assert_eq!(rcs.initialized(), 3);
+        assert_eq!(Rc::strong_count(&counter), 4);
+        let size = rcs.size();
+        rcs.clear();
+        assert_eq!(rcs.initialized(), 0);
+        assert_eq!(rcs.size(), size);
+        assert_eq!(Rc::strong_count(&counter), 1);
+
+        buf.drop_and_dealloc();
+        dups.drop_and_dealloc();
+        rcs.drop_and_dealloc();
+    }
+
+    #[test]
+    fn test_retain_and_dedup_panic_safety() {
+        use std::{
+            panic::{AssertUnwindSafe, catch_unwind},
+            rc::Rc,
+        };
+
+        let counter = Rc::new(());
+        let mut buf =
+            OwnedBuf::from_iter_in((0..6).map(|i| (i, Rc::clone(&counter))), DefaultAlloc).unwrap();
+        let res = catch_unwind(AssertUnwindSafe(|| {
+            buf.retain(|&(i, _)| {
+                assert!(i != 3, "predicate failed");
+                i % 2 == 0
+            });
+        }));
+        assert!(res.is_err());
+        // 0 and 2 were kept, 1 was dropped, and 3.. are left untouched after them
+        let kept: Vec<i32> = buf.init_buf().iter().map(|&(i, _)| i).collect();
+        assert_eq!(kept, [0, 2, 3, 4, 5]);
+        assert_eq!(Rc::strong_count(&counter), 6);
+
+        let res = catch_unwind(AssertUnwindSafe(|| {
+            buf.dedup_by(|a, _| {
+                assert!(a.0 != 4, "comparison failed");
+                false
+            });
+        }));
+        assert!(res.is_err());
+        assert_eq!(buf.initialized(), 5);
+        assert_eq!(Rc::strong_count(&counter), 6);
+
+        buf.drop_and_dealloc();
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+    #[test]
+    fn test_splice() {
+        fn contents(buf: &OwnedBuf<String>) -> Vec<&str> {
+            buf.init_buf().iter().map(String::as_str).collect()
+        }
+        let strings = |s: &'static [&str]| s.iter().map(|&s| String::from(s));
+
+        let mut buf = OwnedBuf::from_iter_in(strings(&["a", "b", "c", "d"]), DefaultAlloc).unwrap();
+        // equal size
+        buf.splice(1..3, strings(&["x", "y"])).unwrap();
+        assert_eq!(contents(&buf), ["a", "x", "y", "d"]);
+        assert_eq!(buf.size(), 4);
+
+        // shrink
+        buf.splice(..3, strings(&["z"])).unwrap();
+        assert_eq!(contents(&buf), ["z", "d"]);
+
+        // grow, with an exact size hint
+        buf.splice(1..1, strings(&["1", "2", "3"])).unwrap();
+        assert_eq!(contents(&buf), ["z", "1", "2", "3", "d"]);
+        assert_eq!(buf.size(), 5);
+
+        // grow, with no useful size hint
+        let mut n = 0;
+        let unhinted = core::iter::from_fn(|| {
+            n += 1;
+            (n <= 4).then(|| n.to_string())
+        });
+        buf.splice(4.., unhinted).unwrap();
+        assert_eq!(contents(&buf), ["z", "1", "2", "3", "1", "2", "3", "4"]);
+
+        buf.splice(.., core::iter::empty()).unwrap();
+        assert_eq!(buf.initialized(), 0);
+        buf.drop_and_dealloc();
+
+        let mut empty = OwnedBuf::<String>::new_unallocated();
+        empty.splice(.., strings(&["only"])).unwrap();
+        assert_eq!(contents(&empty), ["only"]);
+        empty.drop_and_dealloc();
+    }
+
+    #[test]
+    fn test_splice_panic_safety() {
+        use std::{
+            panic::{AssertUnwindSafe, catch_unwind},
+            rc::Rc,
+        };
+
+        let counter = Rc::new(());
+        let mut buf =
+            OwnedBuf::from_iter_in((0..6).map(|i| (i, Rc::clone(&counter))), DefaultAlloc).unwrap();
+        let res = catch_unwind(AssertUnwindSafe(|| {
+            let replacements = (10..20).map(|i| {
+                assert!(i != 13, "iterator failed");
+                (i, Rc::clone(&counter))
+            });
+            buf.splice(1..3, replacements).unwrap();
+        }));
+        assert!(res.is_err());
+        // the removed elements were dropped once, and the written replacements kept
+        let left: Vec<i32> = buf.init_buf().iter().map(|&(i, _)| i).collect();
+        assert_eq!(left, [0, 10, 11, 12, 3, 4, 5]);
+        assert_eq!(Rc::strong_count(&counter), 8);
+
+        buf.drop_and_dealloc();
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn test_splice_out_of_bounds() {
+        let mut buf = OwnedBuf::from_iter_in(0..3u8, DefaultAlloc).unwrap();
+        let _ = buf.splice(2..4, []);
+    }
+
+    #[test]
+    fn test_debug_and_display_errors() {
+        // Debug impl for OwnedBuf
+        let buf = OwnedBuf::<u8, DefaultAlloc>::new_unallocated_in(DefaultAlloc);
+        let s = format!("{buf:?}");
+        assert!(s.contains("OwnedBuf"));
+
+        // VariableError debug and display
+        let soft: VariableError<&str, &str> = VariableError::Soft("oops");
+        let hard: VariableError<&str, &str> = VariableError::Hard("boom");
+        let ds = format!("{soft:?}");
+        let dh = format!("{hard:?}");
+        assert!(ds.contains("Soft"));
+        assert!(dh.contains("Hard"));
+
+        let ls = format!("{soft}");
+        let lh = format!("{hard}");
+        assert!(ls.contains("oops"));
+        assert!(lh.contains("boom"));
+
+        buf.drop_and_dealloc();
+    }
+}
+
+#[cfg(feature = "arena")]
+mod arena_tests {
+    use memapi::{DefaultAlloc, arena::GenArena};
+    use std::rc::Rc;
+
+    #[test]
+    fn test_partition_slices_one_block() {
+        use memapi::{AllocError, arena::PartitionBuilder};
+
+        let mut b = PartitionBuilder::new();
+        let flags = b.add::<u8>(5);
+        let pos = b.add::<[f32; 3]>(4);
+        let ids = b.add::<u64>(3);
+        let part = b.build().unwrap();
+        assert_eq!((flags.offset(), pos.offset(), ids.offset()), (0, 8, 56));
+        assert_eq!(part.layout().size(), 80);
+        assert_eq!(part.layout().align(), 8);
+
+        let ids_ptr = part.slice(ids);
+        assert_eq!(ids_ptr.len(), 3);
+        assert_eq!(ids_ptr.cast::<u8>().addr().get() % 8, 0);
+        unsafe {
+            let pos_ptr = part.slice(pos).cast::<[f32; 3]>();
+            for i in 0..3 {
+                ids_ptr.cast::<u64>().add(i).write(i as u64 * 10);
+                pos_ptr.add(i).write([1.0; 3]);
+            }
+            part.slice(flags).cast::<u8>().write_bytes(0xFF, 5);
+            assert_eq!(ids_ptr.as_ref(), [0, 10, 20]);
+        }
+        part.free();
+
+        let mut b = PartitionBuilder::new();
+        let _ = b.add::<u64>(usize::MAX);
+        assert!(matches!(b.build(), Err(AllocError::LayoutError(8, 8))));
+        assert!(matches!(
+            PartitionBuilder::new().build(),
+            Err(AllocError::ZeroSizedLayout(_))
+        ));
+
+        let other = {
+            let mut b = PartitionBuilder::new();
+            b.add::<u64>(100)
+        };
+        let small = {
+            let mut b = PartitionBuilder::new();
+            let _ = b.add::<u64>(1);
+            b.build().unwrap()
+        };
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| small.slice(other)));
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_replay_detects_divergence() {
+        use core::alloc::Layout;
+        use memapi::{
+            Alloc,
+            arena::{ReplayAlloc, ReplayEvent},
+        };
+
+        fn workload(alloc: &ReplayAlloc, extra: bool) {
+            for size in [8, 24, 3] {
+                alloc
+                    .alloc(Layout::from_size_align(size, 8).unwrap())
+                    .unwrap();
+            }
+            if extra {
+                alloc.alloc_zeroed(Layout::new::<u64>()).unwrap();
+            }
+        }
+
+        let region = Layout::from_size_align(4096, 64).unwrap();
+        let rec = ReplayAlloc::record(region).unwrap();
+        workload(&rec, false);
+        let log = rec.log();
+        assert_eq!(log.iter().map(|e| e.offset).collect::<Vec<_>>(), [0, 8, 32]);
+
+        // the log survives a round trip through text
+        let text = log.iter().map(ToString::to_string).collect::<Vec<_>>();
+        let parsed = text
+            .iter()
+            .map(|l| ReplayEvent::parse(l).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(parsed, log);
+
+        let same = ReplayAlloc::replay(region, parsed.clone()).unwrap();
+        workload(&same, false);
+        assert_eq!(same.finish(), Ok(()));
+
+        let longer = ReplayAlloc::replay(region, parsed.clone()).unwrap();
+        workload(&longer, true);
+        let d = longer.finish().unwrap_err();
+        assert_eq!((d.index, d.expected), (3, None));
+        assert_eq!(d.actual.map(|e| e.offset), Some(40));
+
+        let shorter = ReplayAlloc::replay(region, parsed).unwrap();
+        shorter.alloc(Layout::new::<u64>()).unwrap();
+        shorter.alloc(Layout::new::<u32>()).unwrap();
+        let d = shorter.divergence().unwrap();
+        assert_eq!(d.index, 1);
+        assert_eq!(
+            d.to_string(),
+            "allocation #1 diverged: expected 24 bytes (align 8) at +0x8, got 4 bytes (align 4) at +0x8"
+        );
+
+        // placement of over-aligned requests would depend on the region's address
+        assert!(rec.alloc(Layout::from_size_align(8, 128).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_insert_and_get() {
+        let mut arena = GenArena::new();
+        let a = arena.insert(1u32).unwrap();
+        let b = arena.insert(2u32).unwrap();
+        assert_eq!(arena.len(), 2);
+        assert_eq!(arena.get(a), Some(&1));
+        assert_eq!(arena.get(b), Some(&2));
+
+        *arena.get_mut(a).unwrap() = 10;
+        assert_eq!(arena.get(a), Some(&10));
+    }
+
+    #[test]
+    fn test_grows_past_initial_capacity() {
+        let mut arena = GenArena::with_capacity_in(1, DefaultAlloc).unwrap();
+        let handles: Vec<_> = (0..100usize).map(|i| arena.insert(i).unwrap()).collect();
+        assert!(arena.capacity() >= 100);
+        for (i, h) in handles.into_iter().enumerate() {
+            assert_eq!(arena.get(h), Some(&i));
+        }
+    }
+
+    #[test]
+    fn test_reset_invalidates_handles() {
+        let mut arena = GenArena::new();
+        let old = arena.insert(String::from("old")).unwrap();
+        let cap = arena.capacity();
+
+        arena.reset();
+        assert!(arena.is_empty());
+        assert_eq!(arena.capacity(), cap);
+        assert_eq!(arena.generation(), 1);
+        assert!(!arena.contains(old));
+        assert!(arena.get(old).is_none());
+
+        // the new value reuses the old slot, but the stale handle still doesn't resolve
+        let new = arena.insert(String::from("new")).unwrap();
+        assert_eq!(new.index(), old.index());
+        assert_ne!(new, old);
+        assert!(arena.get(old).is_none());
+        assert_eq!(arena.get(new).map(String::as_str), Some("new"));
+    }
+
+    #[test]
+    fn test_reset_and_drop_drop_values() {
+        let counter = Rc::new(());
+        let mut arena = GenArena::new();
+        for _ in 0..8 {
+            arena.insert(Rc::clone(&counter)).unwrap();
+        }
+        assert_eq!(Rc::strong_count(&counter), 9);
+
+        arena.reset();
+        assert_eq!(Rc::strong_count(&counter), 1);
+
+        arena.insert(Rc::clone(&counter)).unwrap();
+        drop(arena);
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn test_atomic_bump_aligns_and_exhausts() {
+        use core::alloc::Layout;
+        use memapi::{Alloc, AllocError, arena::AtomicBump};
+
+        let mut bump = AtomicBump::new(Layout::from_size_align(64, 8).unwrap()).unwrap();
+        let a = bump.alloc(Layout::new::<u8>()).unwrap();
+        let b = bump
+            .alloc(Layout::from_size_align(16, 16).unwrap())
+            .unwrap();
+        assert_eq!(b.addr().get() % 16, 0);
+        assert!(b.addr().get() > a.addr().get());
+        assert!(bump.used() <= 32);
+
+        let big = Layout::from_size_align(64, 1).unwrap();
+        assert_eq!(bump.alloc(big), Err(AllocError::AllocFailed(big)));
+
+        bump.reset();
+        assert_eq!(bump.used(), 0);
+        let z = bump.alloc_zeroed(big).unwrap();
+        assert!(
+            unsafe { core::slice::from_raw_parts(z.as_ptr(), 64) }
+                .iter()
+                .all(|&b| b == 0)
+        );
+    }
+
+    #[test]
+    fn test_atomic_bump_reserve() {
+        use core::alloc::Layout;
+        use memapi::{Alloc, arena::AtomicBump};
+
+        let bump = AtomicBump::new(Layout::from_size_align(256, 8).unwrap()).unwrap();
+        // through a reference too, so wrappers forward it
+        assert!(Alloc::reserve(&&bump, 256).is_ok());
+        assert!(bump.reserve(257).is_err());
+
+        bump.reserve(128).unwrap();
+        for _ in 0..16 {
+            bump.alloc(Layout::new::<u64>()).unwrap();
+        }
+        assert!(bump.reserve(129).is_err());
+        assert!(bump.reserve(0).is_ok());
+    }
+
+    #[test]
+    fn test_atomic_bump_concurrent_allocations_are_disjoint() {
+        use core::alloc::Layout;
+        use memapi::{Alloc, arena::AtomicBump};
+        use std::thread;
+
+        const THREADS: usize = 8;
+        const PER_THREAD: usize = 2000;
+
+        let bump = AtomicBump::new(Layout::from_size_align(1 << 20, 64).unwrap()).unwrap();
+        let mut blocks: Vec<(usize, usize)> = thread::scope(|s| {
+            let handles: Vec<_> = (0..THREADS)
+                .map(|t| {
+                    let bump = &bump;
+                    s.spawn(move || {
+                        let mut mine = Vec::with_capacity(PER_THREAD);
+                        for i in 0..PER_THREAD {
+                            let size = 1 + (i * 7 + t) % 48;
+                            let align = 1 << (i % 4);
+                            let layout = Layout::from_size_align(size, align).unwrap();
+                            #[allow(clippy::cast_possible_truncation)]
+                            let p = bump.alloc_filled(layout, t as u8).unwrap();
+                            assert_eq!(p.addr().get() % align, 0);
+                            mine.push((p.addr().get(), size, t));
+                        }
+                        // nobody else may have written into this thread's blocks
+                        for &(addr, size, t) in &mine {
+                            let bytes =
+                                unsafe { core::slice::from_raw_parts(addr as *const u8, size) };
+                            #[allow(clippy::cast_possible_truncation)]
+                            let t = t as u8;
+                            assert!(bytes.iter().all(|&b| b == t));
+                        }
+                        mine.into_iter().map(|(a, s, _)| (a, s)).collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect()
+        });
+
+        blocks.sort_unstable();
+        for pair in blocks.windows(2) {
+            assert!(pair[0].0 + pair[0].1 <= pair[1].0, "overlapping blocks");
+        }
+        assert!(bump.used() <= bump.capacity());
+    }
+}
+
+/// The same round trips through `alloc_clone_to` under every combination of the `metadata` and
+/// `clone_to_uninit` features, so the separate implementations can't drift apart. Types a
+/// configuration can't clone are excluded with `#[cfg]` rather than left to fail to compile.
+#[cfg(feature = "alloc_ext")]
+mod alloc_clone_to_matrix {
+    use super::alloc_clone_to_props::{CheckedAlloc, OverAligned, round_trip};
+    use memapi::{Alloc, AllocExt, DefaultAlloc};
+    use std::rc::Rc;
+
+    /// The active configuration, for assertion messages.
+    const CONFIG: &str = match (
+        cfg!(feature = "metadata"),
+        cfg!(feature = "clone_to_uninit"),
+    ) {
+        (false, false) => "default",
+        (true, false) => "metadata",
+        (false, true) => "clone_to_uninit",
+        (true, true) => "metadata + clone_to_uninit",
+    };
+
+    #[test]
+    fn test_sized_values() {
+        let alloc = CheckedAlloc::default();
+        round_trip(&alloc, &0xDEAD_BEEF_u64);
+        round_trip(&alloc, &[1u8, 2, 3]);
+        round_trip(&alloc, &OverAligned(9, String::from("over")));
+        round_trip(&alloc, &String::from("matrix"));
+        round_trip(
+            &alloc,
+            &vec![String::from("a"), String::new(), String::from("c")],
+        );
+        round_trip(&alloc, &Some(Box::new(5i32)));
+    }
+
+    #[test]
+    fn test_clones_exactly_once() {
+        let rc = Rc::new(());
+        let ptr = DefaultAlloc.alloc_clone_to(&Rc::clone(&rc)).unwrap();
+        assert_eq!(Rc::strong_count(&rc), 2, "under {CONFIG}");
+        unsafe { DefaultAlloc.drop_and_dealloc(ptr) };
+        assert_eq!(Rc::strong_count(&rc), 1, "under {CONFIG}");
+    }
+
+    #[cfg(all(feature = "metadata", feature = "clone_to_uninit"))]
+    #[test]
+    fn test_unsized_values_match_sized() {
+        let alloc = CheckedAlloc::default();
+        let strings = vec![String::from("x"), String::from("yz")];
+
+        let s = alloc.alloc_clone_to("matrix").unwrap();
+        let slice = alloc.alloc_clone_to(strings.as_slice()).unwrap();
+        let sized = alloc.alloc_clone_to(&strings).unwrap();
+        unsafe {
+            assert_eq!(s.as_ref(), "matrix", "under {CONFIG}");
+            assert_eq!(slice.as_ref(), sized.as_ref().as_slice(), "under {CONFIG}");
+            alloc.drop_and_dealloc(s);
+            alloc.drop_and_dealloc(slice);
+            alloc.drop_and_dealloc(sized);
+        }
+        alloc.assert_clean();
+    }
+}
+
+#[cfg(feature = "alloc_ext")]
+mod alloc_clone_to_props {
+    use core::{alloc::Layout, ptr::NonNull};
+    use memapi::{Alloc, AllocError, AllocExt, DefaultAlloc};
+    use proptest::prelude::*;
+    use std::{
+        collections::HashMap,
+        panic::{AssertUnwindSafe, catch_unwind},
+        sync::Mutex,
+    };
+
+    /// An allocator which records every live allocation's layout and every deallocation made with
+    /// a layout other than the one it was allocated with.
+    #[derive(Default)]
+    pub(super) struct CheckedAlloc {
+        live: Mutex<HashMap<usize, Layout>>,
+        mismatches: Mutex<Vec<(Layout, Layout)>>,
+    }
+
+    impl CheckedAlloc {
+        fn track(
+            &self,
+            res: Result<NonNull<u8>, AllocError>,
+            layout: Layout,
+        ) -> Result<NonNull<u8>, AllocError> {
+            if let Ok(ptr) = res {
+                self.live
+                    .lock()
+                    .unwrap()
+                    .insert(ptr.as_ptr() as usize, layout);
+            }
+            res
+        }
+
+        pub(super) fn assert_clean(&self) {
+            assert!(self.live.lock().unwrap().is_empty(), "leaked allocations");
+            assert!(
+                self.mismatches.lock().unwrap().is_empty(),
+                "deallocated with mismatched layouts (allocated, freed): {:?}",
+                self.mismatches.lock().unwrap()
+            );
+        }
+    }
+
+    impl Alloc for CheckedAlloc {
+        fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+            self.track(DefaultAlloc.alloc(layout), layout)
+        }
+
+        fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+            self.track(DefaultAlloc.alloc_zeroed(layout), layout)
+        }
+
+        fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+            self.track(DefaultAlloc.alloc_filled(layout, n), layout)
+        }
+
+        fn alloc_patterned<F: Fn(usize) -> u8 + Clone>(
+            &self,
+            layout: Layout,
+            pattern: F,
+        ) -> Result<NonNull<u8>, AllocError> {
+            self.track(DefaultAlloc.alloc_patterned(layout, pattern), layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+            let allocated = self
+                .live
+                .lock()
+                .unwrap()
+                .remove(&(ptr.as_ptr() as usize))
+                .expect("deallocated a pointer which was not allocated by this allocator");
+            if allocated != layout {
+                self.mismatches.lock().unwrap().push((allocated, layout));
+            }
+            // always free with the real layout so a mismatch can't corrupt the global heap
+            unsafe { DefaultAlloc.dealloc(ptr, allocated) };
+        }
+    }
+
+    /// Clones `val` into `alloc`, checks the clone is aligned and equal to the original, then
+    /// frees it.
+    pub(super) fn round_trip<T: Clone + PartialEq + core::fmt::Debug>(
+        alloc: &CheckedAlloc,
+        val: &T,
+    ) {
+        match alloc.alloc_clone_to(val) {
+            Ok(ptr) => unsafe {
+                assert_eq!(ptr.as_ptr() as usize % align_of::<T>(), 0);
+                assert_eq!(ptr.as_ref(), val);
+                alloc.drop_and_dealloc(ptr);
+            },
+            Err(AllocError::ZeroSizedLayout(_)) => assert_eq!(size_of::<T>(), 0),
+            Err(e) => panic!("unexpected error: {e}"),
+        }
+        alloc.assert_clean();
+    }
+
+    #[repr(align(64))]
+    #[derive(Clone, Debug, PartialEq)]
+    pub(super) struct OverAligned(pub(super) u64, pub(super) String);
+
+    /// A value whose clone always panics, used to exercise the cleanup path.
+    #[derive(Debug, PartialEq)]
+    struct PanicOnClone([u64; 4]);
+
+    impl Clone for PanicOnClone {
+        fn clone(&self) -> Self {
+            panic!("PanicOnClone::clone")
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_sized_primitives_round_trip(a: u8, b: u64, c: i128, d: char) {
+            let alloc = CheckedAlloc::default();
+            round_trip(&alloc, &a);
+            round_trip(&alloc, &b);
+            round_trip(&alloc, &c);
+            round_trip(&alloc, &d);
+        }
+
+        #[test]
+        fn test_sized_owning_types_round_trip(
+            s in ".*",
+            v in proptest::collection::vec(any::<u32>(), 0..64),
+            n: u64,
+        ) {
+            let alloc = CheckedAlloc::default();
+            round_trip(&alloc, &s);
+            round_trip(&alloc, &v);
+            round_trip(&alloc, &(n, s.clone()));
+            round_trip(&alloc, &OverAligned(n, s));
+        }
+    }
+
+    #[test]
+    fn test_zero_sized_clone_is_rejected() {
+        let alloc = CheckedAlloc::default();
+        assert!(matches!(
+            alloc.alloc_clone_to(&()),
+            Err(AllocError::ZeroSizedLayout(_))
+        ));
+        alloc.assert_clean();
+    }
+
+    #[test]
+    fn test_over_aligned_clone_is_aligned() {
+        let alloc = CheckedAlloc::default();
+        let ptr = alloc
+            .alloc_clone_to(&OverAligned(7, String::from("x")))
+            .unwrap();
+        assert_eq!(ptr.as_ptr() as usize % align_of::<OverAligned>(), 0);
+        unsafe { alloc.drop_and_dealloc(ptr) };
+        alloc.assert_clean();
+    }
+
+    #[test]
+    fn test_panicking_clone_frees_with_correct_layout() {
+        let alloc = CheckedAlloc::default();
+        let res = catch_unwind(AssertUnwindSafe(|| {
+            let _ = alloc.alloc_clone_to(&PanicOnClone([1, 2, 3, 4]));
+        }));
+        assert!(res.is_err());
+        alloc.assert_clean();
+    }
+
+    /// Allocates `len` elements from `f` with `alloc_slice_with`, then frees them through every
+    /// slice deallocation path; each must use the layout the slice was allocated with.
+    fn free_every_way<T, F: Fn(usize) -> T + Copy>(alloc: &CheckedAlloc, len: usize, f: F) {
+        unsafe {
+            let p = alloc.alloc_slice_with(len, f).unwrap();
+            p.drop_in_place();
+            alloc.dealloc_n(p.cast::<T>(), len);
+
+            let p = alloc.alloc_slice_with(len, f).unwrap();
+            p.drop_in_place();
+            alloc.dealloc_typed(p);
+
+            let p = alloc.alloc_slice_with(len, f).unwrap();
+            alloc.drop_and_dealloc(p);
+
+            let p = alloc.alloc_slice_with(len, f).unwrap();
+            alloc.drop_and_dealloc_n(p.cast::<T>(), len);
+
+            let p = alloc.alloc_slice_with(len, f).unwrap();
+            // the prefix helper leaves the rest to the caller
+            NonNull::slice_from_raw_parts(p.cast::<T>().add(len / 2), len - len / 2)
+                .drop_in_place();
+            alloc.drop_prefix_and_dealloc_slice(p, len / 2);
+        }
+    }
+
+    proptest! {
+        #[test]
+        #[allow(clippy::cast_possible_truncation)]
+        fn test_slice_deallocation_layouts_match(len in 1usize..64, seed: u64) {
+            let alloc = CheckedAlloc::default();
+            free_every_way(&alloc, len, |i| seed as u8 ^ i as u8);
+            free_every_way(&alloc, len, |i| [seed, i as u64]);
+            free_every_way(&alloc, len, |i| (i as u16, [0u8; 3]));
+            free_every_way(&alloc, len, |i| i.to_string());
+            free_every_way(&alloc, len, |i| OverAligned(seed, i.to_string()));
+            alloc.assert_clean();
+        }
+    }
+
+    #[cfg(all(feature = "clone_to_uninit", feature = "metadata"))]
+    mod unsized_props {
+        use super::{CheckedAlloc, round_trip};
+        use memapi::{Alloc, AllocError, AllocExt};
+        use proptest::prelude::*;
+
+        /// Clones an unsized `val` into `alloc`, checks the clone is equal to the original, then
+        /// frees it.
+        fn round_trip_unsized<
+            T: core::clone::CloneToUninit + PartialEq + core::fmt::Debug + ?Sized,
+        >(
+            alloc: &CheckedAlloc,
+            val: &T,
+        ) {
+            match alloc.alloc_clone_to(val) {
+                Ok(ptr) => unsafe {
+                    assert_eq!(ptr.as_ref(), val);
+                    alloc.drop_and_dealloc(ptr);
+                },
+                Err(AllocError::ZeroSizedLayout(_)) => assert_eq!(size_of_val(val), 0),
+                Err(e) => panic!("unexpected error: {e}"),
+            }
+            alloc.assert_clean();
+        }
+
+        proptest! {
+            #[test]
+            fn test_unsized_round_trip(
+                s in ".*",
+                v in proptest::collection::vec(any::<u64>(), 0..64),
+                strings in proptest::collection::vec(".*", 0..16),
+            ) {
+                let alloc = CheckedAlloc::default();
+                round_trip_unsized(&alloc, s.as_str());
+                round_trip_unsized(&alloc, v.as_slice());
+                round_trip_unsized(&alloc, strings.as_slice());
+                // the sized path must agree with the unsized one for the owning type
+                round_trip(&alloc, &strings);
+            }
+        }
+    }
+}
+
+#[cfg(all(feature = "adapters", feature = "stats"))]
+mod adapters_tests {
+    use core::{alloc::Layout, sync::atomic::AtomicUsize};
+    use memapi::{
+        Alloc, AllocError, DefaultAlloc,
+        adapters::Transaction,
+        stats::{Stats, StatsLogger},
+    };
+
+    fn build(
+        tx: &Transaction<'_, Stats<DefaultAlloc, AtomicUsize>>,
+        fail: bool,
+    ) -> Result<(), AllocError> {
+        let a = tx.alloc(Layout::new::<u64>())?;
+        let b = tx.alloc_zeroed(Layout::new::<[u32; 4]>())?;
+        unsafe {
+            // resizing through the transaction keeps the record current
+            let _ = tx.grow(b, Layout::new::<[u32; 4]>(), Layout::new::<[u32; 8]>())?;
+            tx.dealloc(a, Layout::new::<u64>());
+        }
+        if fail {
+            return Err(AllocError::ArithmeticOverflow);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_dropped_transaction_frees_everything() {
+        let alloc = Stats(DefaultAlloc, AtomicUsize::new(0));
+        {
+            let tx = Transaction::new(&alloc);
+            assert!(build(&tx, true).is_err());
+            assert_eq!(tx.len(), 1);
+            assert_eq!(alloc.1.total(), 32);
+        }
+        assert_eq!(alloc.1.total(), 0);
+    }
+
+    #[test]
+    fn test_committed_transaction_keeps_allocations() {
+        let alloc = Stats(DefaultAlloc, AtomicUsize::new(0));
+        let tx = Transaction::new(&alloc);
+        let p = tx.alloc(Layout::new::<u64>()).unwrap();
+        tx.commit();
+        assert_eq!(alloc.1.total(), 8);
+        unsafe {
+            alloc.dealloc(p, Layout::new::<u64>());
+        }
+        assert_eq!(alloc.1.total(), 0);
+    }
+
+    #[cfg(feature = "alloc_ext")]
+    #[test]
+    fn test_begin_starts_a_transaction() {
+        use memapi::AllocExt;
+
+        let alloc = Stats(DefaultAlloc, AtomicUsize::new(0));
+        let tx = alloc.begin();
+        tx.alloc_write(5u32).unwrap();
+        assert!(!tx.is_empty());
+        drop(tx);
+        assert_eq!(alloc.1.total(), 0);
+    }
+
+    #[test]
+    fn test_delay_alloc_spins_within_range() {
+        use memapi::adapters::{Delay, DelayAlloc};
+
+        let alloc = DelayAlloc::new(DefaultAlloc, Delay::Spin { min: 10, max: 20 }).with_seed(7);
+        let layout = Layout::new::<u64>();
+        for _ in 0..8 {
+            let p = alloc.alloc(layout).unwrap();
+            unsafe { alloc.dealloc(p, layout) };
+        }
+        // deallocation isn't delayed
+        assert_eq!(alloc.delayed_calls(), 8);
+        assert!((80..=160).contains(&alloc.total_spins()));
+
+        let fixed = DelayAlloc::new(DefaultAlloc, Delay::spin(5));
+        let p = fixed.alloc_zeroed(layout).unwrap();
+        unsafe { fixed.dealloc(p, layout) };
+        assert_eq!(fixed.total_spins(), 5);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_delay_alloc_sleeps() {
+        use memapi::adapters::{Delay, DelayAlloc};
+        use std::time::{Duration, Instant};
+
+        let alloc = DelayAlloc::new(DefaultAlloc, Delay::sleep(Duration::from_millis(2)));
+        let layout = Layout::new::<[u8; 64]>();
+        let start = Instant::now();
+        let p = alloc.alloc(layout).unwrap();
+        // growing allocates once more
+        let p = unsafe { alloc.grow(p, layout, Layout::new::<[u8; 128]>()) }.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(4));
+        assert_eq!(alloc.total_slept(), Duration::from_millis(4));
+        assert_eq!(alloc.total_spins(), 0);
+        unsafe { alloc.dealloc(p, Layout::new::<[u8; 128]>()) };
+    }
+
+    #[test]
+    #[allow(clippy::cast_possible_truncation)]
+    fn test_colored_alloc_cycles_through_sets() {
+        use core::ptr::NonNull;
+        use memapi::adapters::ColoredAlloc;
+
+        // page-aligns every block so the colors are the only thing moving allocations
+        struct PageAligned(Stats<DefaultAlloc, AtomicUsize>);
+
+        impl PageAligned {
+            fn page(layout: Layout) -> Layout {
+                layout.align_to(4096).unwrap()
+            }
+        }
+
+        impl Alloc for PageAligned {
+            fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+                self.0.alloc(Self::page(layout))
+            }
+            fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+                self.0.alloc_zeroed(Self::page(layout))
+            }
+            fn alloc_filled(&self, layout: Layout, n: u8) -> Result<NonNull<u8>, AllocError> {
+                self.0.alloc_filled(Self::page(layout), n)
+            }
+            fn alloc_patterned<F: Fn(usize) -> u8 + Clone>(
+                &self,
+                layout: Layout,
+                pattern: F,
+            ) -> Result<NonNull<u8>, AllocError> {
+                self.0.alloc_patterned(Self::page(layout), pattern)
+            }
+            unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+                unsafe { self.0.dealloc(ptr, Self::page(layout)) }
+            }
+        }
+
+        let alloc = ColoredAlloc::new(PageAligned(Stats(DefaultAlloc, AtomicUsize::new(0))), 4);
+        let layout = Layout::from_size_align(4096, 8).unwrap();
+        let ptrs: Vec<_> = (0..8).map(|_| alloc.alloc(layout).unwrap()).collect();
+        let offsets: Vec<_> = ptrs.iter().map(|p| p.addr().get() % 4096).collect();
+        assert_eq!(offsets, [64, 128, 192, 256, 64, 128, 192, 256]);
+        assert_eq!(alloc.inner().0.1.total(), 8 * (4096 + 4 * 64));
+
+        // zero-sized layouts are refused rather than colored into a block of padding
+        assert!(matches!(
+            alloc.alloc_zeroed(Layout::from_size_align(0, 8).unwrap()),
+            Err(AllocError::ZeroSizedLayout(_))
+        ));
+        assert_eq!(alloc.inner().0.1.total(), 8 * (4096 + 4 * 64));
+
+        // over-aligned layouts step by their alignment instead
+        let wide = Layout::from_size_align(100, 128).unwrap();
+        let p = alloc.alloc_patterned(wide, |i| i as u8).unwrap();
+        assert_eq!(p.addr().get() % 4096, 128);
+        let bytes = unsafe { core::slice::from_raw_parts(p.as_ptr(), 100) };
+        assert!(bytes.iter().enumerate().all(|(i, &b)| b == i as u8));
+
+        unsafe {
+            alloc.dealloc(p, wide);
+            for p in ptrs {
+                alloc.dealloc(p, layout);
+            }
+        }
+        assert_eq!(alloc.inner().0.1.total(), 0);
+    }
+
+    #[test]
+    fn test_tail_padded_rounds_sizes_up() {
+        use memapi::adapters::TailPadded;
+
+        let alloc = TailPadded::<16, _>::new(Stats(DefaultAlloc, AtomicUsize::new(0)));
+        assert_eq!(alloc.granularity(), 16);
+        // sized to suit both the padding and the inner allocator
+        assert_eq!(
+            TailPadded::<16, _>::new(TailPadded::<24, _>::new(DefaultAlloc)).granularity(),
+            48
+        );
+        let small = Layout::from_size_align(10, 4).unwrap();
+        let p = alloc.alloc(small).unwrap();
+        assert_eq!(p.addr().get() % 4, 0);
+        assert_eq!(alloc.inner().1.total(), 16);
+
+        // the padding is filled along with the requested bytes
+        let big = Layout::from_size_align(20, 1).unwrap();
+        let q = alloc.alloc_filled(big, 0x7F).unwrap();
+        let padded = unsafe { core::slice::from_raw_parts(q.as_ptr(), 32) };
+        assert!(padded.iter().all(|&b| b == 0x7F));
+        assert_eq!(alloc.inner().1.total(), 48);
+
+        let p = unsafe { alloc.grow(p, small, big) }.unwrap();
+        assert_eq!(alloc.inner().1.total(), 64);
+        unsafe {
+            alloc.dealloc(p, big);
+            alloc.dealloc(q, big);
+        }
+        assert_eq!(alloc.inner().1.total(), 0);
+        assert_eq!(
+            TailPadded::<16, DefaultAlloc>::padded(
+                Layout::from_size_align(isize::MAX as usize - 3, 1).unwrap()
+            ),
+            Err(AllocError::LayoutError(isize::MAX as usize + 1, 1))
+        );
+    }
+
+    #[test]
+    fn test_max_size_alloc_refuses_oversized_requests() {
+        use memapi::adapters::MaxSizeAlloc;
+
+        let alloc = MaxSizeAlloc::new(Stats(DefaultAlloc, AtomicUsize::new(0)), 64);
+        assert_eq!(alloc.max_alloc_size(), 64);
+        let fits = Layout::from_size_align(64, 8).unwrap();
+        let over = Layout::from_size_align(65, 8).unwrap();
+        let p = alloc.alloc_zeroed(fits).unwrap();
+        assert_eq!(
+            alloc.alloc_filled(over, 1),
+            Err(AllocError::Unsupported("exceeds max allocation size"))
+        );
+        // refused requests never reach the inner allocator
+        assert_eq!(alloc.inner().1.total(), 64);
+
+        // growing past the cap fails too, leaving the old block intact
+        assert!(unsafe { alloc.grow(p, fits, over) }.is_err());
+        unsafe {
+            alloc.dealloc(p, fits);
+        }
+        assert_eq!(alloc.inner().1.total(), 0);
+    }
+
+    #[test]
+    fn test_callback_alloc_dispatches_to_callbacks() {
+        use core::sync::atomic::Ordering::Relaxed;
+        use memapi::adapters::CallbackAlloc;
+
+        fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+        let stats = Stats(DefaultAlloc, AtomicUsize::new(0));
+        let reallocs = AtomicUsize::new(0);
+        let plain = CallbackAlloc::new(
+            |layout| stats.alloc(layout),
+            |ptr, layout| unsafe { stats.dealloc(ptr, layout) },
+        );
+        assert_send_sync(&plain);
+
+        // without a realloc callback, resizing moves the block through the other callbacks
+        let p = plain.alloc_filled(Layout::new::<u32>(), 0xAB).unwrap();
+        let p =
+            unsafe { plain.grow_zeroed(p, Layout::new::<u32>(), Layout::new::<u64>()) }.unwrap();
+        assert_eq!(
+            unsafe { p.cast::<[u8; 8]>().read() },
+            [0xAB, 0xAB, 0xAB, 0xAB, 0, 0, 0, 0]
+        );
+        assert_eq!(stats.1.total(), 8);
+
+        let with_realloc = plain.with_realloc(|ptr, old, new| {
+            reallocs.fetch_add(1, Relaxed);
+            unsafe { stats.realloc(ptr, old, new) }
+        });
+        let p = with_realloc
+            .grow_filled(p, Layout::new::<u64>(), Layout::new::<[u8; 12]>(), 7)
+            .unwrap();
+        assert_eq!(unsafe { p.add(11).read() }, 7);
+        let p = unsafe { with_realloc.shrink(p, Layout::new::<[u8; 12]>(), Layout::new::<u16>()) }
+            .unwrap();
+        assert_eq!(unsafe { p.cast::<[u8; 2]>().read() }, [0xAB, 0xAB]);
+        assert_eq!(reallocs.load(Relaxed), 2);
+        assert_eq!(
+            unsafe { with_realloc.grow(p, Layout::new::<u16>(), Layout::new::<u8>()) },
+            Err(AllocError::GrowSmallerNewLayout(2, 1))
+        );
+
+        unsafe { with_realloc.dealloc(p, Layout::new::<u16>()) };
+        assert_eq!(stats.1.total(), 0);
+
+        // like `malloc(0)`, this callback would hand out a block for a zero-sized layout
+        let calls = AtomicUsize::new(0);
+        let lenient = CallbackAlloc::new(
+            |_| {
+                calls.fetch_add(1, Relaxed);
+                Ok(core::ptr::NonNull::dangling())
+            },
+            |_, _| {},
+        )
+        .with_alloc_zeroed(|_| {
+            calls.fetch_add(1, Relaxed);
+            Ok(core::ptr::NonNull::dangling())
+        });
+        let empty = Layout::from_size_align(0, 8).unwrap();
+        assert!(matches!(
+            lenient.alloc(empty),
+            Err(AllocError::ZeroSizedLayout(_))
+        ));
+        assert!(lenient.alloc_zeroed(empty).is_err());
+        assert!(lenient.alloc_filled(empty, 1).is_err());
+        assert!(lenient.alloc_patterned(empty, |_| 1).is_err());
+        assert_eq!(calls.load(Relaxed), 0);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_depth_limited_alloc_refuses_deep_recursion() {
+        use memapi::adapters::DepthLimitedAlloc;
+
+        // a recursive descent over nested lists, allocating one node per level
+        fn descend(
+            alloc: &DepthLimitedAlloc<DefaultAlloc>,
+            levels: usize,
+        ) -> Result<(), AllocError> {
+            let _level = alloc.enter();
+            let layout = Layout::new::<u64>();
+            let node = alloc.alloc(layout)?;
+            let res = if levels == 0 {
+                Ok(())
+            } else {
+                descend(alloc, levels - 1)
+            };
+            unsafe { alloc.dealloc(node, layout) };
+            res
+        }
+
+        let alloc = DepthLimitedAlloc::new(DefaultAlloc, 8);
+        assert_eq!(alloc.max_depth(), 8);
+        assert_eq!(descend(&alloc, 7), Ok(()));
+        assert_eq!(
+            descend(&alloc, 8),
+            Err(AllocError::Unsupported("recursion depth exceeded"))
+        );
+        // every guard was dropped on the way back out
+        assert_eq!(alloc.depth(), 0);
+
+        // depth is per allocator and per thread
+        let other = DepthLimitedAlloc::new(DefaultAlloc, 0);
+        let _outer = alloc.enter();
+        assert_eq!((alloc.depth(), other.depth()), (1, 0));
+        let on_other_thread = std::thread::scope(|s| s.spawn(|| alloc.depth()).join().unwrap());
+        assert_eq!(on_other_thread, 0);
+
+        // shrinking is allowed past the limit
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let p = other.alloc(layout).unwrap();
+        let deep = other.enter();
+        assert!(other.alloc(layout).is_err());
+        let small = Layout::from_size_align(16, 8).unwrap();
+        let p = unsafe { other.shrink(p, layout, small) }.unwrap();
+        drop(deep);
+        unsafe { other.dealloc(p, small) };
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_epoch_alloc_defers_frees_past_pinned_guards() {
+        use memapi::adapters::EpochAlloc;
+
+        let alloc = EpochAlloc::new(Stats(DefaultAlloc, AtomicUsize::new(0)));
+        let layout = Layout::new::<u64>();
+        let a = alloc.alloc(layout).unwrap();
+        let b = alloc.alloc(layout).unwrap();
+
+        let old = alloc.pin();
+        unsafe { alloc.dealloc(a, layout) };
+        // a guard pinned before the retirement may still reference `a`
+        assert_eq!(alloc.collect(), 0);
+        assert_eq!(alloc.pending(), 1);
+        assert_eq!(alloc.inner().1.total(), 16);
+
+        // a guard pinned afterward doesn't hold it back
+        let new = alloc.pin();
+        assert!(new.epoch() > old.epoch());
+        drop(old);
+        assert_eq!(alloc.collect(), 1);
+        assert_eq!(alloc.inner().1.total(), 8);
+
+        unsafe { alloc.dealloc(b, layout) };
+        drop(new);
+        assert_eq!(alloc.collect(), 1);
+        assert_eq!(alloc.pending(), 0);
+        assert_eq!(alloc.inner().1.total(), 0);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_epoch_alloc_frees_retired_blocks_on_drop() {
+        use memapi::adapters::EpochAlloc;
+
+        let stats = AtomicUsize::new(0);
+        {
+            let alloc = EpochAlloc::new(Stats(DefaultAlloc, &stats));
+            let _guard = alloc.pin();
+            let p = alloc.alloc(Layout::new::<[u8; 64]>()).unwrap();
+            unsafe { alloc.dealloc(p, Layout::new::<[u8; 64]>()) };
+            assert_eq!(alloc.collect(), 0);
+            assert_eq!(stats.total(), 64);
+        }
+        assert_eq!(stats.total(), 0);
+    }
+}
+
+#[cfg(feature = "debug_checks")]
+mod debug_checks_tests {
+    use core::{alloc::Layout, cell::Cell};
+    use memapi::{
+        Alloc, DefaultAlloc,
+        debug_checks::{MirrorOnDealloc, ThreadBound},
+    };
+
+    #[test]
+    fn test_thread_bound_panics_on_use_from_another_thread() {
+        let layout = Layout::new::<u64>();
+        let mut alloc = ThreadBound::new(DefaultAlloc);
+        assert_eq!(alloc.owner(), None);
+        let p = alloc.alloc(layout).unwrap();
+        assert_eq!(alloc.owner(), Some(std::thread::current().id()));
+
+        let shared = &alloc;
+        let res = std::thread::scope(|s| s.spawn(|| shared.alloc(layout).is_ok()).join());
+        assert!(res.is_err());
+        unsafe {
+            alloc.dealloc(p, layout);
+        }
+
+        // once unbound, the next user rebinds it
+        alloc.unbind();
+        let shared = &alloc;
+        let p = std::thread::scope(|s| {
+            s.spawn(|| shared.alloc(layout).unwrap().addr().get())
+                .join()
+                .unwrap()
+        });
+        assert_ne!(alloc.owner(), Some(std::thread::current().id()));
+        alloc.unbind();
+        unsafe {
+            alloc.dealloc(core::ptr::NonNull::new(p as *mut u8).unwrap(), layout);
+        }
+    }
+
+    #[test]
+    fn test_untouched_allocation_is_not_reported() {
+        let hits = Cell::new(0);
+        let alloc = MirrorOnDealloc::new(DefaultAlloc, |_, _| hits.set(hits.get() + 1));
+        let layout = Layout::new::<[u8; 64]>();
+        let p = alloc.alloc_filled(layout, 0xAA).unwrap();
+        assert_eq!(unsafe { alloc.verify(p) }, Some(true));
+        unsafe {
+            alloc.dealloc(p, layout);
+        }
+        assert_eq!(hits.get(), 0);
+    }
+
+    #[test]
+    fn test_corruption_is_reported_on_dealloc() {
+        let hits = Cell::new(0);
+        let alloc = MirrorOnDealloc::new(DefaultAlloc, |_, l: Layout| {
+            assert_eq!(l.size(), 64);
+            hits.set(hits.get() + 1);
+        });
+        let layout = Layout::new::<[u8; 64]>();
+        let p = alloc.alloc_zeroed(layout).unwrap();
+        unsafe {
+            p.add(17).write(1);
+            assert_eq!(alloc.verify(p), Some(false));
+            alloc.dealloc(p, layout);
+        }
+        assert_eq!(hits.get(), 1);
+    }
+
+    #[test]
+    fn test_uninit_allocations_are_checked_once_sealed() {
+        let hits = Cell::new(0);
+        let alloc = MirrorOnDealloc::new(DefaultAlloc, |_, _| hits.set(hits.get() + 1));
+        let layout = Layout::new::<u64>();
+
+        // unsealed allocations aren't checked
+        let p = alloc.alloc(layout).unwrap();
+        assert_eq!(unsafe { alloc.verify(p) }, None);
+        unsafe {
+            p.cast::<u64>().write(1);
+            alloc.dealloc(p, layout);
+        }
+        assert_eq!(hits.get(), 0);
+
+        let p = alloc.alloc(layout).unwrap();
+        unsafe {
+            p.cast::<u64>().write(1);
+            assert!(alloc.seal(p));
+            // resealing after an intended write keeps it clean
+            p.cast::<u64>().write(2);
+            assert!(alloc.seal(p));
+            assert_eq!(alloc.verify(p), Some(true));
+            p.cast::<u64>().write(3);
+            alloc.dealloc(p, layout);
+        }
+        assert_eq!(hits.get(), 1);
+        assert!(!unsafe { alloc.seal(p) });
+    }
+
+    #[test]
+    fn test_resized_allocations_are_not_falsely_reported() {
+        let hits = Cell::new(0);
+        let alloc = MirrorOnDealloc::new(DefaultAlloc, |_, _| hits.set(hits.get() + 1));
+        let small = Layout::new::<[u8; 16]>();
+        let big = Layout::new::<[u8; 64]>();
+        let huge = Layout::new::<[u8; 128]>();
+
+        let p = alloc.alloc_zeroed(small).unwrap();
+        unsafe {
+            let p = alloc.grow_zeroed(p, small, big).unwrap();
+            assert_eq!(alloc.verify(p), None);
+            p.write(1);
+            let p = alloc.grow_filled(p, big, huge, 7).unwrap();
+            let p = alloc.realloc_zeroed(p, huge, big).unwrap();
+            assert_eq!(p.read(), 1);
+            alloc.dealloc(p, big);
+        }
+        assert_eq!(hits.get(), 0);
+
+        // corruption of a sealed block is still caught when it's resized away
+        let p = alloc.alloc_zeroed(small).unwrap();
+        unsafe {
+            p.write(1);
+            let p = alloc.grow(p, small, big).unwrap();
+            alloc.dealloc(p, big);
+        }
+        assert_eq!(hits.get(), 1);
+    }
+}
+
+#[cfg(all(feature = "vmem", unix))]
+mod vmem_tests {
+    use core::alloc::Layout;
+    use memapi::{
+        Alloc, AllocError,
+        vmem::{MmapAlloc, ReserveCommitAlloc},
+    };
+
+    #[test]
+    fn test_freeze_and_thaw_large_allocations() {
+        let mm = MmapAlloc::new();
+        let page = mm.granularity();
+        let big = Layout::from_size_align(page + 1, 8).unwrap();
+        let ptr = mm.alloc_filled(big, 0x3A).unwrap();
+        unsafe {
+            assert!(mm.freeze(ptr, big));
+            // reads still work while frozen
+            assert_eq!(ptr.add(page).read(), 0x3A);
+            assert!(mm.thaw(ptr, big));
+            ptr.add(page).write(0x4B);
+            assert_eq!(ptr.add(page).read(), 0x4B);
+            // frozen memory can be freed without thawing it first
+            assert!(mm.freeze(ptr, big));
+            mm.dealloc(ptr, big);
+        }
+
+        // small allocations share pages, so can't be protected on their own
+        let small = Layout::new::<u64>();
+        let ptr = mm.alloc(small).unwrap();
+        unsafe {
+            assert!(!mm.freeze(ptr, small));
+            ptr.cast::<u64>().write(1);
+            mm.dealloc(ptr, small);
+        }
+    }
+
+    #[cfg(all(feature = "numa", target_os = "linux"))]
+    #[test]
+    fn test_interleaved_allocations_span_whole_pages() {
+        use memapi::vmem::InterleavedAlloc;
+
+        let il = InterleavedAlloc::new();
+        assert!(il.nodes().contains(&0));
+        let page = il.granularity();
+        let layout = Layout::from_size_align(page * 4 + 1, 64).unwrap();
+        let ptr = il.alloc_filled(layout, 0x5C).unwrap();
+        assert_eq!(ptr.addr().get() % page, 0);
+        unsafe {
+            assert_eq!(ptr.add(page * 4).read(), 0x5C);
+            il.dealloc(ptr, layout);
+        }
+
+        // duplicate nodes are merged, and an empty set falls back to node 0
+        assert_eq!(InterleavedAlloc::on_nodes(vec![0, 0]).nodes(), [0]);
+        assert_eq!(InterleavedAlloc::on_nodes(Vec::new()).nodes(), [0]);
+        let one = InterleavedAlloc::on_nodes(vec![0]);
+        let zeroed = one.alloc_slice_zeroed::<u64>(1024).unwrap();
+        unsafe {
+            assert!(zeroed.as_ref().iter().all(|&x| x == 0));
+            one.dealloc_n(zeroed.cast::<u64>(), zeroed.len());
+        }
+    }
+
+    #[test]
+    fn test_granularity_is_the_page_size() {
+        let vm = ReserveCommitAlloc::new(1 << 20).unwrap();
+        let mm = MmapAlloc::new();
+        let page = vm.granularity();
+        assert!(page.is_power_of_two() && page >= 4096);
+        assert_eq!(mm.granularity(), page);
+        // references and wrappers report their inner allocator's value
+        assert_eq!(Alloc::granularity(&&mm), page);
+        #[cfg(feature = "adapters")]
+        assert_eq!(
+            memapi::adapters::TailPadded::<8, _>::new(&vm).granularity(),
+            page
+        );
+        assert_eq!(memapi::DefaultAlloc.granularity(), 1);
+    }
+
+    #[test]
+    fn test_relative_pointers_survive_relocation() {
+        use memapi::vmem::RelativePtr;
+
+        #[derive(Clone, Copy)]
+        struct Node {
+            value: u32,
+            next: Option<RelativePtr<Node>>,
+        }
+
+        let a = ReserveCommitAlloc::new(1 << 20).unwrap();
+        let tail = a
+            .alloc_relative(Node {
+                value: 2,
+                next: None,
+            })
+            .unwrap();
+        let head = a
+            .alloc_relative(Node {
+                value: 1,
+                next: Some(tail),
+            })
+            .unwrap();
+        let saved = head.to_le_bytes();
+
+        // a second range, at another address, with the same blocks copied over
+        let b = ReserveCommitAlloc::new(1 << 20).unwrap();
+        assert_ne!(a.base(), b.base());
+        for rel in [tail, head] {
+            let copy = b.alloc_relative(unsafe { a.resolve(rel).unwrap().read() });
+            assert_eq!(copy.unwrap(), rel);
+        }
+
+        let mut cur = RelativePtr::<Node>::from_le_bytes(saved);
+        let mut values = Vec::new();
+        while let Some(rel) = cur {
+            let node = unsafe { b.resolve(rel).unwrap().read() };
+            values.push(node.value);
+            cur = node.next;
+        }
+        assert_eq!(values, [1, 2]);
+
+        let ptr = a.resolve(head).unwrap();
+        assert_eq!(a.relative(ptr), Some(head));
+        assert_eq!(b.relative(ptr), None);
+        assert!(a.resolve(RelativePtr::<u64>::from_offset(3)).is_none());
+        assert!(
+            a.resolve(RelativePtr::<u64>::from_offset(1 << 20))
+                .is_none()
+        );
+        unsafe {
+            a.dealloc_relative(head);
+            a.dealloc_relative(tail);
+        }
+        assert_eq!(a.committed_bytes(), 0);
+    }
+
+    #[test]
+    fn test_commits_only_live_pages() {
+        let vm = ReserveCommitAlloc::new(64 << 20).unwrap();
+        assert_eq!(vm.reserved_bytes(), 64 << 20);
+        assert_eq!(vm.committed_bytes(), 0);
+
+        let small = Layout::from_size_align(24, 8).unwrap();
+        let a = vm.alloc(small).unwrap();
+        let page = vm.committed_bytes();
+        assert!(page > 0);
+        // a second small block shares the page
+        let b = vm.alloc_zeroed(small).unwrap();
+        assert_eq!(vm.committed_bytes(), page);
+        assert!(
+            unsafe { core::slice::from_raw_parts(b.as_ptr(), 24) }
+                .iter()
+                .all(|&x| x == 0)
+        );
+
+        let big = Layout::from_size_align(4 * page, page).unwrap();
+        let c = vm.alloc_filled(big, 0x5C).unwrap();
+        assert_eq!(c.addr().get() % page, 0);
+        assert_eq!(vm.committed_bytes(), 5 * page);
+        assert_eq!(unsafe { c.add(4 * page - 1).read() }, 0x5C);
+
+        unsafe {
+            vm.dealloc(c, big);
+            assert_eq!(vm.committed_bytes(), page);
+            vm.dealloc(a, small);
+            assert_eq!(vm.committed_bytes(), page);
+            vm.dealloc(b, small);
+        }
+        assert_eq!(vm.committed_bytes(), 0);
+
+        #[cfg(feature = "debug_checks")]
+        assert_eq!(vm.verify_integrity(), Ok(()));
+
+        // freed space is reused from the same stable base
+        let d = vm.alloc(big).unwrap();
+        assert_eq!(d, vm.base());
+        unsafe { vm.dealloc(d, big) };
+    }
+
+    #[test]
+    fn test_exhausts_and_recovers() {
+        let vm = ReserveCommitAlloc::new(1).unwrap();
+        let all = Layout::from_size_align(vm.reserved_bytes(), 1).unwrap();
+        assert!(vm.reserve(vm.reserved_bytes()).is_ok());
+        let p = vm.alloc(all).unwrap();
+        assert!(matches!(
+            vm.alloc(Layout::new::<u8>()),
+            Err(AllocError::AllocFailed(_))
+        ));
+        assert!(vm.reserve(1).is_err());
+        unsafe { vm.dealloc(p, all) };
+        let q = vm.alloc(Layout::new::<u64>()).unwrap();
+        unsafe { vm.dealloc(q, Layout::new::<u64>()) };
+        // the reservation keeps its pages committed until it's released
+        assert_eq!(vm.committed_bytes(), vm.reserved_bytes());
+        vm.reserve(0).unwrap();
+        assert_eq!(vm.committed_bytes(), 0);
+    }
+
+    #[test]
+    fn test_reserve_commits_pages_ahead_of_allocations() {
+        let vm = ReserveCommitAlloc::new(1 << 20).unwrap();
+        let page = vm.granularity();
+        let small = Layout::new::<u64>();
+        let kept = vm.alloc(small).unwrap();
+
+        // the held pages start past the partly used first page
+        vm.reserve(2 * page + 1).unwrap();
+        assert_eq!(vm.committed_bytes(), 4 * page);
+        #[cfg(feature = "debug_checks")]
+        assert_eq!(vm.verify_integrity(), Ok(()));
+
+        // allocating and freeing inside the reservation doesn't decommit it
+        let big = Layout::from_size_align(2 * page, page).unwrap();
+        let p = vm.alloc_zeroed(big).unwrap();
+        assert_eq!(p.addr().get() - vm.base().addr().get(), page);
+        unsafe { vm.dealloc(p, big) };
+        assert_eq!(vm.committed_bytes(), 4 * page);
+
+        // a new reservation replaces the old one
+        vm.reserve(page).unwrap();
+        assert_eq!(vm.committed_bytes(), 2 * page);
+        vm.reserve(0).unwrap();
+        assert_eq!(vm.committed_bytes(), page);
+        unsafe { vm.dealloc(kept, small) };
+        assert_eq!(vm.committed_bytes(), 0);
+    }
+
+    #[test]
+    fn test_mmap_small_frees_unmap_per_page() {
+        let mm = MmapAlloc::new();
+        let small = Layout::new::<[u64; 4]>();
+        let blocks: Vec<_> = (0..4).map(|_| mm.alloc_zeroed(small).unwrap()).collect();
+        assert_eq!(mm.mapped_pages(), 1);
+
+        unsafe {
+            mm.dealloc(blocks[0], small);
+            mm.dealloc(blocks[1], small);
+        }
+        assert_eq!(mm.pending_bytes(), 64);
+        assert_eq!(mm.mapped_pages(), 1);
+        unsafe {
+            mm.dealloc(blocks[2], small);
+            mm.dealloc(blocks[3], small);
+        }
+        // the emptied current page is kept for reuse
+        assert_eq!(mm.pending_bytes(), 0);
+        assert_eq!(mm.alloc(small).unwrap(), blocks[0]);
+
+        let big = Layout::from_size_align(1 << 20, 16).unwrap();
+        let p = mm.alloc_filled(big, 3).unwrap();
+        assert_eq!(unsafe { p.add((1 << 20) - 1).read() }, 3);
+        unsafe {
+            mm.dealloc(p, big);
+            mm.dealloc(blocks[0], small);
+        }
+    }
+
+    #[test]
+    fn test_mmap_reserve_maps_spare_pages() {
+        let mm = MmapAlloc::new();
+        let page = mm.granularity();
+        mm.reserve(2 * page + 1).unwrap();
+        assert_eq!(mm.spare_pages(), 3);
+        // already covered
+        mm.reserve(page).unwrap();
+        assert_eq!(mm.spare_pages(), 3);
+
+        // small allocations take their shared pages from the spares
+        let half = Layout::from_size_align(page / 2, 8).unwrap();
+        let blocks: Vec<_> = (0..4).map(|_| mm.alloc_zeroed(half).unwrap()).collect();
+        assert_eq!(mm.mapped_pages(), 2);
+        assert_eq!(mm.spare_pages(), 1);
+        for b in blocks {
+            unsafe { mm.dealloc(b, half) };
+        }
+    }
+
+    #[test]
+    fn test_mmap_full_pages_are_unmapped() {
+        let mm = MmapAlloc::new();
+        let half = Layout::from_size_align(2048, 8).unwrap();
+        // fill a few pages
+        let blocks: Vec<_> = (0..128).map(|_| mm.alloc(half).unwrap()).collect();
+        let pages = mm.mapped_pages();
+        assert!(pages > 1);
+        for &b in &blocks[..blocks.len() - 1] {
+            unsafe { mm.dealloc(b, half) };
+        }
+        #[cfg(feature = "debug_checks")]
+        assert_eq!(mm.verify_integrity(), Ok(()));
+        // only the page holding the last block remains
+        assert_eq!(mm.mapped_pages(), 1);
+        unsafe { mm.dealloc(blocks[blocks.len() - 1], half) };
+        assert_eq!(mm.mapped_pages(), 1);
+    }
+
+    #[cfg(feature = "debug_checks")]
+    #[test]
+    fn test_integrity_holds_under_churn() {
+        let vm = ReserveCommitAlloc::new(1 << 20).unwrap();
+        let mut live = Vec::new();
+        for i in 0..200usize {
+            let layout = Layout::from_size_align(1 + (i * 37) % 5000, 1 << (i % 7)).unwrap();
+            live.push((vm.alloc(layout).unwrap(), layout));
+            if i % 3 == 0 {
+                let (p, l) = live.swap_remove((i * 7) % live.len());
+                unsafe { vm.dealloc(p, l) };
+            }
+            vm.verify_integrity().unwrap();
+        }
+        for (p, l) in live {
+            unsafe { vm.dealloc(p, l) };
+        }
+        vm.verify_integrity().unwrap();
+        assert_eq!(vm.committed_bytes(), 0);
+    }
 }